@@ -5,14 +5,111 @@ use clap::{Args, Parser, Subcommand};
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
 pub struct SyncArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
     #[clap(subcommand)]
     pub subcommand: SubcommandType,
 }
 
+/// Values that override the environment and `sync.toml`
+#[derive(Debug, Args)]
+pub struct ConfigArgs {
+    /// The config file to read. Defaults to `SYNC_CONFIG`, then `sync.toml` if it exists
+    #[clap(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// The profile in the config file to use, e.g. `mumbai` or `matic`
+    #[clap(long, global = true)]
+    pub profile: Option<String>,
+    /// The URL of the RPC node
+    #[clap(long, global = true)]
+    pub rpc_url: Option<String>,
+    /// The chain id to sign transactions for
+    #[clap(long, global = true)]
+    pub chain_id: Option<u64>,
+    /// The address of the CrudFs contract. Defaults to the one in the manifest
+    #[clap(long, global = true)]
+    pub contract_address: Option<String>,
+    /// Where to get the signing key from: `private-key`, `keystore` or `remote`
+    #[clap(long, global = true)]
+    pub signer: Option<String>,
+    /// The encrypted keystore to sign with
+    #[clap(long, global = true)]
+    pub keystore: Option<PathBuf>,
+    /// The store to pin content to: `estuary`, `kubo` or `local`
+    #[clap(long, global = true)]
+    pub store: Option<String>,
+    /// The directory holding the content to sync
+    #[clap(long, global = true)]
+    pub content_root: Option<PathBuf>,
+    /// Upload markdown without its front matter. The metadata is still read from it
+    #[clap(long, global = true, overrides_with = "no_strip_front_matter")]
+    pub strip_front_matter: bool,
+    /// Upload markdown with its front matter, even if the config strips it
+    #[clap(long, global = true, overrides_with = "strip_front_matter")]
+    pub no_strip_front_matter: bool,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum SubcommandType {
     /// Create a file in the configured backend
     Create(CreateArgs),
+    /// Update a file in the configured backend
+    Update(UpdateArgs),
+    /// Remove a file from the configured backend
+    Rm(RmArgs),
+    /// Move a tracked file, keeping its history on the configured backend
+    Mv(MvArgs),
+    /// Download a file from the configured backend to a local path
+    Get(GetArgs),
+    /// Print a file from the configured backend to stdout
+    Cat(CatArgs),
+    /// Rebuild the local content and manifest from the configured backend
+    #[clap(alias = "clone")]
+    Pull(PullArgs),
+    /// Show how the local folder, manifest and backend differ
+    Status(StatusArgs),
+    /// Show every revision of a file, from the contract's logs
+    Log(LogArgs),
+    /// Sync every file in a content directory to the configured backend
+    Push(PushArgs),
+    /// Watch a content directory and sync it to the configured backend as it changes
+    Daemon(DaemonArgs),
+    /// Pack the content of every file in the manifest into a CAR file
+    Export(ExportArgs),
+    /// Unpack a CAR file into the files in the manifest
+    Import(ImportArgs),
+    /// Inspect the configuration
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+    /// Manage the key that signs transactions
+    #[clap(subcommand)]
+    Wallet(WalletCommand),
+    /// Maintain the manifest file
+    #[clap(subcommand)]
+    Manifest(ManifestCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the resolved configuration, with secrets redacted
+    Show,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WalletCommand {
+    /// Create an encrypted keystore for a new key at the configured `keystore`
+    New,
+    /// Encrypt an existing private key into a keystore at the configured `keystore`.
+    /// Reads the key from `PRIVATE_KEY`, or prompts for it
+    Import,
+    /// Print the address of the configured signer
+    Address,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ManifestCommand {
+    /// Rewrite the manifest in the current layout. Reading migrates it anyway; this saves it
+    Migrate(MigrateArgs),
 }
 
 /* Subcommands */
@@ -30,3 +127,159 @@ pub struct CreateArgs {
     #[clap(long)]
     pub manifest: Option<PathBuf>,
 }
+
+#[derive(Debug, Args)]
+pub struct UpdateArgs {
+    /// The path to the file to update
+    #[clap(short, long)]
+    pub path: PathBuf,
+    /// New metadata to store with the file. Keeps the current metadata if not set
+    #[clap(short, long)]
+    pub metadata: Option<String>,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct RmArgs {
+    /// The path to the file to remove
+    #[clap(short, long)]
+    pub path: PathBuf,
+    /// Unpin the file's content from the store
+    #[clap(long)]
+    pub unpin: bool,
+    /// Leave the file on disk. This is the default
+    #[clap(long, conflicts_with = "purge_local")]
+    pub keep_local: bool,
+    /// Delete the file from disk as well
+    #[clap(long)]
+    pub purge_local: bool,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct MvArgs {
+    /// The path the file is tracked at
+    pub from: PathBuf,
+    /// The path to move it to
+    pub to: PathBuf,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct GetArgs {
+    /// The path of the file in the backend
+    #[clap(short, long)]
+    pub path: PathBuf,
+    /// Where to write the file. Defaults to the path of the file. An existing file is only replaced with --force
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+    /// Overwrite the output if it already exists
+    #[clap(long)]
+    pub force: bool,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct CatArgs {
+    /// The path of the file in the backend
+    #[clap(short, long)]
+    pub path: PathBuf,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct PullArgs {
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct StatusArgs {
+    /// A content directory to check for untracked files. Defaults to the content root
+    pub dir: Option<PathBuf>,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct LogArgs {
+    /// The path of the file
+    pub path: PathBuf,
+    /// Only show what is already indexed, without reading new logs
+    #[clap(long)]
+    pub offline: bool,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct PushArgs {
+    /// The content directory to push. Defaults to the content root
+    pub dir: Option<PathBuf>,
+    /// Print the plan and estimated gas without sending anything
+    #[clap(long)]
+    pub dry_run: bool,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct DaemonArgs {
+    /// The content directory to watch. Defaults to the content root
+    pub dir: Option<PathBuf>,
+    /// How long to wait for edits to settle before syncing, in milliseconds
+    #[clap(long, default_value_t = 2000)]
+    pub debounce: u64,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Where to write the CAR file
+    #[clap(long)]
+    pub car: PathBuf,
+    /// Write a CARv2 file, rather than CARv1
+    #[clap(long)]
+    pub v2: bool,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateArgs {
+    /// Don't write anything; fail if the manifest needs migrating
+    #[clap(long)]
+    pub check: bool,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// The CAR file to unpack
+    pub car: PathBuf,
+    /// Overwrite files that already exist with other content
+    #[clap(long)]
+    pub force: bool,
+    /// Path to the manifest file
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+}
@@ -0,0 +1,674 @@
+/* Configuration */
+
+use crate::crud_fs::store::gateway::{GatewayStrategy, DEFAULT_COOLDOWN, DEFAULT_TIMEOUT};
+use crate::types::cid::CidMode;
+use crate::error::{Result, SyncError};
+use crate::history::{DEFAULT_HISTORY_PATH, DEFAULT_LOG_PAGE_SIZE};
+use crate::types::metadata::DEFAULT_MAX_METADATA_BYTES;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env, fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Where we look for a config file if none is given
+pub const DEFAULT_CONFIG_PATH: &str = "sync.toml";
+
+/// What we print in place of a secret
+const REDACTED: &str = "<redacted>";
+
+/// One layer of configuration: the CLI flags, the environment, or a profile in `sync.toml`.
+/// Every value is optional, so the layers can be stacked
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLayer {
+    /// The URL of the RPC node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpc_url: Option<String>,
+    /// An API key for the RPC node, appended to the URL of the same layer as its last path segment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpc_api_key: Option<String>,
+    /// The chain id to sign transactions for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<u64>,
+    /// The address of the CrudFs contract
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contract_address: Option<String>,
+    /// Where to get the signing key from: `private-key`, `keystore` or `remote`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+    /// The signing key, for the `private-key` signer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<String>,
+    /// The encrypted keystore, for the `keystore` signer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keystore: Option<PathBuf>,
+    /// The JSON-RPC endpoint of the `remote` signer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signer_url: Option<String>,
+    /// The account the `remote` signer signs with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signer_address: Option<String>,
+    /// The store to pin content to: `estuary`, `kubo` or `local`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    /// The Estuary API to pin to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estuary_api_url: Option<String>,
+    /// The Estuary API key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estuary_api_key: Option<String>,
+    /// The RPC API of the Kubo node to pin to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kubo_api_url: Option<String>,
+    /// Where the local store keeps its blobs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_store_path: Option<PathBuf>,
+    /// IPFS gateways to read through before the store, in the order they are tried
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipfs_gateways: Option<Vec<String>>,
+    /// How to spread reads across the gateways: `order` or `race`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipfs_gateway_strategy: Option<String>,
+    /// How long to wait on each gateway, in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipfs_gateway_timeout: Option<u64>,
+    /// How long to skip a gateway after repeated failures, in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipfs_gateway_cooldown: Option<u64>,
+    /// How CIDs are computed: `unixfs`, as `ipfs add` does, or `raw` for one block over the whole file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cid_mode: Option<String>,
+    /// The directory holding the content to sync
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_root: Option<PathBuf>,
+    /// Upload markdown without its front matter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strip_front_matter: Option<bool>,
+    /// A JSON Schema that every file's metadata has to match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_schema: Option<PathBuf>,
+    /// How large a file's metadata may be on chain, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_max_bytes: Option<usize>,
+    /// The block the contract was deployed in, where the history starts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deploy_block: Option<u64>,
+    /// Where the history index is kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_path: Option<PathBuf>,
+    /// How many blocks to ask the RPC node for logs from at once
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_page_size: Option<u64>,
+}
+
+impl ConfigLayer {
+    /// Read a layer from the environment.
+    /// `API_URL` and `CRUDFS_CONTRACT_ADDRESS` are still read, after `RPC_URL` and `CONTRACT_ADDRESS`
+    /// # Returns
+    /// * `Result<ConfigLayer, SyncError>` - Errors if a value can't be parsed
+    pub fn from_env() -> Result<Self, SyncError> {
+        let chain_id = match var("CHAIN_ID") {
+            Some(chain_id) => Some(
+                chain_id
+                    .parse()
+                    .map_err(|_| SyncError::Config("CHAIN_ID must be a number".to_string()))?,
+            ),
+            None => None,
+        };
+        let strip_front_matter = match var("STRIP_FRONT_MATTER") {
+            Some(strip) => Some(strip.parse().map_err(|_| {
+                SyncError::Config("STRIP_FRONT_MATTER must be true or false".to_string())
+            })?),
+            None => None,
+        };
+        let metadata_max_bytes = match var("METADATA_MAX_BYTES") {
+            Some(max_bytes) => Some(
+                max_bytes
+                    .parse()
+                    .map_err(|_| SyncError::Config("METADATA_MAX_BYTES must be a number".to_string()))?,
+            ),
+            None => None,
+        };
+        let deploy_block = match var("DEPLOY_BLOCK") {
+            Some(block) => Some(
+                block
+                    .parse()
+                    .map_err(|_| SyncError::Config("DEPLOY_BLOCK must be a number".to_string()))?,
+            ),
+            None => None,
+        };
+        let ipfs_gateways = var("IPFS_GATEWAYS").map(|urls| {
+            urls.split(',')
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty())
+                .collect()
+        });
+        let ipfs_gateway_timeout = match var("IPFS_GATEWAY_TIMEOUT") {
+            Some(timeout) => Some(timeout.parse().map_err(|_| {
+                SyncError::Config("IPFS_GATEWAY_TIMEOUT must be a number of seconds".to_string())
+            })?),
+            None => None,
+        };
+        let ipfs_gateway_cooldown = match var("IPFS_GATEWAY_COOLDOWN") {
+            Some(cooldown) => Some(cooldown.parse().map_err(|_| {
+                SyncError::Config("IPFS_GATEWAY_COOLDOWN must be a number of seconds".to_string())
+            })?),
+            None => None,
+        };
+        let log_page_size = match var("LOG_PAGE_SIZE") {
+            Some(size) => Some(
+                size.parse()
+                    .ok()
+                    .filter(|size| *size > 0)
+                    .ok_or_else(|| SyncError::Config("LOG_PAGE_SIZE must be a positive number".to_string()))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            rpc_url: var("RPC_URL").or_else(|| var("API_URL")),
+            rpc_api_key: var("API_KEY"),
+            chain_id,
+            contract_address: var("CONTRACT_ADDRESS").or_else(|| var("CRUDFS_CONTRACT_ADDRESS")),
+            signer: var("SIGNER"),
+            private_key: var("PRIVATE_KEY"),
+            keystore: var("KEYSTORE").map(PathBuf::from),
+            signer_url: var("SIGNER_URL"),
+            signer_address: var("SIGNER_ADDRESS"),
+            store: var("STORE_BACKEND"),
+            estuary_api_url: var("ESTUARY_API_URL"),
+            estuary_api_key: var("ESTUARY_API_KEY"),
+            kubo_api_url: var("KUBO_API_URL"),
+            local_store_path: var("LOCAL_STORE_PATH").map(PathBuf::from),
+            ipfs_gateways,
+            ipfs_gateway_strategy: var("IPFS_GATEWAY_STRATEGY"),
+            ipfs_gateway_timeout,
+            ipfs_gateway_cooldown,
+            cid_mode: var("CID_MODE"),
+            content_root: var("CONTENT_ROOT").map(PathBuf::from),
+            strip_front_matter,
+            metadata_schema: var("METADATA_SCHEMA").map(PathBuf::from),
+            metadata_max_bytes,
+            deploy_block,
+            history_path: var("HISTORY_PATH").map(PathBuf::from),
+            log_page_size,
+        })
+    }
+
+    /// Fill in the values this layer doesn't set from a lower layer.
+    /// An RPC API key only goes with the URL of its own layer, so a key in the environment is
+    /// never appended to a `--rpc-url` given on the command line
+    /// # Arguments
+    /// * `lower` - The layer to fall back on
+    pub fn or(self, lower: ConfigLayer) -> Self {
+        let (rpc_url, rpc_api_key) = match self.rpc_url {
+            Some(rpc_url) => (Some(rpc_url), self.rpc_api_key),
+            None => (lower.rpc_url, lower.rpc_api_key),
+        };
+        Self {
+            rpc_url,
+            rpc_api_key,
+            chain_id: self.chain_id.or(lower.chain_id),
+            contract_address: self.contract_address.or(lower.contract_address),
+            signer: self.signer.or(lower.signer),
+            private_key: self.private_key.or(lower.private_key),
+            keystore: self.keystore.or(lower.keystore),
+            signer_url: self.signer_url.or(lower.signer_url),
+            signer_address: self.signer_address.or(lower.signer_address),
+            store: self.store.or(lower.store),
+            estuary_api_url: self.estuary_api_url.or(lower.estuary_api_url),
+            estuary_api_key: self.estuary_api_key.or(lower.estuary_api_key),
+            kubo_api_url: self.kubo_api_url.or(lower.kubo_api_url),
+            local_store_path: self.local_store_path.or(lower.local_store_path),
+            ipfs_gateways: self.ipfs_gateways.or(lower.ipfs_gateways),
+            ipfs_gateway_strategy: self.ipfs_gateway_strategy.or(lower.ipfs_gateway_strategy),
+            ipfs_gateway_timeout: self.ipfs_gateway_timeout.or(lower.ipfs_gateway_timeout),
+            ipfs_gateway_cooldown: self.ipfs_gateway_cooldown.or(lower.ipfs_gateway_cooldown),
+            cid_mode: self.cid_mode.or(lower.cid_mode),
+            content_root: self.content_root.or(lower.content_root),
+            strip_front_matter: self.strip_front_matter.or(lower.strip_front_matter),
+            metadata_schema: self.metadata_schema.or(lower.metadata_schema),
+            metadata_max_bytes: self.metadata_max_bytes.or(lower.metadata_max_bytes),
+            deploy_block: self.deploy_block.or(lower.deploy_block),
+            history_path: self.history_path.or(lower.history_path),
+            log_page_size: self.log_page_size.or(lower.log_page_size),
+        }
+    }
+}
+
+/// Read an environment variable, treating an empty one as unset
+pub(crate) fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// The contents of `sync.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    /// The profile to use if none is given
+    profile: Option<String>,
+    /// Values shared by every profile
+    #[serde(default)]
+    defaults: ConfigLayer,
+    /// Named profiles, e.g. `mumbai` and `matic`
+    #[serde(default)]
+    profiles: HashMap<String, ConfigLayer>,
+}
+
+/// Where to get the key that signs transactions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignerSource {
+    /// A raw private key, from `private_key`
+    PrivateKey,
+    /// An encrypted keystore, from `keystore`
+    Keystore,
+    /// A JSON-RPC signer at `signer_url`, signing as `signer_address`
+    Remote,
+}
+
+impl FromStr for SignerSource {
+    type Err = SyncError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "private-key" => Ok(SignerSource::PrivateKey),
+            "keystore" => Ok(SignerSource::Keystore),
+            "remote" => Ok(SignerSource::Remote),
+            other => Err(SyncError::Config(format!("Unknown signer: {}", other))),
+        }
+    }
+}
+
+impl fmt::Display for SignerSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignerSource::PrivateKey => write!(f, "private-key"),
+            SignerSource::Keystore => write!(f, "keystore"),
+            SignerSource::Remote => write!(f, "remote"),
+        }
+    }
+}
+
+/// Which store to pin content to, and how to reach it
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreConfig {
+    /// `estuary`, `kubo` or `local`
+    pub backend: String,
+    /// Required for the `estuary` backend; there is no public Estuary to default to
+    pub estuary_api_url: Option<String>,
+    pub estuary_api_key: Option<String>,
+    pub kubo_api_url: String,
+    pub local_store_path: PathBuf,
+    /// IPFS gateways to read through before the store, if any
+    pub gateways: Vec<String>,
+    pub gateway_strategy: GatewayStrategy,
+    /// How long to wait on each gateway, in seconds
+    pub gateway_timeout: u64,
+    /// How long to skip a gateway after repeated failures, in seconds
+    pub gateway_cooldown: u64,
+    /// How content is hashed into the CIDs recorded on chain
+    pub cid_mode: CidMode,
+}
+
+/// Configuration, resolved from the CLI flags, then the environment, then `sync.toml`, then defaults
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// The profile the values were read from, if any
+    pub profile: Option<String>,
+    /// The config file the values were read from, if any
+    pub config_path: Option<PathBuf>,
+    /// The URL of the RPC node, with its API key if there is one
+    pub rpc_url: Option<String>,
+    pub chain_id: Option<u64>,
+    pub contract_address: Option<String>,
+    pub signer: SignerSource,
+    pub private_key: Option<String>,
+    pub keystore: Option<PathBuf>,
+    pub signer_url: Option<String>,
+    pub signer_address: Option<String>,
+    pub store: StoreConfig,
+    /// The directory holding the content to sync
+    pub content_root: Option<PathBuf>,
+    /// Upload markdown without its front matter
+    pub strip_front_matter: bool,
+    /// A JSON Schema that every file's metadata has to match
+    pub metadata_schema: Option<PathBuf>,
+    /// How large a file's metadata may be on chain, in bytes
+    pub metadata_max_bytes: usize,
+    /// The block the contract was deployed in, where the history starts
+    pub deploy_block: u64,
+    /// Where the history index is kept
+    pub history_path: PathBuf,
+    /// How many blocks to ask the RPC node for logs from at once
+    pub log_page_size: u64,
+}
+
+impl Config {
+    /// Resolve the configuration from the environment and defaults alone
+    /// # Returns
+    /// * `Result<Config, SyncError>` - Errors if a value is invalid
+    pub fn from_env() -> Result<Self, SyncError> {
+        Self::resolve(None, ConfigLayer::from_env()?)
+    }
+
+    /// Load the configuration.
+    /// The config file is `path`, then `SYNC_CONFIG`, then `sync.toml` if it exists.
+    /// The profile is `profile`, then `SYNC_PROFILE`, then the file's `profile`
+    /// # Arguments
+    /// * `path` - The config file to read, if given on the command line
+    /// * `profile` - The profile to use, if given on the command line
+    /// * `flags` - Values given on the command line
+    /// # Returns
+    /// * `Result<Config, SyncError>` - Errors if the file or profile is missing or invalid
+    pub fn load(path: Option<&Path>, profile: Option<&str>, flags: ConfigLayer) -> Result<Self, SyncError> {
+        let config_path = match path.map(PathBuf::from).or_else(|| var("SYNC_CONFIG").map(PathBuf::from)) {
+            // A file we were pointed at has to exist
+            Some(path) => Some(path),
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Some(PathBuf::from(DEFAULT_CONFIG_PATH)),
+            None => None,
+        };
+        let file = match &config_path {
+            Some(path) => Self::read_file(path)?,
+            None => ConfigFile::default(),
+        };
+        let profile = profile
+            .map(String::from)
+            .or_else(|| var("SYNC_PROFILE"))
+            .or(file.profile);
+        let mut file_layer = file.defaults;
+        if let Some(name) = &profile {
+            let mut profiles = file.profiles;
+            let layer = profiles
+                .remove(name)
+                .ok_or_else(|| SyncError::Config(format!("No profile named {} in the config file", name)))?;
+            file_layer = layer.or(file_layer);
+        }
+        let mut config = Self::resolve(profile, flags.or(ConfigLayer::from_env()?).or(file_layer))?;
+        config.config_path = config_path;
+        Ok(config)
+    }
+
+    fn read_file(path: &Path) -> Result<ConfigFile, SyncError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| SyncError::Config(format!("Could not read {}: {}", path.display(), e)))?;
+        toml::from_str(&contents)
+            .map_err(|e| SyncError::Config(format!("Could not parse {}: {}", path.display(), e)))
+    }
+
+    /// Apply the defaults to the merged layers
+    fn resolve(profile: Option<String>, layer: ConfigLayer) -> Result<Self, SyncError> {
+        let rpc_url = match (layer.rpc_url, layer.rpc_api_key) {
+            (Some(url), Some(key)) => Some(format!("{}/{}", url.trim_end_matches('/'), key)),
+            (url, _) => url,
+        };
+        // Without a signer, use whichever kind of key is configured
+        let signer = match layer.signer {
+            Some(signer) => signer.parse()?,
+            None if layer.keystore.is_some() => SignerSource::Keystore,
+            None if layer.signer_url.is_some() => SignerSource::Remote,
+            None => SignerSource::PrivateKey,
+        };
+        Ok(Self {
+            profile,
+            config_path: None,
+            rpc_url,
+            chain_id: layer.chain_id,
+            contract_address: layer.contract_address,
+            signer,
+            private_key: layer.private_key,
+            keystore: layer.keystore,
+            signer_url: layer.signer_url,
+            signer_address: layer.signer_address,
+            store: StoreConfig {
+                backend: layer.store.unwrap_or_else(|| String::from("kubo")),
+                estuary_api_url: layer.estuary_api_url,
+                estuary_api_key: layer.estuary_api_key,
+                kubo_api_url: layer
+                    .kubo_api_url
+                    .unwrap_or_else(|| String::from("http://127.0.0.1:5001")),
+                local_store_path: layer.local_store_path.unwrap_or_else(|| PathBuf::from(".store")),
+                gateways: layer.ipfs_gateways.unwrap_or_default(),
+                gateway_strategy: match layer.ipfs_gateway_strategy {
+                    Some(strategy) => strategy.parse()?,
+                    None => GatewayStrategy::InOrder,
+                },
+                gateway_timeout: layer.ipfs_gateway_timeout.unwrap_or(DEFAULT_TIMEOUT.as_secs()),
+                gateway_cooldown: layer.ipfs_gateway_cooldown.unwrap_or(DEFAULT_COOLDOWN.as_secs()),
+                cid_mode: match layer.cid_mode {
+                    Some(mode) => mode.parse()?,
+                    None => CidMode::default(),
+                },
+            },
+            content_root: layer.content_root,
+            strip_front_matter: layer.strip_front_matter.unwrap_or(false),
+            metadata_schema: layer.metadata_schema,
+            metadata_max_bytes: layer.metadata_max_bytes.unwrap_or(DEFAULT_MAX_METADATA_BYTES),
+            deploy_block: layer.deploy_block.unwrap_or(0),
+            history_path: layer.history_path.unwrap_or_else(|| PathBuf::from(DEFAULT_HISTORY_PATH)),
+            log_page_size: layer.log_page_size.unwrap_or(DEFAULT_LOG_PAGE_SIZE),
+        })
+    }
+
+    /// Get a value that has no default, or explain how to set it
+    /// # Arguments
+    /// * `value` - The value, if it was set
+    /// * `name` - The key in `sync.toml`
+    /// * `env` - The environment variable
+    /// # Returns
+    /// * `Result<T, SyncError>` - A configuration error if the value is missing
+    pub fn require<T: Clone>(value: &Option<T>, name: &str, env: &str) -> Result<T, SyncError> {
+        value.clone().ok_or_else(|| {
+            SyncError::Config(format!("{} is not set: set {} in sync.toml or {}", name, name, env))
+        })
+    }
+
+    /// Render the resolved values as TOML, with secrets redacted
+    /// # Returns
+    /// * `String` - The values, in the same form as a profile in `sync.toml`
+    pub fn show(&self) -> String {
+        let layer = ConfigLayer {
+            rpc_url: self.rpc_url.as_deref().map(redact_url),
+            rpc_api_key: None,
+            chain_id: self.chain_id,
+            contract_address: self.contract_address.clone(),
+            signer: Some(self.signer.to_string()),
+            private_key: self.private_key.as_ref().map(|_| REDACTED.to_string()),
+            keystore: self.keystore.clone(),
+            signer_url: self.signer_url.as_deref().map(redact_url),
+            signer_address: self.signer_address.clone(),
+            store: Some(self.store.backend.clone()),
+            estuary_api_url: self.store.estuary_api_url.clone(),
+            estuary_api_key: self.store.estuary_api_key.as_ref().map(|_| REDACTED.to_string()),
+            kubo_api_url: Some(self.store.kubo_api_url.clone()),
+            local_store_path: Some(self.store.local_store_path.clone()),
+            ipfs_gateways: (!self.store.gateways.is_empty()).then(|| self.store.gateways.clone()),
+            ipfs_gateway_strategy: Some(self.store.gateway_strategy.to_string()),
+            ipfs_gateway_timeout: Some(self.store.gateway_timeout),
+            ipfs_gateway_cooldown: Some(self.store.gateway_cooldown),
+            cid_mode: Some(self.store.cid_mode.to_string()),
+            content_root: self.content_root.clone(),
+            strip_front_matter: Some(self.strip_front_matter),
+            metadata_schema: self.metadata_schema.clone(),
+            metadata_max_bytes: Some(self.metadata_max_bytes),
+            deploy_block: Some(self.deploy_block),
+            history_path: Some(self.history_path.clone()),
+            log_page_size: Some(self.log_page_size),
+        };
+        let values = toml::to_string(&layer).unwrap_or_default();
+        match &self.profile {
+            Some(profile) => format!("# profile: {}\n{}", profile, values),
+            None => values,
+        }
+    }
+}
+
+/// RPC providers put API keys in the URL path, so only show the scheme and host
+fn redact_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if parsed.path() != "/" || parsed.query().is_some() || !parsed.username().is_empty() => {
+            let port = parsed.port().map(|port| format!(":{}", port)).unwrap_or_default();
+            format!(
+                "{}://{}{}/{}",
+                parsed.scheme(),
+                parsed.host_str().unwrap_or_default(),
+                port,
+                REDACTED
+            )
+        }
+        Ok(_) => url.to_string(),
+        Err(_) => REDACTED.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+profile = "mumbai"
+
+[defaults]
+store = "local"
+content_root = "content"
+
+[profiles.mumbai]
+rpc_url = "https://polygon-mumbai.g.alchemy.com/v2"
+rpc_api_key = "secret-rpc-key"
+chain_id = 80001
+contract_address = "0x0000000000000000000000000000000000000001"
+private_key = "secret-private-key"
+
+[profiles.matic]
+rpc_url = "https://polygon-rpc.com"
+chain_id = 137
+store = "estuary"
+"#;
+
+    #[test]
+    /// Profiles override the defaults, and flags override everything
+    fn test_layers() {
+        let file: ConfigFile = toml::from_str(CONFIG).unwrap();
+        let mut profiles = file.profiles;
+        let mumbai = profiles.remove("mumbai").unwrap().or(file.defaults.clone());
+        let config = Config::resolve(Some("mumbai".to_string()), mumbai.clone()).unwrap();
+        assert_eq!(
+            config.rpc_url.as_deref(),
+            Some("https://polygon-mumbai.g.alchemy.com/v2/secret-rpc-key")
+        );
+        assert_eq!(config.chain_id, Some(80001));
+        assert_eq!(config.store.backend, "local");
+        assert_eq!(config.content_root, Some(PathBuf::from("content")));
+        assert_eq!(config.signer, SignerSource::PrivateKey);
+
+        let matic = profiles.remove("matic").unwrap().or(file.defaults);
+        let config = Config::resolve(Some("matic".to_string()), matic).unwrap();
+        assert_eq!(config.chain_id, Some(137));
+        assert_eq!(config.store.backend, "estuary");
+        assert_eq!(config.store.estuary_api_url, None);
+        assert_eq!(config.contract_address, None);
+        assert!(Config::require(&config.contract_address, "contract_address", "CONTRACT_ADDRESS").is_err());
+
+        let flags = ConfigLayer {
+            chain_id: Some(31337),
+            ..Default::default()
+        };
+        let config = Config::resolve(None, flags.or(mumbai.clone())).unwrap();
+        assert_eq!(config.chain_id, Some(31337));
+        assert_eq!(config.private_key.as_deref(), Some("secret-private-key"));
+
+        // A URL from a higher layer doesn't pick up the API key of a lower one
+        let flags = ConfigLayer {
+            rpc_url: Some("http://127.0.0.1:8545".to_string()),
+            ..Default::default()
+        };
+        let config = Config::resolve(None, flags.or(mumbai)).unwrap();
+        assert_eq!(config.rpc_url.as_deref(), Some("http://127.0.0.1:8545"));
+    }
+
+    #[test]
+    /// Nothing secret is shown
+    fn test_show() {
+        let file: ConfigFile = toml::from_str(CONFIG).unwrap();
+        let mut profiles = file.profiles;
+        let mumbai = profiles.remove("mumbai").unwrap().or(file.defaults);
+        let shown = Config::resolve(Some("mumbai".to_string()), mumbai).unwrap().show();
+        assert!(!shown.contains("secret"));
+        assert!(shown.contains("private_key = \"<redacted>\""));
+        assert!(shown.contains("rpc_url = \"https://polygon-mumbai.g.alchemy.com/<redacted>\""));
+        assert!(shown.contains("chain_id = 80001"));
+        // What we show can be read back as a profile
+        let _: ConfigLayer = toml::from_str(shown.trim_start_matches("# profile: mumbai\n")).unwrap();
+    }
+
+    #[test]
+    /// The signer follows whichever key source is configured, unless it is named
+    fn test_signer() {
+        let keystore = ConfigLayer {
+            keystore: Some(PathBuf::from("keystore.json")),
+            private_key: Some("secret-private-key".to_string()),
+            ..Default::default()
+        };
+        let config = Config::resolve(None, keystore.clone()).unwrap();
+        assert_eq!(config.signer, SignerSource::Keystore);
+        let remote = ConfigLayer {
+            signer_url: Some("http://127.0.0.1:8550".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(Config::resolve(None, remote).unwrap().signer, SignerSource::Remote);
+        let named = ConfigLayer {
+            signer: Some("private-key".to_string()),
+            ..keystore
+        };
+        assert_eq!(Config::resolve(None, named).unwrap().signer, SignerSource::PrivateKey);
+        let bad = ConfigLayer {
+            signer: Some("ledger".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(Config::resolve(None, bad), Err(SyncError::Config(_))));
+    }
+
+    #[test]
+    /// Gateways are read from the config file, and shown so they can be read back
+    fn test_gateways() {
+        let layer: ConfigLayer = toml::from_str(
+            "ipfs_gateways = [\"https://ipfs.io\", \"https://dweb.link\"]\nipfs_gateway_strategy = \"race\"\nipfs_gateway_timeout = 5",
+        )
+        .unwrap();
+        let config = Config::resolve(None, layer).unwrap();
+        assert_eq!(config.store.gateways, vec!["https://ipfs.io", "https://dweb.link"]);
+        assert_eq!(config.store.gateway_strategy, GatewayStrategy::Race);
+        assert_eq!(config.store.gateway_timeout, 5);
+        assert_eq!(config.store.gateway_cooldown, DEFAULT_COOLDOWN.as_secs());
+        let shown: ConfigLayer = toml::from_str(&config.show()).unwrap();
+        assert_eq!(Config::resolve(None, shown).unwrap(), config);
+        let bad = ConfigLayer {
+            ipfs_gateway_strategy: Some("fastest".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(Config::resolve(None, bad), Err(SyncError::Config(_))));
+    }
+
+    #[test]
+    /// The CID mode defaults to UnixFS, and raw can be chosen and shown
+    fn test_cid_mode() {
+        let config = Config::resolve(None, ConfigLayer::default()).unwrap();
+        assert_eq!(config.store.cid_mode, CidMode::default());
+        let layer: ConfigLayer = toml::from_str("cid_mode = \"raw\"").unwrap();
+        let config = Config::resolve(None, layer).unwrap();
+        assert_eq!(config.store.cid_mode, CidMode::Raw);
+        let shown: ConfigLayer = toml::from_str(&config.show()).unwrap();
+        assert_eq!(Config::resolve(None, shown).unwrap(), config);
+        let bad = ConfigLayer {
+            cid_mode: Some("sha1".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(Config::resolve(None, bad), Err(SyncError::Config(_))));
+    }
+
+    #[test]
+    /// Unknown keys are mistakes, not silently ignored
+    fn test_unknown_key() {
+        assert!(toml::from_str::<ConfigFile>("[profiles.mumbai]\nrpc = \"x\"").is_err());
+    }
+}
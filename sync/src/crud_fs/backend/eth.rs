@@ -0,0 +1,528 @@
+/* Ethereum Backend */
+
+use async_trait::async_trait;
+use ethers::{
+    abi::{Detokenize, Token, Tokenizable},
+    contract::builders::ContractCall,
+    middleware::SignerMiddleware,
+    providers::{Http, Provider},
+    signers::{LocalWallet, Signer},
+};
+use ethers::{
+    prelude::*,
+    types::{Address, U256},
+};
+use std::sync::Arc;
+use std::{
+    convert::TryFrom,
+    path::PathBuf,
+};
+use std::str::FromStr;
+// use rand::Rng;
+use crate::config::Config;
+use crate::error::{revert_reason, Result, SyncError};
+use crate::types::{cid::Cid, crud_file::CrudFile, metadata::Metadata};
+use crate::utils::path::normalize;
+
+use super::signer::SyncSigner;
+use super::Backend;
+
+abigen!(
+    CrudFsContract,
+    // This path is relative to `sync`
+    "./../contracts/artifacts/contracts/crudFs.sol/CrudFs.json",
+    event_derives(serde::Deserialize, serde::Serialize),
+);
+
+pub struct BackendClient {
+    contract: CrudFsContract<EthSigner>,
+}
+
+impl BackendClient {
+    /// Create a BackendClient for the contract at `CONTRACT_ADDRESS`, signing with an EthClient from the environment
+    /// # Returns
+    /// - `Result<BackendClient, SyncError>` - A configuration error if the environment is incomplete
+    pub fn from_env() -> Result<Self, SyncError> {
+        Self::from_config(&Config::from_env()?)
+    }
+
+    /// Create a BackendClient for the configured contract, signing with the configured signer
+    /// # Arguments
+    /// - `config` - The resolved configuration
+    /// # Returns
+    /// - `Result<BackendClient, SyncError>` - A configuration error if the configuration is incomplete
+    pub fn from_config(config: &Config) -> Result<Self, SyncError> {
+        let contract_address = Config::require(&config.contract_address, "contract_address", "CONTRACT_ADDRESS")?;
+        Self::from_eth_client(contract_address, EthClient::from_config(config)?)
+    }
+
+    /// Create a BackendClient that signs with an EthClient from the environment
+    /// # Arguments
+    /// - `contract_address` - The address of the CrudFs contract
+    pub fn new(contract_address: String) -> Result<Self, SyncError> {
+        // Get an Eth client from the environment
+        let eth_client = EthClient::from_env()?;
+        Self::from_eth_client(contract_address, eth_client)
+    }
+
+    /// Create a BackendClient that signs with a given EthClient
+    /// # Arguments
+    /// - `contract_address` - The address of the CrudFs contract
+    /// - `eth_client` - The EthClient to send transactions with
+    pub fn from_eth_client(contract_address: String, eth_client: EthClient) -> Result<Self, SyncError> {
+        // Parse the contract address
+        let contract_address = Address::from_str(&contract_address).map_err(|e| {
+            SyncError::Config(format!("Invalid contract address {:?}: {}", contract_address, e))
+        })?;
+        // Get the underlying client type from the EthClient struct
+        let signer = eth_client.signer;
+        let contract = CrudFsContract::new(contract_address, Arc::new(signer));
+        Ok(Self { contract })
+    }
+
+    /// Send a transaction and wait for it to be mined
+    /// # Arguments
+    /// - `call` - The contract call to send
+    /// # Returns
+    /// - `Result<TransactionReceipt, SyncError>` - The receipt, or an error if the transaction reverted or was dropped.
+    ///   A revert carries its reason where the node gives one
+    async fn send<D: Detokenize>(call: ContractCall<EthSigner, D>) -> Result<TransactionReceipt, SyncError> {
+        let receipt = call
+            .send()
+            .await?
+            .await?
+            .ok_or_else(|| SyncError::Rpc("Transaction was dropped from the mempool".to_string()))?;
+        if receipt.status == Some(U64::zero()) {
+            // Receipts don't carry the reason, so replay the call at the block it was mined in
+            let reason = match receipt.block_number {
+                Some(block) => call.block(block).call().await.err().and_then(|e| revert_reason(&e.to_string())),
+                None => None,
+            };
+            return Err(SyncError::Revert(reason.unwrap_or_else(|| {
+                format!("Transaction {:?} reverted", receipt.transaction_hash)
+            })));
+        }
+        Ok(receipt)
+    }
+
+    /// Find the event a transaction emitted
+    /// # Arguments
+    /// - `receipt` - The receipt of the transaction
+    /// # Returns
+    /// - `Result<D, SyncError>` - The event, or an error if the transaction didn't emit one
+    fn event<D: EthEvent>(&self, receipt: &TransactionReceipt) -> Result<D, SyncError> {
+        receipt
+            .logs
+            .iter()
+            .filter(|log| log.address == self.contract.address())
+            .find_map(|log| self.contract.event::<D>().parse_log(log.clone()).ok())
+            .ok_or_else(|| SyncError::Decode(format!("No {} event in transaction receipt", D::name())))
+    }
+}
+
+#[async_trait]
+impl Backend for BackendClient {
+    /// Create a new file in the backend
+    /// # Arguments
+    /// - `path` - The path of the file to create
+    /// - `cid` - The CID of the file to create
+    /// - `metadata` - The metadata of the file to create
+    /// # Returns
+    /// - `Result<CrudFile, SyncError>` - The created file or an error
+    async fn create(
+        &self,
+        path: PathBuf,
+        cid: Cid,
+        metadata: Metadata,
+    ) -> Result<CrudFile, SyncError> {
+        // Put the path in canonical form, which the contract keys it by
+        let path = normalize(&path)?;
+        let path_string = path.to_string_lossy().to_string();
+        // Get the file name from the path
+        let filename = path
+            .file_name()
+            .and_then(|filename| filename.to_str())
+            .unwrap_or_default()
+            .to_string();
+        // Convert the CID to a string
+        let cid_string = cid.to_string();
+        // Convert the metadata to a string
+        let metadata_string = serde_json::to_string(&metadata)?;
+
+        // Send the transaction to the contract
+        let receipt = Self::send(
+            self.contract
+                .create_file(path_string, cid_string, metadata_string),
+        )
+        .await?;
+
+        // Get the event from the transaction receipt
+        let event: CreateFileFilter = self.event(&receipt)?;
+
+        // Get the key from the event
+        let key = event.key;
+        // Get the timestamp from the event
+        let timestamp = event.timestamp.as_u64();
+
+        Ok(CrudFile {
+            path,
+            filename,
+            key,
+            cid,
+            timestamp,
+            metadata,
+        })
+    }
+
+    /// Read a file from the backend
+    /// # Arguments
+    /// - `key` - The key of the file to read
+    /// # Returns
+    /// - `Result<CrudFile, SyncError>` - The read file or an error
+    async fn read(&self, key: [u8; 32]) -> Result<CrudFile, SyncError> {
+        // Get the file
+        let res = self.contract.read_file(key).call().await?;
+        // Return the file
+        decode_file(res)
+    }
+
+    /// List every file in the backend
+    /// # Returns
+    /// - `Result<Vec<CrudFile>, SyncError>` - All files in the backend or an error
+    async fn list(&self) -> Result<Vec<CrudFile>, SyncError> {
+        let res = self.contract.read_all_files().call().await?;
+        decode_files(res)
+    }
+
+    /// Update a file in the backend
+    /// # Arguments
+    /// - `key` - The key of the file to update
+    /// - `cid` - The CID of the file to update
+    /// - `metadata` - The metadata of the file to update
+    /// # Returns
+    /// - `Result<([u8; 32], u64), SyncError>` - The key and updated timestamp of the file or an error
+    async fn update(
+        &self,
+        key: [u8; 32],
+        cid: Cid,
+        metadata: Metadata,
+    ) -> Result<([u8; 32], u64), SyncError> {
+        // Convert the CID to a string
+        let cid_string = cid.to_string();
+        // Convert the metadata to a string
+        let metadata_string = serde_json::to_string(&metadata)?;
+
+        // Send the transaction to the contract
+        let receipt = Self::send(self.contract.update_file(key, cid_string, metadata_string)).await?;
+
+        // Get the event from the transaction receipt
+        let event: UpdateFileFilter = self.event(&receipt)?;
+
+        // Get the key from the event
+        let key = event.key;
+        // Get the timestamp from the event
+        let timestamp = event.timestamp.as_u64();
+
+        Ok((key, timestamp))
+    }
+
+    /// Delete a file from the backend
+    /// # Arguments
+    /// - `key` - The key of the file to delete
+    /// # Returns
+    /// - `Result<(), SyncError>` - An error if the file could not be deleted
+    async fn delete(&self, key: [u8; 32]) -> Result<(), SyncError> {
+        // Send the transaction to the contract
+        let receipt = Self::send(self.contract.delete_file(key)).await?;
+
+        // Get the event from the transaction receipt
+        let event: DeleteFileFilter = self.event(&receipt)?;
+
+        // Check that the contract deleted the key we sent
+        if event.key != key {
+            return Err(SyncError::Decode("DeleteFile event is for a different key".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Estimate the gas needed to create a file
+    /// # Arguments
+    /// - `path` - The path of the file to create
+    /// - `cid` - The CID of the file to create
+    /// - `metadata` - The metadata of the file to create
+    /// # Returns
+    /// - `Result<U256, SyncError>` - The estimated gas or an error
+    async fn estimate_create(
+        &self,
+        path: PathBuf,
+        cid: Cid,
+        metadata: Metadata,
+    ) -> Result<U256, SyncError> {
+        let path_string = normalize(&path)?.to_string_lossy().to_string();
+        let metadata_string = serde_json::to_string(&metadata)?;
+        let gas = self
+            .contract
+            .create_file(path_string, cid.to_string(), metadata_string)
+            .estimate_gas()
+            .await?;
+        Ok(gas)
+    }
+
+    /// Estimate the gas needed to update a file
+    /// # Arguments
+    /// - `key` - The key of the file to update
+    /// - `cid` - The CID of the file to update
+    /// - `metadata` - The metadata of the file to update
+    /// # Returns
+    /// - `Result<U256, SyncError>` - The estimated gas or an error
+    async fn estimate_update(
+        &self,
+        key: [u8; 32],
+        cid: Cid,
+        metadata: Metadata,
+    ) -> Result<U256, SyncError> {
+        let metadata_string = serde_json::to_string(&metadata)?;
+        let gas = self
+            .contract
+            .update_file(key, cid.to_string(), metadata_string)
+            .estimate_gas()
+            .await?;
+        Ok(gas)
+    }
+
+    /// Estimate the gas needed to delete a file
+    /// # Arguments
+    /// - `key` - The key of the file to delete
+    /// # Returns
+    /// - `Result<U256, SyncError>` - The estimated gas or an error
+    async fn estimate_delete(&self, key: [u8; 32]) -> Result<U256, SyncError> {
+        let gas = self.contract.delete_file(key).estimate_gas().await?;
+        Ok(gas)
+    }
+
+    /// Get the current gas price of the network
+    /// # Returns
+    /// - `Result<U256, SyncError>` - The gas price in wei or an error
+    async fn gas_price(&self) -> Result<U256, SyncError> {
+        let gas_price = self
+            .contract
+            .client()
+            .get_gas_price()
+            .await
+            .map_err(|e| SyncError::Rpc(e.to_string()))?;
+        Ok(gas_price)
+    }
+}
+
+/// Decode a file as `readFile` and `readFileAtIndex` return it
+/// # Arguments
+/// - `file` - The path, CID, timestamp and metadata of the file
+/// # Returns
+/// - `Result<CrudFile, SyncError>` - The file, or an error if any member is malformed
+pub(crate) fn decode_file(file: (String, String, U256, String)) -> Result<CrudFile, SyncError> {
+    CrudFile::from_token(file.into_token()).map_err(|e| SyncError::Decode(e.to_string()))
+}
+
+/// Decode the files `readAllFiles` and `readFiles` return
+/// # Arguments
+/// - `files` - The paths, CIDs, timestamps and metadata of the files, one array per member
+/// # Returns
+/// - `Result<Vec<CrudFile>, SyncError>` - The files, or an error if any member is malformed
+pub(crate) fn decode_files(
+    files: (Vec<String>, Vec<String>, Vec<U256>, Vec<String>),
+) -> Result<Vec<CrudFile>, SyncError> {
+    let (paths, cids, timestamps, metadata) = files;
+    // Zip the struct members back into one tuple per file
+    let mut crud_files = Vec::with_capacity(paths.len());
+    for (((path, cid), timestamp), metadata) in paths
+        .into_iter()
+        .zip(cids)
+        .zip(timestamps)
+        .zip(metadata)
+    {
+        let token = Token::Tuple(vec![
+            Token::String(path),
+            Token::String(cid),
+            Token::Uint(timestamp),
+            Token::String(metadata),
+        ]);
+        crud_files.push(CrudFile::from_token(token).map_err(|e| SyncError::Decode(e.to_string()))?);
+    }
+    Ok(crud_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Test Initialization from .env file
+    fn client_init() {
+        // Load the .env file from the root of the project
+        dotenv::from_filename("../env/.env").expect("Failed to load .env file");
+        // Initialize the Client from the environment variables
+        let _ = BackendClient::from_env().unwrap();
+    }
+
+    #[tokio::test]
+    /// Test creating a file
+    async fn test_crud() {
+        use crate::utils::hash::hash_path;
+        use rand::Rng;
+
+        // Initialize the Client from the .env file
+        dotenv::from_path("./../../env/.env").ok();
+        let client = BackendClient::from_env().unwrap();
+
+        // Use a random string as the path
+        let path = PathBuf::from(format!("/tmp/{}", rand::thread_rng().gen::<u64>()));
+        // Hash the path
+        let key = hash_path(&path).unwrap();
+        // Create a new CID
+        let cid = Cid::from_str(
+            "bafybeic3gsbthvobb2jenjpeam32yj2hrznnmk4ei4rwbdosexc4ewyz7e".to_string(),
+        )
+        .unwrap();
+        // Create a new metadata with 'test: value'
+        let mut metadata = Metadata::new();
+        metadata.insert("test".to_string(), "value".to_string());
+
+        // Create the file
+        let result = client
+            .create(path.clone(), cid.clone(), metadata.clone())
+            .await;
+
+        // Assert that the result is Ok
+        assert!(result.is_ok());
+
+        // Get the CrudFile from the result
+        let crud_file = result.unwrap();
+        // Verify the contents of the file
+        assert_eq!(crud_file.path, path);
+        assert_eq!(crud_file.key, key);
+        assert_eq!(crud_file.cid, cid);
+        assert_eq!(crud_file.metadata, metadata);
+        assert!(crud_file.timestamp > 0);
+        let timestamp = crud_file.timestamp;
+
+        // R is for read
+
+        // Read the file
+        println!("Reading file with key: {:?}", ethers::types::Bytes::from(key.to_vec()));
+        let result = client.read(key).await;
+        // Assert that the result is Ok
+        assert!(result.is_ok());
+        // Get the CrudFile from the result
+        let crud_file = result.unwrap();
+        // Verify the contents of the file
+        assert_eq!(crud_file.path, path);
+        assert_eq!(crud_file.key, key);
+        assert_eq!(crud_file.cid, cid);
+        assert_eq!(crud_file.metadata, metadata);
+        assert_eq!(crud_file.timestamp, timestamp);
+
+        // U is for update
+
+        // Create a new CID
+        let cid = Cid::from_str(
+            "bafkreiai4g4rxn3kkeqyi3vi4ovjs4ewugqxpek4x2kr7tlhbmhd2gw6nq".to_string(),
+        )
+        .unwrap();
+        // Create a new metadata with 'test: value'
+        let mut metadata = Metadata::new();
+        metadata.insert("test".to_string(), "value2".to_string());
+
+        // Update the file
+        let result = client.update(key, cid.clone(), metadata.clone()).await;
+
+        // Assert that the result is Ok
+        assert!(result.is_ok());
+
+        // Get the CrudFile from the result
+        let (key, timestamp) = result.unwrap();
+        // Verify the contents of the file
+
+        // Read the file to verify the contents
+        let result = client.read(key).await;
+        // Assert that the result is Ok
+        assert!(result.is_ok());
+        // Get the CrudFile from the result
+        let crud_file = result.unwrap();
+        // Verify the contents of the file
+        assert_eq!(crud_file.path, path);
+        assert_eq!(crud_file.key, key);
+        assert_eq!(crud_file.cid, cid);
+        assert_eq!(crud_file.metadata, metadata);
+        assert_eq!(crud_file.timestamp, timestamp);
+
+        // D is for delete
+
+        // Delete the file
+        let result = client.delete(key).await;
+        // Assert that the result is Ok
+        assert!(result.is_ok());
+    }
+}
+
+/* Eth Client */
+
+pub type EthSigner = SignerMiddleware<Provider<Http>, SyncSigner>;
+
+/// A multi-purpose Ethereum Client - just a wrapper around ethers::SignerMiddleware
+#[derive(Debug, Clone)]
+pub struct EthClient {
+    pub signer: EthSigner,
+}
+
+impl EthClient {
+    /// Create a new EthClient from `RPC_URL` (or `API_URL`), `API_KEY`, `CHAIN_ID` and the signer's variables
+    /// # Returns
+    /// * `Result<EthClient, SyncError>` - A configuration error if any are missing or invalid
+    pub fn from_env() -> Result<Self, SyncError> {
+        Self::from_config(&Config::from_env()?)
+    }
+
+    /// Create a new EthClient for the configured RPC node and signer
+    /// # Arguments
+    /// * `config` - The resolved configuration
+    /// # Returns
+    /// * `Result<EthClient, SyncError>` - A configuration error if any values are missing or invalid
+    pub fn from_config(config: &Config) -> Result<Self, SyncError> {
+        let rpc_url = Config::require(&config.rpc_url, "rpc_url", "RPC_URL")?;
+        let chain_id = Config::require(&config.chain_id, "chain_id", "CHAIN_ID")?;
+        let signer = SyncSigner::from_config(config, chain_id)?;
+        EthClient::with_signer(rpc_url, chain_id, signer)
+    }
+
+    /// Create a new EthClient - Uses EthClientBuilder::new()
+    /// # Arguments
+    /// * `rpc_url` - The URL of the Ethereum API to connect to, including any API key
+    /// * `chain_id` - The Chain ID of the network we're connected to.
+    /// * `private_key` - The Private Key for the Ethereum Account we're using to sign.
+    pub fn new(
+        rpc_url: String,
+        chain_id: u64,
+        private_key: String,
+    ) -> Result<Self, SyncError> {
+        // Initialize a Wallet to use
+        let wallet = private_key
+            .parse::<LocalWallet>()
+            .map_err(|e| SyncError::Config(format!("Invalid private_key: {}", e)))?;
+        Self::with_signer(rpc_url, chain_id, SyncSigner::from(wallet))
+    }
+
+    /// Create a new EthClient that signs with any SyncSigner
+    /// # Arguments
+    /// * `rpc_url` - The URL of the Ethereum API to connect to, including any API key
+    /// * `chain_id` - The Chain ID of the network we're connected to.
+    /// * `signer` - What signs our transactions
+    pub fn with_signer(rpc_url: String, chain_id: u64, signer: SyncSigner) -> Result<Self, SyncError> {
+        // Determine an API URL and Initialize the Provider
+        let provider = Provider::<Http>::try_from(rpc_url)
+            .map_err(|e| SyncError::Config(format!("Invalid rpc_url: {}", e)))?;
+        let signer = SignerMiddleware::new(provider, signer.with_chain_id(chain_id));
+        // Return the Client
+        Ok(Self { signer })
+    }
+}
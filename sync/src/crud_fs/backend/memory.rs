@@ -0,0 +1,215 @@
+/* In-Memory Backend */
+
+use crate::error::{Result, SyncError};
+use async_trait::async_trait;
+use ethers::types::U256;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::types::{cid::Cid, crud_file::CrudFile, metadata::Metadata};
+use crate::utils::{hash::hash_path, path::normalize};
+
+use super::eth::{CreateFileFilter, CrudFsContractEvents, DeleteFileFilter, UpdateFileFilter};
+use super::Backend;
+
+/// InMemoryBackend - A Backend that mirrors the CrudFs contract in memory
+/// Reverts with the same messages as the contract, and records the events it would emit
+pub struct InMemoryBackend {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The key set, in the order the contract's UnorderedKeySetLib would keep it
+    keys: Vec<[u8; 32]>,
+    /// The files, by key
+    files: HashMap<[u8; 32], CrudFile>,
+    /// Every event emitted so far
+    events: Vec<CrudFsContractEvents>,
+}
+
+impl Default for InMemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryBackend {
+    /// Create a new, empty InMemoryBackend
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Every event emitted so far, oldest first
+    pub fn events(&self) -> Vec<CrudFsContractEvents> {
+        self.state.lock().unwrap().events.clone()
+    }
+
+    /// Stand in for `block.timestamp`
+    fn timestamp() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl Backend for InMemoryBackend {
+    async fn create(&self, path: PathBuf, cid: Cid, metadata: Metadata) -> Result<CrudFile, SyncError> {
+        let path = normalize(&path)?;
+        if path.as_os_str().is_empty() {
+            return Err(SyncError::Revert("Path cannot be empty".to_string()));
+        }
+        let key = hash_path(&path)?;
+        let mut state = self.state.lock().unwrap();
+        if state.files.contains_key(&key) {
+            return Err(SyncError::Revert("File already exists.".to_string()));
+        }
+        let filename = path
+            .file_name()
+            .and_then(|f| f.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let timestamp = Self::timestamp();
+        let crud_file = CrudFile {
+            path,
+            filename,
+            key,
+            cid,
+            timestamp,
+            metadata,
+        };
+        state.keys.push(key);
+        state.files.insert(key, crud_file.clone());
+        state.events.push(CrudFsContractEvents::CreateFileFilter(CreateFileFilter {
+            key,
+            timestamp: U256::from(timestamp),
+            cid: crud_file.cid.to_string(),
+            metadata: serde_json::to_string(&crud_file.metadata)?,
+        }));
+        Ok(crud_file)
+    }
+
+    async fn read(&self, key: [u8; 32]) -> Result<CrudFile, SyncError> {
+        let state = self.state.lock().unwrap();
+        state
+            .files
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| SyncError::Revert("File does not exist.".to_string()))
+    }
+
+    async fn list(&self) -> Result<Vec<CrudFile>, SyncError> {
+        let state = self.state.lock().unwrap();
+        Ok(state.keys.iter().map(|key| state.files[key].clone()).collect())
+    }
+
+    async fn update(&self, key: [u8; 32], cid: Cid, metadata: Metadata) -> Result<([u8; 32], u64), SyncError> {
+        let mut state = self.state.lock().unwrap();
+        let timestamp = Self::timestamp();
+        let crud_file = state
+            .files
+            .get_mut(&key)
+            .ok_or_else(|| SyncError::Revert("File does not exist.".to_string()))?;
+        crud_file.cid = cid;
+        crud_file.timestamp = timestamp;
+        crud_file.metadata = metadata;
+        let event = CrudFsContractEvents::UpdateFileFilter(UpdateFileFilter {
+            key,
+            timestamp: U256::from(timestamp),
+            cid: crud_file.cid.to_string(),
+            metadata: serde_json::to_string(&crud_file.metadata)?,
+        });
+        state.events.push(event);
+        Ok((key, timestamp))
+    }
+
+    async fn delete(&self, key: [u8; 32]) -> Result<(), SyncError> {
+        let mut state = self.state.lock().unwrap();
+        if state.files.remove(&key).is_none() {
+            return Err(SyncError::Revert("File does not exist.".to_string()));
+        }
+        // UnorderedKeySetLib moves the last key into the removed slot
+        if let Some(index) = state.keys.iter().position(|k| *k == key) {
+            state.keys.swap_remove(index);
+        }
+        state.events.push(CrudFsContractEvents::DeleteFileFilter(DeleteFileFilter { key }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    /// Run the CRUD cycle against the contract's semantics
+    async fn test_crud() {
+        let backend = InMemoryBackend::new();
+        let path = PathBuf::from("content/hello.md");
+        let key = hash_path(&path).unwrap();
+        let cid = Cid::from(b"hello".as_slice());
+        let mut metadata = Metadata::new();
+        metadata.insert("test".to_string(), "value".to_string());
+
+        // C is for create
+        let crud_file = backend.create(path.clone(), cid.clone(), metadata.clone()).await.unwrap();
+        assert_eq!(crud_file.key, key);
+        assert!(crud_file.timestamp > 0);
+        let err = backend.create(path.clone(), cid.clone(), metadata.clone()).await.unwrap_err();
+        assert!(matches!(err, SyncError::Revert(reason) if reason == "File already exists."));
+        let err = backend.create(PathBuf::new(), cid.clone(), metadata.clone()).await.unwrap_err();
+        assert!(matches!(err, SyncError::Revert(reason) if reason == "Path cannot be empty"));
+
+        // R is for read
+        let read = backend.read(key).await.unwrap();
+        assert_eq!(read.path, path);
+        assert_eq!(read.cid, cid);
+        assert_eq!(read.metadata, metadata);
+
+        // U is for update
+        let new_cid = Cid::from(b"hello again".as_slice());
+        let (updated_key, _) = backend.update(key, new_cid.clone(), metadata.clone()).await.unwrap();
+        assert_eq!(updated_key, key);
+        assert_eq!(backend.read(key).await.unwrap().cid, new_cid);
+        let err = backend.update([0; 32], new_cid, metadata).await.unwrap_err();
+        assert!(matches!(err, SyncError::Revert(reason) if reason == "File does not exist."));
+
+        // D is for delete
+        backend.delete(key).await.unwrap();
+        assert!(backend.list().await.unwrap().is_empty());
+        let err = backend.read(key).await.unwrap_err();
+        assert!(matches!(err, SyncError::Revert(reason) if reason == "File does not exist."));
+        let err = backend.delete(key).await.unwrap_err();
+        assert!(matches!(err, SyncError::Revert(reason) if reason == "File does not exist."));
+
+        // Every successful call emitted one event
+        let events = backend.events();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], CrudFsContractEvents::CreateFileFilter(_)));
+        assert!(matches!(events[1], CrudFsContractEvents::UpdateFileFilter(_)));
+        assert!(matches!(events[2], CrudFsContractEvents::DeleteFileFilter(_)));
+    }
+
+    #[tokio::test]
+    /// Deleting moves the last key into the deleted slot, like the contract
+    async fn test_list_order() {
+        let backend = InMemoryBackend::new();
+        let cid = Cid::from(b"hello".as_slice());
+        let mut keys = Vec::new();
+        for name in ["a", "b", "c"] {
+            let crud_file = backend.create(PathBuf::from(name), cid.clone(), Metadata::new()).await.unwrap();
+            keys.push(crud_file.key);
+        }
+        backend.delete(keys[0]).await.unwrap();
+        let listed: Vec<[u8; 32]> = backend.list().await.unwrap().iter().map(|f| f.key).collect();
+        assert_eq!(listed, vec![keys[2], keys[1]]);
+    }
+}
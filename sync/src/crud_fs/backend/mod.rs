@@ -0,0 +1,76 @@
+use crate::types::{cid::Cid, crud_file::CrudFile, metadata::Metadata};
+use crate::error::{Result, SyncError};
+use async_trait::async_trait;
+use ethers::types::U256;
+use std::path::PathBuf;
+
+pub mod eth;
+pub mod memory;
+pub mod read_only;
+pub mod signer;
+
+pub use eth::{BackendClient, EthClient, EthSigner};
+pub use memory::InMemoryBackend;
+pub use read_only::ReadOnlyBackend;
+pub use signer::{RemoteSigner, SyncSigner};
+
+/// A backend that maintains CrudFs state, with the semantics of the CrudFs contract
+#[async_trait]
+pub trait Backend: Send + Sync {
+    /// Create a new file in the backend
+    /// # Arguments
+    /// - `path` - The path of the file to create
+    /// - `cid` - The CID of the file to create
+    /// - `metadata` - The metadata of the file to create
+    /// # Returns
+    /// - `Result<CrudFile, SyncError>` - The created file or an error
+    async fn create(&self, path: PathBuf, cid: Cid, metadata: Metadata) -> Result<CrudFile, SyncError>;
+
+    /// Read a file from the backend
+    /// # Arguments
+    /// - `key` - The key of the file to read
+    /// # Returns
+    /// - `Result<CrudFile, SyncError>` - The read file or an error
+    async fn read(&self, key: [u8; 32]) -> Result<CrudFile, SyncError>;
+
+    /// List every file in the backend
+    /// # Returns
+    /// - `Result<Vec<CrudFile>, SyncError>` - All files in the backend or an error
+    async fn list(&self) -> Result<Vec<CrudFile>, SyncError>;
+
+    /// Update a file in the backend
+    /// # Arguments
+    /// - `key` - The key of the file to update
+    /// - `cid` - The CID of the file to update
+    /// - `metadata` - The metadata of the file to update
+    /// # Returns
+    /// - `Result<([u8; 32], u64), SyncError>` - The key and updated timestamp of the file or an error
+    async fn update(&self, key: [u8; 32], cid: Cid, metadata: Metadata) -> Result<([u8; 32], u64), SyncError>;
+
+    /// Delete a file from the backend
+    /// # Arguments
+    /// - `key` - The key of the file to delete
+    /// # Returns
+    /// - `Result<(), SyncError>` - An error if the file could not be deleted
+    async fn delete(&self, key: [u8; 32]) -> Result<(), SyncError>;
+
+    /// Estimate the gas needed to create a file. Backends without gas return zero
+    async fn estimate_create(&self, _path: PathBuf, _cid: Cid, _metadata: Metadata) -> Result<U256, SyncError> {
+        Ok(U256::zero())
+    }
+
+    /// Estimate the gas needed to update a file. Backends without gas return zero
+    async fn estimate_update(&self, _key: [u8; 32], _cid: Cid, _metadata: Metadata) -> Result<U256, SyncError> {
+        Ok(U256::zero())
+    }
+
+    /// Estimate the gas needed to delete a file. Backends without gas return zero
+    async fn estimate_delete(&self, _key: [u8; 32]) -> Result<U256, SyncError> {
+        Ok(U256::zero())
+    }
+
+    /// Get the current gas price. Backends without gas return zero
+    async fn gas_price(&self) -> Result<U256, SyncError> {
+        Ok(U256::zero())
+    }
+}
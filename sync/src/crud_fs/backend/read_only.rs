@@ -0,0 +1,226 @@
+/* Read-only Ethereum Backend */
+
+use async_trait::async_trait;
+use ethers::{
+    providers::{Http, Middleware, Provider},
+    types::{Address, U256},
+};
+use std::{collections::HashMap, convert::TryFrom, path::PathBuf, str::FromStr, sync::Arc};
+
+use crate::config::Config;
+use crate::error::{Result, SyncError};
+use crate::history::{Change, EventSource, Revision};
+use crate::types::{cid::Cid, crud_file::CrudFile, metadata::Metadata};
+
+use super::eth::{decode_file, decode_files, CrudFsContract};
+use super::Backend;
+
+/// ReadOnlyBackend - Reads the CrudFs contract over a bare Provider, without a key.
+/// Every view is a plain `eth_call`; every write fails with `SyncError::ReadOnly`
+pub struct ReadOnlyBackend {
+    contract: CrudFsContract<Provider<Http>>,
+}
+
+impl ReadOnlyBackend {
+    /// Create a ReadOnlyBackend from `RPC_URL` (or `API_URL`), `API_KEY` and `CONTRACT_ADDRESS`
+    /// # Returns
+    /// - `Result<ReadOnlyBackend, SyncError>` - A configuration error if the environment is incomplete
+    pub fn from_env() -> Result<Self, SyncError> {
+        Self::from_config(&Config::from_env()?)
+    }
+
+    /// Create a ReadOnlyBackend for the configured RPC node and contract. No signer is needed
+    /// # Arguments
+    /// - `config` - The resolved configuration
+    /// # Returns
+    /// - `Result<ReadOnlyBackend, SyncError>` - A configuration error if the configuration is incomplete
+    pub fn from_config(config: &Config) -> Result<Self, SyncError> {
+        let rpc_url = Config::require(&config.rpc_url, "rpc_url", "RPC_URL")?;
+        let contract_address = Config::require(&config.contract_address, "contract_address", "CONTRACT_ADDRESS")?;
+        Self::new(rpc_url, contract_address)
+    }
+
+    /// Create a new ReadOnlyBackend
+    /// # Arguments
+    /// - `rpc_url` - The URL of the Ethereum API to connect to, including any API key
+    /// - `contract_address` - The address of the CrudFs contract
+    pub fn new(rpc_url: String, contract_address: String) -> Result<Self, SyncError> {
+        let provider = Provider::<Http>::try_from(rpc_url)
+            .map_err(|e| SyncError::Config(format!("Invalid rpc_url: {}", e)))?;
+        let contract_address = Address::from_str(&contract_address).map_err(|e| {
+            SyncError::Config(format!("Invalid contract address {:?}: {}", contract_address, e))
+        })?;
+        let contract = CrudFsContract::new(contract_address, Arc::new(provider));
+        Ok(Self { contract })
+    }
+
+    /// The account that owns the contract, and so is the only one that can write to it
+    /// # Returns
+    /// - `Result<Address, SyncError>` - The owner or an error
+    pub async fn owner(&self) -> Result<Address, SyncError> {
+        Ok(self.contract.owner().call().await?)
+    }
+
+    /// How many files the contract holds
+    /// # Returns
+    /// - `Result<u64, SyncError>` - The number of files or an error
+    pub async fn count(&self) -> Result<u64, SyncError> {
+        let count = self.contract.read_file_count().call().await?;
+        Ok(count.as_u64())
+    }
+
+    /// The key of every file, in the contract's order
+    /// # Returns
+    /// - `Result<Vec<[u8; 32]>, SyncError>` - The keys or an error
+    pub async fn keys(&self) -> Result<Vec<[u8; 32]>, SyncError> {
+        Ok(self.contract.read_all_file_keys().call().await?)
+    }
+
+    /// The key of the file at an index in the contract's order
+    /// # Arguments
+    /// - `index` - The index of the file
+    /// # Returns
+    /// - `Result<[u8; 32], SyncError>` - The key, or a revert if the index is out of range
+    pub async fn key_at(&self, index: u64) -> Result<[u8; 32], SyncError> {
+        Ok(self.contract.read_file_key_at_index(U256::from(index)).call().await?)
+    }
+
+    /// Read the file at an index in the contract's order
+    /// # Arguments
+    /// - `index` - The index of the file
+    /// # Returns
+    /// - `Result<CrudFile, SyncError>` - The file, or a revert if the index is out of range
+    pub async fn read_at(&self, index: u64) -> Result<CrudFile, SyncError> {
+        let res = self.contract.read_file_at_index(U256::from(index)).call().await?;
+        decode_file(res)
+    }
+
+    /// Read several files in one call
+    /// # Arguments
+    /// - `keys` - The keys of the files to read
+    /// # Returns
+    /// - `Result<Vec<CrudFile>, SyncError>` - The files in the order of `keys`, or a revert if any don't exist
+    pub async fn read_many(&self, keys: Vec<[u8; 32]>) -> Result<Vec<CrudFile>, SyncError> {
+        let res = self.contract.read_files(keys).call().await?;
+        decode_files(res)
+    }
+
+    /// The error every write fails with
+    fn read_only(operation: &str) -> SyncError {
+        SyncError::ReadOnly(format!(
+            "{} needs a signer; configure private_key, keystore or signer_url",
+            operation
+        ))
+    }
+}
+
+#[async_trait]
+impl Backend for ReadOnlyBackend {
+    async fn create(&self, _path: PathBuf, _cid: Cid, _metadata: Metadata) -> Result<CrudFile, SyncError> {
+        Err(Self::read_only("createFile"))
+    }
+
+    async fn read(&self, key: [u8; 32]) -> Result<CrudFile, SyncError> {
+        let res = self.contract.read_file(key).call().await?;
+        decode_file(res)
+    }
+
+    async fn list(&self) -> Result<Vec<CrudFile>, SyncError> {
+        let res = self.contract.read_all_files().call().await?;
+        decode_files(res)
+    }
+
+    async fn update(&self, _key: [u8; 32], _cid: Cid, _metadata: Metadata) -> Result<([u8; 32], u64), SyncError> {
+        Err(Self::read_only("updateFile"))
+    }
+
+    async fn delete(&self, _key: [u8; 32]) -> Result<(), SyncError> {
+        Err(Self::read_only("deleteFile"))
+    }
+
+    async fn estimate_create(&self, _path: PathBuf, _cid: Cid, _metadata: Metadata) -> Result<U256, SyncError> {
+        Err(Self::read_only("createFile"))
+    }
+
+    async fn estimate_update(&self, _key: [u8; 32], _cid: Cid, _metadata: Metadata) -> Result<U256, SyncError> {
+        Err(Self::read_only("updateFile"))
+    }
+
+    async fn estimate_delete(&self, _key: [u8; 32]) -> Result<U256, SyncError> {
+        Err(Self::read_only("deleteFile"))
+    }
+
+    async fn gas_price(&self) -> Result<U256, SyncError> {
+        Ok(self.contract.client().get_gas_price().await?)
+    }
+}
+
+#[async_trait]
+impl EventSource for ReadOnlyBackend {
+    async fn block_number(&self) -> Result<u64, SyncError> {
+        Ok(self.contract.client().get_block_number().await?.as_u64())
+    }
+
+    async fn revisions(&self, from_block: u64, to_block: u64) -> Result<Vec<Revision>, SyncError> {
+        let events = self
+            .contract
+            .events()
+            .from_block(from_block)
+            .to_block(to_block)
+            .query_with_meta()
+            .await?;
+        let mut revisions = Vec::with_capacity(events.len());
+        // DeleteFile carries no timestamp, so deletes take their block's
+        let mut timestamps: HashMap<u64, u64> = HashMap::new();
+        for (event, meta) in events {
+            let block_number = meta.block_number.as_u64();
+            let revision = Revision::from_event(event, block_number, meta.log_index.as_u64(), meta.transaction_hash.0)?;
+            let mut revision = match revision {
+                Some(revision) => revision,
+                None => continue,
+            };
+            if revision.change == Change::Delete {
+                revision.timestamp = match timestamps.get(&block_number) {
+                    Some(timestamp) => *timestamp,
+                    None => {
+                        let block = self
+                            .contract
+                            .client()
+                            .get_block(block_number)
+                            .await?
+                            .ok_or_else(|| SyncError::Rpc(format!("Block {} not found", block_number)))?;
+                        timestamps.insert(block_number, block.timestamp.as_u64());
+                        block.timestamp.as_u64()
+                    }
+                };
+            }
+            revisions.push(revision);
+        }
+        Ok(revisions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    /// Writes fail before anything is sent, so no node is needed
+    async fn test_writes_fail() {
+        let backend = ReadOnlyBackend::new(
+            "http://127.0.0.1:1".to_string(),
+            "0x0000000000000000000000000000000000000001".to_string(),
+        )
+        .unwrap();
+        let cid = Cid::from(b"hello".as_slice());
+        let err = backend.create(PathBuf::from("a.md"), cid.clone(), Metadata::new()).await.unwrap_err();
+        assert!(matches!(err, SyncError::ReadOnly(_)));
+        assert!(err.to_string().contains("createFile"));
+        let err = backend.update([0; 32], cid, Metadata::new()).await.unwrap_err();
+        assert!(matches!(err, SyncError::ReadOnly(_)));
+        let err = backend.delete([0; 32]).await.unwrap_err();
+        assert!(matches!(err, SyncError::ReadOnly(_)));
+        let err = backend.estimate_delete([0; 32]).await.unwrap_err();
+        assert!(matches!(err, SyncError::ReadOnly(_)));
+    }
+}
@@ -0,0 +1,291 @@
+/* Signers */
+
+use crate::config::{Config, SignerSource};
+use crate::error::{Result, SyncError};
+use crate::utils::keystore;
+use async_trait::async_trait;
+use ethers::{
+    providers::{Http, Provider},
+    signers::{LocalWallet, Signer},
+    types::{
+        transaction::{eip2718::TypedTransaction, eip712::Eip712},
+        Address, Bytes, Signature,
+    },
+    utils::rlp,
+};
+use serde_json::Value;
+use std::{convert::TryFrom, str::FromStr};
+
+/// SyncSigner - Signs transactions with a local wallet, or asks a remote signer to
+#[derive(Debug, Clone)]
+pub enum SyncSigner {
+    /// A wallet we hold the key for, from a private key or a keystore
+    Local(LocalWallet),
+    /// A signer reached over JSON-RPC
+    Remote(RemoteSigner),
+}
+
+impl SyncSigner {
+    /// Create the configured signer. A keystore's passphrase is read from `KEYSTORE_PASSPHRASE`,
+    /// or prompted for
+    /// # Arguments
+    /// * `config` - The resolved configuration
+    /// * `chain_id` - The chain id to sign transactions for
+    /// # Returns
+    /// * `Result<SyncSigner, SyncError>` - A configuration error if the signer's values are missing
+    pub fn from_config(config: &Config, chain_id: u64) -> Result<Self, SyncError> {
+        let signer = match config.signer {
+            SignerSource::PrivateKey => {
+                let private_key = Config::require(&config.private_key, "private_key", "PRIVATE_KEY")?;
+                let wallet = private_key
+                    .parse::<LocalWallet>()
+                    .map_err(|e| SyncError::Config(format!("Invalid private_key: {}", e)))?;
+                SyncSigner::Local(wallet)
+            }
+            SignerSource::Keystore => {
+                let path = Config::require(&config.keystore, "keystore", "KEYSTORE")?;
+                let passphrase = keystore::passphrase(&path, false)?;
+                SyncSigner::Local(keystore::decrypt(&path, &passphrase)?)
+            }
+            SignerSource::Remote => {
+                let url = Config::require(&config.signer_url, "signer_url", "SIGNER_URL")?;
+                let address = Config::require(&config.signer_address, "signer_address", "SIGNER_ADDRESS")?;
+                let address = Address::from_str(&address)
+                    .map_err(|e| SyncError::Config(format!("Invalid signer_address: {}", e)))?;
+                SyncSigner::Remote(RemoteSigner::new(&url, address, chain_id)?)
+            }
+        };
+        Ok(signer.with_chain_id(chain_id))
+    }
+}
+
+impl From<LocalWallet> for SyncSigner {
+    fn from(wallet: LocalWallet) -> Self {
+        SyncSigner::Local(wallet)
+    }
+}
+
+impl From<RemoteSigner> for SyncSigner {
+    fn from(signer: RemoteSigner) -> Self {
+        SyncSigner::Remote(signer)
+    }
+}
+
+#[async_trait]
+impl Signer for SyncSigner {
+    type Error = SyncError;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(&self, message: S) -> Result<Signature, SyncError> {
+        match self {
+            SyncSigner::Local(wallet) => Ok(wallet.sign_message(message).await?),
+            SyncSigner::Remote(signer) => signer.sign_message(message).await,
+        }
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, SyncError> {
+        match self {
+            SyncSigner::Local(wallet) => Ok(wallet.sign_transaction(tx).await?),
+            SyncSigner::Remote(signer) => signer.sign_transaction(tx).await,
+        }
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(&self, payload: &T) -> Result<Signature, SyncError> {
+        match self {
+            SyncSigner::Local(wallet) => Ok(wallet.sign_typed_data(payload).await?),
+            SyncSigner::Remote(signer) => signer.sign_typed_data(payload).await,
+        }
+    }
+
+    fn address(&self) -> Address {
+        match self {
+            SyncSigner::Local(wallet) => wallet.address(),
+            SyncSigner::Remote(signer) => signer.address(),
+        }
+    }
+
+    fn chain_id(&self) -> u64 {
+        match self {
+            SyncSigner::Local(wallet) => wallet.chain_id(),
+            SyncSigner::Remote(signer) => signer.chain_id(),
+        }
+    }
+
+    fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+        match self {
+            SyncSigner::Local(wallet) => SyncSigner::Local(wallet.with_chain_id(chain_id)),
+            SyncSigner::Remote(signer) => SyncSigner::Remote(signer.with_chain_id(chain_id)),
+        }
+    }
+}
+
+/// RemoteSigner - Signs with an account held by a JSON-RPC signer, such as clef or web3signer,
+/// using `eth_signTransaction` and `eth_sign`
+#[derive(Debug, Clone)]
+pub struct RemoteSigner {
+    provider: Provider<Http>,
+    address: Address,
+    chain_id: u64,
+}
+
+impl RemoteSigner {
+    /// Create a new RemoteSigner
+    /// # Arguments
+    /// * `url` - The JSON-RPC endpoint of the signer
+    /// * `address` - The account to sign with
+    /// * `chain_id` - The chain id to sign transactions for
+    /// # Returns
+    /// * `Result<RemoteSigner, SyncError>` - A configuration error if the URL is invalid
+    pub fn new(url: &str, address: Address, chain_id: u64) -> Result<Self, SyncError> {
+        let provider = Provider::<Http>::try_from(url)
+            .map_err(|e| SyncError::Config(format!("Invalid signer_url: {}", e)))?;
+        Ok(Self {
+            provider,
+            address,
+            chain_id,
+        })
+    }
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(&self, message: S) -> Result<Signature, SyncError> {
+        // The signer adds the message prefix, as we would
+        let message = Bytes::from(message.as_ref().to_vec());
+        let signature: String = self.provider.request("eth_sign", (self.address, message)).await?;
+        Signature::from_str(&signature).map_err(|e| SyncError::Signer(format!("Invalid signature: {}", e)))
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, SyncError> {
+        let mut tx = tx.clone();
+        tx.set_from(self.address);
+        if tx.chain_id().is_none() {
+            tx.set_chain_id(self.chain_id);
+        }
+        // geth and clef answer with `{ raw, tx }`, web3signer with just the raw transaction
+        let response: Value = self.provider.request("eth_signTransaction", [&tx]).await?;
+        let raw = match &response {
+            Value::String(raw) => raw.as_str(),
+            Value::Object(fields) => fields.get("raw").and_then(Value::as_str).unwrap_or_default(),
+            _ => "",
+        };
+        let raw = Bytes::from_str(raw)
+            .map_err(|_| SyncError::Signer(format!("Unexpected eth_signTransaction response: {}", response)))?;
+        let (_, signature) = TypedTransaction::decode_signed(&rlp::Rlp::new(&raw))
+            .map_err(|e| SyncError::Signer(format!("Could not decode the signed transaction: {}", e)))?;
+        // The signature is only any use to us if it is over the transaction we asked for
+        signature.verify(tx.sighash(), self.address).map_err(|_| {
+            SyncError::Signer("The remote signer signed a different transaction".to_string())
+        })?;
+        Ok(signature)
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(&self, _payload: &T) -> Result<Signature, SyncError> {
+        Err(SyncError::Signer(
+            "The remote signer doesn't support typed data".to_string(),
+        ))
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    fn with_chain_id<T: Into<u64>>(mut self, chain_id: T) -> Self {
+        self.chain_id = chain_id.into();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{TransactionRequest, U256};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Serve `eth_signTransaction`, signing with `wallet`. If `tamper` is set, sign a different nonce
+    async fn serve(wallet: LocalWallet, tamper: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let wallet = wallet.clone();
+                tokio::spawn(async move {
+                    // Read until we have the headers and the whole body
+                    let mut request = Vec::new();
+                    let mut buf = [0; 4096];
+                    let body = loop {
+                        let n = socket.read(&mut buf).await.unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                        let text = String::from_utf8_lossy(&request).to_string();
+                        if let Some(end) = text.find("\r\n\r\n") {
+                            let length: usize = text
+                                .lines()
+                                .find_map(|line| line.to_lowercase().strip_prefix("content-length: ").map(String::from))
+                                .and_then(|length| length.trim().parse().ok())
+                                .unwrap_or(0);
+                            if request.len() >= end + 4 + length {
+                                break text[end + 4..end + 4 + length].to_string();
+                            }
+                        }
+                    };
+                    let request: Value = serde_json::from_str(&body).unwrap();
+                    let mut tx: TypedTransaction = serde_json::from_value(request["params"][0].clone()).unwrap();
+                    if tamper {
+                        tx.set_nonce(U256::from(99));
+                    }
+                    let signature = wallet.sign_transaction(&tx).await.unwrap();
+                    let response = serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "result": { "raw": tx.rlp_signed(&signature), "tx": {} },
+                    })
+                    .to_string();
+                    let head = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        response.len()
+                    );
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        url
+    }
+
+    fn transaction() -> TypedTransaction {
+        TransactionRequest::new()
+            .to(Address::zero())
+            .value(1)
+            .nonce(1)
+            .gas(21000)
+            .gas_price(1)
+            .into()
+    }
+
+    #[tokio::test]
+    /// A remote signature is as good as a local one
+    async fn test_remote_signer() {
+        let wallet = LocalWallet::new(&mut rand::thread_rng()).with_chain_id(31337u64);
+        let url = serve(wallet.clone(), false).await;
+        let signer = SyncSigner::from(RemoteSigner::new(&url, wallet.address(), 31337).unwrap());
+        assert_eq!(signer.address(), wallet.address());
+        let mut tx = transaction();
+        tx.set_chain_id(31337u64);
+        let signature = signer.sign_transaction(&tx).await.unwrap();
+        assert_eq!(signature, wallet.sign_transaction(&tx).await.unwrap());
+    }
+
+    #[tokio::test]
+    /// A signature over some other transaction is refused
+    async fn test_remote_signer_tampered() {
+        let wallet = LocalWallet::new(&mut rand::thread_rng()).with_chain_id(31337u64);
+        let url = serve(wallet.clone(), true).await;
+        let signer = RemoteSigner::new(&url, wallet.address(), 31337).unwrap();
+        let err = signer.sign_transaction(&transaction()).await.unwrap_err();
+        assert!(matches!(err, SyncError::Signer(_)));
+    }
+}
@@ -1,79 +1,605 @@
-use crate::types::{cid::Cid, crud_file::CrudFile, metadata::Metadata};
-use anyhow::{anyhow, Error, Result};
-// use ethers::{prelude::*, utils};
-use std::path::PathBuf;
+use crate::types::{
+    cid::{Cid, CidMode},
+    crud_file::CrudFile,
+    metadata::{Metadata, MetadataPolicy, ALIASES, MOVED_TO},
+};
+use crate::utils::{front_matter, hash::hash_path, path::ContentRoot};
+use crate::config::Config;
+use crate::error::{Result, SyncError};
+use ethers::types::U256;
+use std::path::{Path, PathBuf};
 
-use super::backend::BackendClient;
+use super::backend::{Backend, BackendClient, ReadOnlyBackend};
 use super::store::StoreClient;
 
+/// How many moves `resolve` follows before giving up, in case they loop
+const MAX_MOVES: usize = 8;
+
 /// A CRUD filesystem representation
 /// # Fields
 /// * `backend_client` - The backend client - this maintains FS state on a remote backend
 /// * `store_client` - The store client - this maintains FS state on a remote store
+/// * `strip_front_matter` - Whether markdown is uploaded without its front matter
+/// * `cid_mode` - How the Cids of uploaded files are computed
+/// * `metadata_policy` - What metadata has to satisfy before it is sent to the backend
+/// * `content_root` - What file paths are relative to, on chain
 pub struct CrudFs {
-    backend_client: BackendClient,
+    backend_client: Box<dyn Backend>,
     store_client: StoreClient,
+    strip_front_matter: bool,
+    cid_mode: CidMode,
+    metadata_policy: MetadataPolicy,
+    content_root: ContentRoot,
 }
 
-impl Default for CrudFs {
-    fn default() -> Self {
-        let contract_address = std::env::var("CRUDFS_CONTRACT_ADDRESS")
-            .map_err(|_| anyhow!("CRUDFS_CONTRACT_ADDRESS not set")).unwrap();
-        Self::new(contract_address)
+impl CrudFs {
+    /// New CrudFs for the contract at `CONTRACT_ADDRESS`
+    /// # Returns
+    /// * `Result<CrudFs, SyncError>` - A configuration error if the environment is incomplete
+    pub fn from_env() -> Result<Self, SyncError> {
+        Self::from_config(&Config::from_env()?)
+    }
+
+    /// New CrudFs for the configured contract and store
+    /// # Arguments
+    /// * `config` - The resolved configuration
+    /// # Returns
+    /// * `Result<CrudFs, SyncError>` - A configuration error if the configuration is incomplete
+    pub fn from_config(config: &Config) -> Result<Self, SyncError> {
+        let backend_client = BackendClient::from_config(config)?;
+        let store_client = StoreClient::from_config(&config.store)?;
+        Ok(Self::with_clients(Box::new(backend_client), store_client)
+            .with_strip_front_matter(config.strip_front_matter)
+            .with_cid_mode(config.store.cid_mode)
+            .with_metadata_policy(MetadataPolicy::from_config(config)?)
+            .with_content_root(ContentRoot::from_config(config)))
+    }
+
+    /// New CrudFs that can only read, for the configured contract and store. No signer is needed,
+    /// and writes fail with `SyncError::ReadOnly`
+    /// # Arguments
+    /// * `config` - The resolved configuration
+    /// # Returns
+    /// * `Result<CrudFs, SyncError>` - A configuration error if the configuration is incomplete
+    pub fn read_only(config: &Config) -> Result<Self, SyncError> {
+        let backend_client = ReadOnlyBackend::from_config(config)?;
+        let store_client = StoreClient::for_reading(&config.store)?;
+        Ok(Self::with_clients(Box::new(backend_client), store_client)
+            .with_strip_front_matter(config.strip_front_matter)
+            .with_cid_mode(config.store.cid_mode)
+            .with_content_root(ContentRoot::from_config(config)))
     }
-}
 
-impl CrudFs {
     /// New CrudFs
     /// # Arguments
     /// * `contract_address` - The address of the contract that serves as our CrudFs backend
+    /// # Returns
+    /// * `Result<CrudFs, SyncError>` - A configuration error if the environment is incomplete
     pub fn new(
         contract_address: String,
-    ) -> Self {
-        let backend_client = BackendClient::new(contract_address);
-        let store_client = StoreClient::default();
+    ) -> Result<Self, SyncError> {
+        let backend_client = BackendClient::new(contract_address)?;
+        let store_client = StoreClient::from_env()?;
+        Ok(Self::with_clients(Box::new(backend_client), store_client))
+    }
+
+    /// New CrudFs over any Backend and store
+    /// # Arguments
+    /// * `backend_client` - The backend that maintains FS state
+    /// * `store_client` - The store that holds file content
+    pub fn with_clients(backend_client: Box<dyn Backend>, store_client: StoreClient) -> Self {
         Self {
             backend_client,
             store_client,
+            strip_front_matter: false,
+            cid_mode: CidMode::default(),
+            metadata_policy: MetadataPolicy::default(),
+            content_root: ContentRoot::default(),
         }
     }
+
+    /// Key files by their path from a content root
+    /// # Arguments
+    /// * `content_root` - What file paths are relative to, on chain
+    pub fn with_content_root(mut self, content_root: ContentRoot) -> Self {
+        self.content_root = content_root;
+        self
+    }
+
+    /// What file paths are relative to, on chain
+    pub fn content_root(&self) -> &ContentRoot {
+        &self.content_root
+    }
+
+    /// Check metadata against a policy before sending it
+    /// # Arguments
+    /// * `metadata_policy` - What metadata has to satisfy
+    pub fn with_metadata_policy(mut self, metadata_policy: MetadataPolicy) -> Self {
+        self.metadata_policy = metadata_policy;
+        self
+    }
+
+    /// Upload markdown without its front matter
+    /// # Arguments
+    /// * `strip_front_matter` - Whether to drop the front matter of markdown files
+    pub fn with_strip_front_matter(mut self, strip_front_matter: bool) -> Self {
+        self.strip_front_matter = strip_front_matter;
+        self
+    }
+
+    /// Compute Cids some other way than `ipfs add` does, e.g. as raw blocks
+    /// # Arguments
+    /// * `cid_mode` - How the Cids of uploaded files are computed
+    pub fn with_cid_mode(mut self, cid_mode: CidMode) -> Self {
+        self.cid_mode = cid_mode;
+        self
+    }
+
+    /// The Cid of a file as this CrudFs uploads it
+    /// # Arguments
+    /// * `path` - The path to the file
+    /// # Returns
+    /// * `Result<Cid, SyncError>` - The Cid of the content we upload
+    pub fn cid(&self, path: &Path) -> Result<Cid, SyncError> {
+        front_matter::cid(path, self.strip_front_matter, self.cid_mode)
+    }
+
+    /// Put a file's content into the store. Stripped markdown is staged in a temporary
+    /// directory under its own filename, since stores upload from a path
+    /// # Arguments
+    /// * `crud_file` - The CrudFile to put into the store
+    async fn put(&self, crud_file: &CrudFile) -> Result<(), SyncError> {
+        let local = self.content_root.local(&crud_file.path);
+        if !(self.strip_front_matter && front_matter::is_markdown(&local)) {
+            let mut crud_file = crud_file.clone();
+            crud_file.path = local;
+            self.store_client.put(crud_file).await?;
+            return Ok(());
+        }
+        let data = front_matter::content(&local, true)?;
+        // The stage is removed when it is dropped
+        let stage = tempfile::tempdir()?;
+        let mut staged = crud_file.clone();
+        staged.path = stage.path().join(&crud_file.filename);
+        std::fs::write(&staged.path, data)?;
+        self.store_client.put(staged).await?;
+        Ok(())
+    }
     // C is for Create
     /// Create a CrudFile in the backend and store
     /// # Arguments
-    /// * `path: PathBuf` - The path to the file
+    /// * `path: PathBuf` - The path to the file on disk. It is stored relative to the content root
     /// * `cid: Cid` - The Cid of the file
     /// * `metadata: Metadata` - The metadata of the file
     /// # Returns
-    /// * `Result<CrudFile, Error>` - The result of the operation
-    pub async fn create(&self, path: PathBuf, cid: Cid, metadata: Metadata) -> Result<CrudFile, Error> {
+    /// * `Result<CrudFile, SyncError>` - The result of the operation
+    pub async fn create(&self, path: PathBuf, cid: Cid, metadata: Metadata) -> Result<CrudFile, SyncError> {
+        let path = self.content_root.relative(&path)?;
+        self.metadata_policy.check(&path, &metadata)?;
         let crud_file = self.backend_client.create(path, cid, metadata).await?;
-        let _ = self.store_client.put(crud_file.clone()).await?;
-        Ok(crud_file.clone())
+        self.put(&crud_file).await?;
+        Ok(crud_file)
     }
 
     // R is for Read
     /// Read a file from the backend and store
+    /// The downloaded content is checked against the Cid recorded in the backend
     /// # Arguments
-    /// * `path` - The path to the file
+    /// * `path` - The path to the file on disk
+    /// # Returns
+    /// * `Result<(CrudFile, Vec<u8>), SyncError>` - The CrudFile and its content
+    pub async fn read(&self, path: PathBuf) -> Result<(CrudFile, Vec<u8>), SyncError> {
+        let key = hash_path(&self.content_root.relative(&path)?)?;
+        let crud_file = self.resolve(key).await?;
+        let data = self.fetch(crud_file.cid.clone()).await?;
+        Ok((crud_file, data))
+    }
+
+    /// Read a file from the backend, and stream its content from the store to a path on disk.
+    /// The content goes to a temp file next to `output`, and only replaces it once it matches the Cid
+    /// # Arguments
+    /// * `path` - The path to the file on disk
+    /// * `output` - Where to write the content
     /// # Returns
-    /// * `Result<CrudFile, Error>` - The result of the operation
-    pub async fn read(&self, path: PathBuf) -> Result<CrudFile, Error> {
-        todo!()
+    /// * `Result<CrudFile, SyncError>` - The CrudFile, or an IntegrityError if the content doesn't match
+    pub async fn read_to(&self, path: PathBuf, output: PathBuf) -> Result<CrudFile, SyncError> {
+        let key = hash_path(&self.content_root.relative(&path)?)?;
+        let crud_file = self.resolve(key).await?;
+        self.download(crud_file.cid.clone(), output).await?;
+        Ok(crud_file)
+    }
+
+    /// Read a file from the backend, following it to wherever it was moved
+    /// # Arguments
+    /// * `key` - The key of the file
+    /// # Returns
+    /// * `Result<CrudFile, SyncError>` - The CrudFile at the end of its moves. A path error if they loop
+    pub async fn resolve(&self, key: [u8; 32]) -> Result<CrudFile, SyncError> {
+        let mut crud_file = self.backend_client.read(key).await?;
+        for _ in 0..MAX_MOVES {
+            match crud_file.moved_to() {
+                Some(path) => crud_file = self.backend_client.read(hash_path(&path)?).await?,
+                None => return Ok(crud_file),
+            }
+        }
+        Err(SyncError::Path(format!(
+            "{} was moved more than {} times; do its moves loop?",
+            crud_file.path.display(),
+            MAX_MOVES
+        )))
+    }
+
+    /// List every CrudFile in the backend
+    /// # Returns
+    /// * `Result<Vec<CrudFile>, SyncError>` - The result of the operation
+    pub async fn list(&self) -> Result<Vec<CrudFile>, SyncError> {
+        self.backend_client.list().await
+    }
+
+    /// Fetch content from the store, checking it against its Cid
+    /// # Arguments
+    /// * `cid` - The Cid of the content
+    /// # Returns
+    /// * `Result<Vec<u8>, SyncError>` - The content, or an IntegrityError if it doesn't match
+    pub async fn fetch(&self, cid: Cid) -> Result<Vec<u8>, SyncError> {
+        self.store_client.cat(cid).await
+    }
+
+    /// Stream content from the store to a path on disk, checking it against its Cid on the way.
+    /// Unlike `fetch`, the content is never held in memory
+    /// # Arguments
+    /// * `cid` - The Cid of the content
+    /// * `output` - Where to write the content. Only replaced once the content is verified
+    /// # Returns
+    /// * `Result<(), SyncError>` - An IntegrityError if the content doesn't match
+    pub async fn download(&self, cid: Cid, output: PathBuf) -> Result<(), SyncError> {
+        self.store_client.get(cid, output).await?;
+        Ok(())
     }
 
     // U is for Update
     /// Update a file in the backend, store, and local
+    /// The Cid is recomputed from the file `crud_file.path` points at under the content root
     /// # Arguments
-    /// * `crud_file` - The CrudFile to update
-    pub async fn update(&self, crud_file: CrudFile) -> Result<(), Error> {
-        todo!()
+    /// * `crud_file` - The CrudFile to update, carrying the metadata to set
+    /// # Returns
+    /// * `Result<CrudFile, SyncError>` - The updated CrudFile, with its new Cid and timestamp
+    pub async fn update(&self, crud_file: CrudFile) -> Result<CrudFile, SyncError> {
+        let mut crud_file = crud_file;
+        self.metadata_policy.check(&crud_file.path, &crud_file.metadata)?;
+        // Re-hash the file to get its new Cid
+        crud_file.cid = self.cid(&self.content_root.local(&crud_file.path))?;
+        // Upload the new content before pointing the backend at it
+        self.put(&crud_file).await?;
+        let (_, timestamp) = self
+            .backend_client
+            .update(crud_file.key, crud_file.cid.clone(), crud_file.metadata.clone())
+            .await?;
+        crud_file.set_timestamp(timestamp)?;
+        Ok(crud_file)
     }
 
     // D is for Delete
-    /// Delete a file from the backend, store, and local
+    /// Delete a file from the backend. Its content stays pinned; see `unpin`
     /// # Arguments
-    /// * `path` - The path to the file
-    pub async fn delete(&self, path: PathBuf) -> Result<(), Error> {
-        todo!()
+    /// * `path` - The path to the file on disk
+    /// # Returns
+    /// * `Result<CrudFile, SyncError>` - The CrudFile as it was before deletion
+    pub async fn delete(&self, path: PathBuf) -> Result<CrudFile, SyncError> {
+        self.delete_key(hash_path(&self.content_root.relative(&path)?)?).await
+    }
+
+    /// Delete a file from the backend by its key, e.g. a tracked file's, which may predate path normalization
+    /// # Arguments
+    /// * `key` - The key of the file
+    /// # Returns
+    /// * `Result<CrudFile, SyncError>` - The CrudFile as it was before deletion
+    pub async fn delete_key(&self, key: [u8; 32]) -> Result<CrudFile, SyncError> {
+        // Read the file first so callers know which Cid to unpin
+        let crud_file = self.backend_client.read(key).await?;
+        self.backend_client.delete(key).await?;
+        Ok(crud_file)
+    }
+
+    /// Unpin content from the store, e.g. once the file it belonged to is deleted
+    /// # Arguments
+    /// * `cid` - The Cid of the content
+    /// # Returns
+    /// * `Result<(), SyncError>` - The result of the operation
+    pub async fn unpin(&self, cid: Cid) -> Result<(), SyncError> {
+        self.store_client.unpin(cid).await
+    }
+
+    // M is for Move
+    /// Move a file to a new path, keeping its identity. The file is created at the new path with
+    /// the old one added to its `aliases`, then the entry at the old path is replaced by a
+    /// `moved_to` redirect, which keeps its Cid so old links still resolve. Moving back onto an
+    /// old path revives the redirect there. These are two transactions, so a move that is
+    /// interrupted between them can be run again to finish it
+    /// # Arguments
+    /// * `from` - The path the file was at, on disk
+    /// * `to` - The path the file is at now, on disk
+    /// # Returns
+    /// * `Result<CrudFile, SyncError>` - The CrudFile at the new path
+    pub async fn rename(&self, from: PathBuf, to: PathBuf) -> Result<CrudFile, SyncError> {
+        let local = to;
+        let (from, to) = (self.content_root.relative(&from)?, self.content_root.relative(&local)?);
+        if from == to {
+            return Err(SyncError::Path(format!("{} is already at {}", from.display(), to.display())));
+        }
+        let old = self.backend_client.read(hash_path(&from)?).await?;
+        match old.moved_to() {
+            // Both transactions went through already
+            Some(moved_to) if moved_to == to => return self.backend_client.read(hash_path(&to)?).await,
+            Some(moved_to) => {
+                return Err(SyncError::Path(format!("{} was moved to {}", from.display(), moved_to.display())))
+            }
+            None => {}
+        }
+        let metadata = aliased(&old, &from)?;
+        self.metadata_policy.check(&to, &metadata)?;
+        let cid = self.cid(&local)?;
+        let crud_file = match self.backend_client.read(hash_path(&to)?).await {
+            // The file was created at its new path, but the old path wasn't redirected
+            Ok(existing) if existing.aliases().contains(&from) => existing,
+            // Moving back onto a path the file, or another, was moved away from
+            Ok(mut existing) if existing.moved_to().is_some() => {
+                existing.cid = cid;
+                existing.metadata = metadata;
+                if existing.cid != old.cid {
+                    self.put(&existing).await?;
+                }
+                let (_, timestamp) = self
+                    .backend_client
+                    .update(existing.key, existing.cid.clone(), existing.metadata.clone())
+                    .await?;
+                existing.set_timestamp(timestamp)?;
+                existing
+            }
+            Ok(_) => return Err(SyncError::Revert("File already exists.".to_string())),
+            Err(SyncError::Revert(_)) => {
+                let crud_file = self.backend_client.create(to.clone(), cid, metadata).await?;
+                // A pure move needs no upload; the content is already in the store
+                if crud_file.cid != old.cid {
+                    self.put(&crud_file).await?;
+                }
+                crud_file
+            }
+            Err(e) => return Err(e),
+        };
+        self.backend_client.update(old.key, old.cid, redirect(&to)).await?;
+        Ok(crud_file)
+    }
+
+    /// Estimate the gas needed to move a file
+    /// # Arguments
+    /// * `from` - The path the file was at, on disk
+    /// * `to` - The path the file is at now, on disk
+    /// # Returns
+    /// * `Result<U256, SyncError>` - The estimated gas of both transactions
+    pub async fn estimate_rename(&self, from: PathBuf, to: PathBuf) -> Result<U256, SyncError> {
+        let from = self.content_root.relative(&from)?;
+        let old = self.backend_client.read(hash_path(&from)?).await?;
+        let cid = self.cid(&to)?;
+        let redirect = redirect(&self.content_root.relative(&to)?);
+        let create = self.estimate_create(to, cid, aliased(&old, &from)?).await?;
+        let update = self.backend_client.estimate_update(old.key, old.cid, redirect).await?;
+        Ok(create + update)
+    }
+
+    /// Estimate the gas needed to create a file
+    /// # Arguments
+    /// * `path` - The path to the file on disk
+    /// * `cid` - The Cid of the file
+    /// * `metadata` - The metadata of the file
+    /// # Returns
+    /// * `Result<U256, SyncError>` - The estimated gas
+    pub async fn estimate_create(&self, path: PathBuf, cid: Cid, metadata: Metadata) -> Result<U256, SyncError> {
+        let path = self.content_root.relative(&path)?;
+        self.metadata_policy.check(&path, &metadata)?;
+        self.backend_client.estimate_create(path, cid, metadata).await
+    }
+
+    /// Estimate the gas needed to update a file
+    /// # Arguments
+    /// * `crud_file` - The CrudFile to update
+    /// * `cid` - The new Cid of the file
+    /// # Returns
+    /// * `Result<U256, SyncError>` - The estimated gas
+    pub async fn estimate_update(&self, crud_file: &CrudFile, cid: Cid) -> Result<U256, SyncError> {
+        self.metadata_policy.check(&crud_file.path, &crud_file.metadata)?;
+        self.backend_client
+            .estimate_update(crud_file.key, cid, crud_file.metadata.clone())
+            .await
+    }
+
+    /// Estimate the gas needed to delete a file
+    /// # Arguments
+    /// * `path` - The path to the file on disk
+    /// # Returns
+    /// * `Result<U256, SyncError>` - The estimated gas
+    pub async fn estimate_delete(&self, path: PathBuf) -> Result<U256, SyncError> {
+        let key = hash_path(&self.content_root.relative(&path)?)?;
+        self.backend_client.estimate_delete(key).await
+    }
+
+    /// Get the current gas price of the backend
+    /// # Returns
+    /// * `Result<U256, SyncError>` - The gas price in wei
+    pub async fn gas_price(&self) -> Result<U256, SyncError> {
+        self.backend_client.gas_price().await
+    }
+}
+
+/// A file's metadata once it is moved: the same, with the path it is moved from added to its aliases
+fn aliased(crud_file: &CrudFile, from: &Path) -> Result<Metadata, SyncError> {
+    let mut aliases = crud_file.aliases();
+    aliases.push(from.to_path_buf());
+    let mut metadata = crud_file.metadata.clone();
+    metadata.remove(MOVED_TO);
+    metadata.insert(ALIASES.to_string(), serde_json::to_string(&aliases)?);
+    Ok(metadata)
+}
+
+/// The metadata left at a file's old path, pointing at its new one
+fn redirect(to: &Path) -> Metadata {
+    let mut metadata = Metadata::new();
+    metadata.insert(MOVED_TO.to_string(), to.to_string_lossy().to_string());
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crud_fs::{backend::InMemoryBackend, store::LocalStore};
+
+    #[tokio::test]
+    /// Run the CRUD cycle against an in-memory backend and a local store
+    async fn test_crud() {
+        let temp = tempfile::tempdir().unwrap();
+        let test_dir = temp.path();
+        let crud_fs = CrudFs::with_clients(
+            Box::new(InMemoryBackend::new()),
+            StoreClient::new(Box::new(LocalStore::new(test_dir.join("store")))),
+        );
+        let path = test_dir.join("hello.md");
+
+        // C is for create
+        std::fs::write(&path, "hello").unwrap();
+        let cid = Cid::try_from(&path).unwrap();
+        let crud_file = crud_fs.create(path.clone(), cid.clone(), Metadata::new()).await.unwrap();
+        assert_eq!(crud_file.cid, cid);
+
+        // R is for read
+        let (read, data) = crud_fs.read(path.clone()).await.unwrap();
+        assert_eq!(read.cid, cid);
+        assert_eq!(data, b"hello");
+
+        // U is for update
+        std::fs::write(&path, "hello again").unwrap();
+        let updated = crud_fs.update(crud_file).await.unwrap();
+        assert_ne!(updated.cid, cid);
+        let (_, data) = crud_fs.read(path.clone()).await.unwrap();
+        assert_eq!(data, b"hello again");
+        let output = test_dir.join("hello-copy.md");
+        assert_eq!(crud_fs.read_to(path.clone(), output.clone()).await.unwrap().cid, updated.cid);
+        assert_eq!(std::fs::read(&output).unwrap(), b"hello again");
+
+        // D is for delete
+        let deleted = crud_fs.delete(path.clone()).await.unwrap();
+        crud_fs.unpin(deleted.cid.clone()).await.unwrap();
+        assert_eq!(deleted.cid, updated.cid);
+        assert!(crud_fs.read(path).await.is_err());
+        assert!(crud_fs.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    /// Stripped markdown is stored without its front matter, under the Cid of what was stored
+    async fn test_strip_front_matter() {
+        let temp = tempfile::tempdir().unwrap();
+        let test_dir = temp.path();
+        let crud_fs = CrudFs::with_clients(
+            Box::new(InMemoryBackend::new()),
+            StoreClient::new(Box::new(LocalStore::new(test_dir.join("store")))),
+        )
+        .with_strip_front_matter(true);
+        let path = test_dir.join("post.md");
+        std::fs::write(&path, "---\ntitle: Hello\n---\nhello").unwrap();
+
+        let cid = crud_fs.cid(&path).unwrap();
+        assert_eq!(cid, Cid::from(b"hello".as_slice()));
+        crud_fs.create(path.clone(), cid.clone(), Metadata::new()).await.unwrap();
+        let (read, data) = crud_fs.read(path.clone()).await.unwrap();
+        assert_eq!(read.cid, cid);
+        assert_eq!(data, b"hello");
+        // The local file keeps its front matter
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with("---"));
+    }
+
+    #[tokio::test]
+    /// A moved file keeps its metadata and history, and its old path redirects to it
+    async fn test_rename() {
+        let temp = tempfile::tempdir().unwrap();
+        let test_dir = temp.path();
+        let content = test_dir.join("content");
+        std::fs::create_dir_all(content.join("posts")).unwrap();
+        let crud_fs = CrudFs::with_clients(
+            Box::new(InMemoryBackend::new()),
+            StoreClient::new(Box::new(LocalStore::new(test_dir.join("store")))),
+        )
+        .with_content_root(ContentRoot::new(Some(content.clone())));
+        let (from, to) = (content.join("hello.md"), content.join("posts/hello.md"));
+        std::fs::write(&from, "hello").unwrap();
+        let cid = crud_fs.cid(&from).unwrap();
+        let mut metadata = Metadata::new();
+        metadata.insert("title".to_string(), "Hello".to_string());
+        let created = crud_fs.create(from.clone(), cid.clone(), metadata).await.unwrap();
+
+        std::fs::rename(&from, &to).unwrap();
+        let moved = crud_fs.rename(from.clone(), to.clone()).await.unwrap();
+        assert_eq!(moved.path, PathBuf::from("posts/hello.md"));
+        assert_eq!(moved.cid, cid);
+        assert_eq!(moved.metadata["title"], "Hello");
+        assert_eq!(moved.aliases(), vec![PathBuf::from("hello.md")]);
+        let redirect = crud_fs.backend_client.read(created.key).await.unwrap();
+        assert_eq!(redirect.moved_to(), Some(PathBuf::from("posts/hello.md")));
+        assert_eq!(redirect.cid, cid);
+
+        // Reading the old path follows the move
+        let (read, data) = crud_fs.read(from.clone()).await.unwrap();
+        assert_eq!(read.key, moved.key);
+        assert_eq!(data, b"hello");
+
+        // Running a finished move again changes nothing, and a file can only move on from where it is
+        assert_eq!(crud_fs.rename(from.clone(), to.clone()).await.unwrap().key, moved.key);
+        let elsewhere = content.join("elsewhere.md");
+        assert!(matches!(crud_fs.rename(from.clone(), elsewhere).await, Err(SyncError::Path(_))));
+
+        // Moving back revives the old entry
+        std::fs::rename(&to, &from).unwrap();
+        let back = crud_fs.rename(to.clone(), from.clone()).await.unwrap();
+        assert_eq!(back.key, created.key);
+        assert_eq!(back.aliases(), vec![PathBuf::from("hello.md"), PathBuf::from("posts/hello.md")]);
+        assert_eq!(back.moved_to(), None);
+        assert_eq!(crud_fs.read(to.clone()).await.unwrap().0.key, created.key);
+
+        // Moves that loop are an error, rather than a hang
+        crud_fs.backend_client.update(created.key, cid, redirect.metadata).await.unwrap();
+        assert!(matches!(crud_fs.read(from).await, Err(SyncError::Path(_))));
+    }
+
+    #[tokio::test]
+    /// Invalid metadata is refused before anything is stored or sent to the backend
+    async fn test_metadata_policy() {
+        let temp = tempfile::tempdir().unwrap();
+        let test_dir = temp.path();
+        let crud_fs = CrudFs::with_clients(
+            Box::new(InMemoryBackend::new()),
+            StoreClient::new(Box::new(LocalStore::new(test_dir.join("store")))),
+        )
+        .with_metadata_policy(MetadataPolicy::new(64));
+        let path = test_dir.join("hello.md");
+        std::fs::write(&path, "hello").unwrap();
+        let cid = Cid::try_from(&path).unwrap();
+
+        let mut metadata = Metadata::new();
+        metadata.insert("title".to_string(), "a".repeat(64));
+        let err = crud_fs.create(path.clone(), cid.clone(), metadata.clone()).await.unwrap_err();
+        assert!(matches!(err, SyncError::Metadata(_)));
+        assert!(crud_fs.estimate_create(path.clone(), cid.clone(), metadata).await.is_err());
+        let mut metadata = Metadata::new();
+        metadata.insert("date".to_string(), "yesterday".to_string());
+        let err = crud_fs.create(path.clone(), cid.clone(), metadata).await.unwrap_err();
+        assert!(matches!(err, SyncError::Metadata(_)));
+        assert!(crud_fs.list().await.unwrap().is_empty());
+        assert!(!test_dir.join("store").exists() || std::fs::read_dir(test_dir.join("store")).unwrap().next().is_none());
+
+        // Updates are checked too, and leave the file as it was
+        let mut crud_file = crud_fs.create(path.clone(), cid.clone(), Metadata::new()).await.unwrap();
+        crud_file.metadata.insert("draft".to_string(), "maybe".to_string());
+        let err = crud_fs.update(crud_file).await.unwrap_err();
+        assert!(matches!(err, SyncError::Metadata(_)));
+        let (read, _) = crud_fs.read(path).await.unwrap();
+        assert!(read.metadata.is_empty());
     }
 }
@@ -1,3 +1,4 @@
-mod backend;
+pub mod backend;
+#[allow(clippy::module_inception)]
 pub mod crud_fs;
-mod store;
+pub mod store;
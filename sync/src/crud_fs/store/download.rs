@@ -0,0 +1,297 @@
+/* Verified Downloads */
+
+use crate::error::{Result, SyncError};
+use crate::types::cid::Cid;
+use rand::Rng;
+use std::{
+    fmt,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::File,
+    io::AsyncWriteExt,
+    sync::mpsc::{self, Receiver, Sender},
+    task::JoinHandle,
+};
+
+/// How many chunks can be in flight to the hasher
+const CHANNEL_SIZE: usize = 16;
+
+/// Content did not hash to the CID it was requested by
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntegrityError {
+    /// The CID that was requested
+    pub expected: Cid,
+    /// The CID of what we actually got
+    pub actual: Cid,
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Content does not match CID: expected {}, got {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+/// Download - Streams content to a temp file while hashing it, and only
+/// moves it into place if it matches the CID it was requested by
+pub struct Download {
+    /// The CID we expect
+    cid: Cid,
+    /// Where the content ends up
+    path: PathBuf,
+    /// Where the content is written until it is verified
+    temp_path: PathBuf,
+    temp_file: File,
+    /// Hashes the content as it arrives. Taken by `check`
+    hasher: Option<Hasher>,
+    /// Whether the content has been checked against the CID
+    verified: bool,
+}
+
+/// Hashes content on a blocking thread as it is fed chunks
+struct Hasher {
+    sender: Sender<Vec<u8>>,
+    handle: JoinHandle<Result<Cid, SyncError>>,
+}
+
+impl Hasher {
+    /// Start hashing, with the mode the CID was most likely made with
+    fn start(cid: &Cid) -> Self {
+        let mode = cid.modes()[0];
+        let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+        let handle = tokio::task::spawn_blocking(move || {
+            Cid::from_reader(ChannelReader::new(receiver), mode)
+        });
+        Self { sender, handle }
+    }
+}
+
+impl Download {
+    /// Start a Download
+    /// # Arguments
+    /// * `cid` - The CID of the content
+    /// * `path` - Where to put the content once it is verified
+    /// # Returns
+    /// * `Result<Download, SyncError>` - Errors if the temp file can't be created
+    pub async fn new(cid: Cid, path: PathBuf) -> Result<Self, SyncError> {
+        // Keep the temp file next to the target, so the rename can't cross filesystems
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let temp_path = path.with_file_name(format!(
+            ".{}.{:x}.part",
+            filename,
+            rand::thread_rng().gen::<u64>()
+        ));
+        let temp_file = File::create(&temp_path).await?;
+        let hasher = Some(Hasher::start(&cid));
+        Ok(Self {
+            cid,
+            path,
+            temp_path,
+            temp_file,
+            hasher,
+            verified: false,
+        })
+    }
+
+    /// Write the next chunk of content
+    /// # Arguments
+    /// * `chunk` - The chunk
+    /// # Returns
+    /// * `Result<(), SyncError>` - Errors if the chunk can't be written, or the content was already checked
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), SyncError> {
+        let hasher = self.hasher.as_ref().ok_or_else(Self::checked)?;
+        self.temp_file.write_all(chunk).await?;
+        // The hasher only stops early if it failed, which `check` reports
+        let _ = hasher.sender.send(chunk.to_vec()).await;
+        Ok(())
+    }
+
+    /// Check the content written so far against the CID, without moving it into place.
+    /// Once checked, nothing more can be written until the Download is `reset`
+    /// # Returns
+    /// * `Result<(), SyncError>` - An IntegrityError if the content doesn't match the CID
+    pub async fn check(&mut self) -> Result<(), SyncError> {
+        if self.verified {
+            return Ok(());
+        }
+        let Hasher { sender, handle } = self.hasher.take().ok_or_else(Self::checked)?;
+        self.temp_file.flush().await?;
+        drop(sender);
+        let actual = handle
+            .await
+            .map_err(|e| SyncError::Io(std::io::Error::other(e)))??;
+        if actual != self.cid && !Self::matches_other_modes(&self.cid, &self.temp_path).await? {
+            return Err(SyncError::from(IntegrityError {
+                expected: self.cid.clone(),
+                actual,
+            }));
+        }
+        self.verified = true;
+        Ok(())
+    }
+
+    /// Throw away what was written, e.g. after a source failed part way, and start over
+    /// # Returns
+    /// * `Result<(), SyncError>` - Errors if the temp file can't be truncated
+    pub async fn reset(&mut self) -> Result<(), SyncError> {
+        self.temp_file = File::create(&self.temp_path).await?;
+        self.hasher = Some(Hasher::start(&self.cid));
+        self.verified = false;
+        Ok(())
+    }
+
+    /// Verify the content, unless it was already checked, and move it into place
+    /// # Returns
+    /// * `Result<(), SyncError>` - An IntegrityError if the content doesn't match the CID
+    pub async fn finish(mut self) -> Result<(), SyncError> {
+        let result = async {
+            self.check().await?;
+            self.temp_file.sync_all().await?;
+            tokio::fs::rename(&self.temp_path, &self.path).await?;
+            Ok(())
+        }
+        .await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&self.temp_path).await;
+        }
+        result
+    }
+
+    /// The error for writing to, or checking, a Download that was already checked
+    fn checked() -> SyncError {
+        SyncError::Io(std::io::Error::other("Download was already checked; reset it first"))
+    }
+
+    /// Give up on the Download, and remove the temp file
+    pub async fn abort(self) {
+        drop(self.temp_file);
+        let _ = tokio::fs::remove_file(&self.temp_path).await;
+    }
+
+    /// Check the rest of the modes the CID could have been made with, from the temp file
+    async fn matches_other_modes(cid: &Cid, temp_path: &Path) -> Result<bool, SyncError> {
+        for mode in cid.modes().into_iter().skip(1) {
+            let file = std::fs::File::open(temp_path)?;
+            let cid = cid.clone();
+            let matches = tokio::task::spawn_blocking(move || {
+                Cid::from_reader(file, mode).map(|actual| actual == cid)
+            })
+            .await
+            .map_err(|e| SyncError::Io(std::io::Error::other(e)))??;
+            if matches {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Reads chunks from a channel, so the hasher can run on a blocking thread
+struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    fn new(receiver: Receiver<Vec<u8>>) -> Self {
+        Self {
+            receiver,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                // The sender is gone: that's all the content
+                None => return Ok(0),
+            }
+        }
+        let count = buf.len().min(self.chunk.len() - self.pos);
+        buf[..count].copy_from_slice(&self.chunk[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::cid::CidMode;
+    use crate::types::unixfs::UnixFsOptions;
+
+    #[tokio::test]
+    /// Content that matches is moved into place, in whichever mode its CID was made
+    async fn test_verified() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..600_000u32).map(|i| (i % 251) as u8).collect();
+        let pb_leaves = CidMode::UnixFs(UnixFsOptions { raw_leaves: false, ..Default::default() });
+        for mode in [CidMode::default(), pb_leaves, CidMode::Raw] {
+            let cid = Cid::from_reader(data.as_slice(), mode).unwrap();
+            let path = dir.path().join("file.bin");
+            let mut download = Download::new(cid, path.clone()).await.unwrap();
+            for chunk in data.chunks(10_000) {
+                download.write(chunk).await.unwrap();
+            }
+            download.finish().await.unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), data);
+        }
+        // No temp files left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    /// Content that doesn't match is an IntegrityError, and never lands at the path
+    async fn test_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        let cid = Cid::from(b"hello".as_slice());
+        let mut download = Download::new(cid.clone(), path.clone()).await.unwrap();
+        download.write(b"jello").await.unwrap();
+        let err = match download.finish().await {
+            Err(SyncError::Integrity(err)) => err,
+            other => panic!("Expected an IntegrityError, got {:?}", other),
+        };
+        assert_eq!(err.expected, cid);
+        assert_eq!(err.actual, Cid::from(b"jello".as_slice()));
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    /// A Download that failed its check can be reset and written again, e.g. from another source
+    async fn test_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        let cid = Cid::from(b"hello".as_slice());
+        let mut download = Download::new(cid, path.clone()).await.unwrap();
+        download.write(b"jel").await.unwrap();
+        download.write(b"lo").await.unwrap();
+        assert!(matches!(download.check().await, Err(SyncError::Integrity(_))));
+        assert!(download.write(b"hello").await.is_err());
+        download.reset().await.unwrap();
+        download.write(b"hello").await.unwrap();
+        download.check().await.unwrap();
+        download.finish().await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,214 @@
+/* Estuary Client */
+
+use crate::types::{cid::Cid, crud_file::CrudFile};
+use crate::config::{Config, StoreConfig};
+use crate::error::{Result, SyncError};
+use async_trait::async_trait;
+use reqwest::{multipart, Body, Client, Response};
+use serde::Deserialize;
+use tokio::fs::File;
+use tokio_util::codec::{BytesCodec, FramedRead};
+
+use super::{Download, Store};
+
+/// EstuaryClient - A struct for managing Requests to an Estuary API
+pub struct EstuaryClient {
+    /// The Estuary API Hostname
+    pub estuary_api_hostname: String,
+    /// The Estuary API Key
+    pub estuary_api_key: String,
+    /// The Reqwest Client
+    reqwest_client: Client,
+}
+
+impl EstuaryClient {
+    /// Create a new EstuaryClient from `ESTUARY_API_URL` and `ESTUARY_API_KEY`
+    /// # Returns
+    /// * `Result<EstuaryClient, SyncError>` - A configuration error if the API key is missing
+    pub fn from_env() -> Result<Self, SyncError> {
+        Self::from_config(&Config::from_env()?.store)
+    }
+
+    /// Create a new EstuaryClient from the store configuration
+    /// # Arguments
+    /// * `store` - The store configuration
+    /// # Returns
+    /// * `Result<EstuaryClient, SyncError>` - A configuration error if the API URL or key is missing
+    pub fn from_config(store: &StoreConfig) -> Result<Self, SyncError> {
+        let estuary_api_url = Config::require(&store.estuary_api_url, "estuary_api_url", "ESTUARY_API_URL")?;
+        let estuary_api_key = Config::require(&store.estuary_api_key, "estuary_api_key", "ESTUARY_API_KEY")?;
+        Ok(Self::new(estuary_api_url, estuary_api_key))
+    }
+
+    /// Create a new EstuaryClient using custom values
+    /// # Arguments
+    /// * `estuary_api_hostname` - The Hostname of the Estuary API to use.
+    /// * `estuary_api_key` - The (optional) API Key to use for the Estuary API.
+    pub fn new(estuary_api_hostname: String, estuary_api_key: String) -> Self {
+        let reqwest_client = Client::new();
+        Self {
+            estuary_api_hostname,
+            estuary_api_key,
+            reqwest_client,
+        }
+    }
+}
+
+#[async_trait]
+impl Store for EstuaryClient {
+    /// Stage a File on Estuary
+    /// # Arguments
+    /// * `crud_file` - The CrudFile to stage on Estuary
+    /// # Returns
+    /// * `Result<Cid, SyncError>` - The CID Estuary reports for the file
+    async fn put(&self, crud_file: CrudFile) -> Result<Cid, SyncError> {
+        // Get the filename from the of the crud_file, and then open the file
+        let file = File::open(crud_file.path).await?;
+        // Read the file into a body stream
+        let file_body = Body::wrap_stream(FramedRead::new(file, BytesCodec::new()));
+        // Define a Form Part for the File
+        let some_file = multipart::Part::stream(file_body)
+            .file_name(crud_file.filename)
+            .mime_str("text/plain")?;
+
+        // Create the multipart form
+        let form = multipart::Form::new().part("data", some_file); //add the file part
+        let res = self
+            .reqwest_client
+            // POST to the /content/add endpoint
+            .post(format!("{}/content/add", self.estuary_api_hostname))
+            // Set the Authorization Header
+            .header("Authorization", format!("Bearer {}", self.estuary_api_key))
+            // Add the Form
+            .multipart(form)
+            // Send the Request
+            .send()
+            // Await the Response
+            .await?;
+        // Check the Status Code
+        if res.status().is_success() {
+            let content: AddResponse = res.json().await?;
+            Cid::from_str(content.cid)
+        } else {
+            Err(SyncError::StoreStatus {
+                url: res.url().to_string(),
+                status: res.status().as_u16(),
+            })
+        }
+    }
+
+    /// Stream the content of a CID from Estuary into a Download
+    /// # Arguments
+    /// * `cid` - The CID of the content to download
+    /// * `download` - Where to write the content
+    /// # Returns
+    /// * `Result<(), SyncError>` - Errors if the content could not be downloaded
+    async fn get_into(&self, cid: Cid, download: &mut Download) -> Result<(), SyncError> {
+        let mut res = self.get_response(&cid).await?;
+        while let Some(chunk) = res.chunk().await? {
+            download.write(&chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Check whether Estuary has a pin for a CID
+    /// # Arguments
+    /// * `cid` - The CID of the content
+    /// # Returns
+    /// * `Result<bool, SyncError>` - Whether the content is pinned
+    async fn has(&self, cid: Cid) -> Result<bool, SyncError> {
+        Ok(!self.pins(cid).await?.is_empty())
+    }
+
+    /// Unpin all pins of a CID on Estuary
+    /// Uses the IPFS Pinning Service API, since Estuary's content API is keyed by its own IDs
+    /// # Arguments
+    /// * `cid` - The CID of the content to unpin
+    /// # Returns
+    /// * `Result<(), SyncError>` - Errors if the pins could not be listed or removed
+    async fn unpin(&self, cid: Cid) -> Result<(), SyncError> {
+        // Remove each pin by its request ID
+        for pin in self.pins(cid).await? {
+            let res = self
+                .reqwest_client
+                .delete(format!(
+                    "{}/pinning/pins/{}",
+                    self.estuary_api_hostname, pin.requestid
+                ))
+                .header("Authorization", format!("Bearer {}", self.estuary_api_key))
+                .send()
+                .await?;
+            if !res.status().is_success() {
+                return Err(SyncError::StoreStatus {
+                url: res.url().to_string(),
+                status: res.status().as_u16(),
+            });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl EstuaryClient {
+    /// Request the content of a CID from the Estuary gateway
+    /// # Arguments
+    /// * `cid` - The CID of the content
+    /// # Returns
+    /// * `Result<Response, SyncError>` - The successful response, with the content as its body
+    async fn get_response(&self, cid: &Cid) -> Result<Response, SyncError> {
+        let res = self
+            .reqwest_client
+            .get(format!("{}/get/{}", self.estuary_api_hostname, cid))
+            .send()
+            .await?;
+        if res.status().is_success() {
+            Ok(res)
+        } else {
+            Err(SyncError::StoreStatus {
+                url: res.url().to_string(),
+                status: res.status().as_u16(),
+            })
+        }
+    }
+
+    /// List the pins for a CID through the Pinning Service API
+    /// # Arguments
+    /// * `cid` - The CID of the content
+    /// # Returns
+    /// * `Result<Vec<PinStatus>, SyncError>` - The pins for the CID
+    async fn pins(&self, cid: Cid) -> Result<Vec<PinStatus>, SyncError> {
+        let res = self
+            .reqwest_client
+            .get(format!("{}/pinning/pins", self.estuary_api_hostname))
+            .query(&[("cid", cid.to_string())])
+            .header("Authorization", format!("Bearer {}", self.estuary_api_key))
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            return Err(SyncError::StoreStatus {
+                url: res.url().to_string(),
+                status: res.status().as_u16(),
+            });
+        }
+        let pins: PinResults = res.json().await?;
+        Ok(pins.results)
+    }
+}
+
+/// The response from the `/content/add` endpoint. We only need the CID
+#[derive(Debug, Deserialize)]
+struct AddResponse {
+    cid: String,
+}
+
+/// A page of results from the Pinning Service API `GET /pins` endpoint
+#[derive(Debug, Deserialize)]
+struct PinResults {
+    results: Vec<PinStatus>,
+}
+
+/// A single pin from the Pinning Service API. We only need the request ID
+#[derive(Debug, Deserialize)]
+struct PinStatus {
+    requestid: String,
+}
@@ -0,0 +1,413 @@
+/* IPFS Gateways */
+
+use crate::config::StoreConfig;
+use crate::error::{Result, SyncError};
+use crate::types::cid::Cid;
+use reqwest::{Client, Response};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::task::JoinSet;
+
+use super::Download;
+
+/// How long to wait on one gateway before giving up on it
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to skip a gateway that keeps failing
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(300);
+/// How many failures in a row put a gateway in cool-down
+pub const DEFAULT_MAX_FAILURES: u32 = 3;
+
+/// How to spread a read across gateways
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewayStrategy {
+    /// Try each gateway in turn until one works
+    InOrder,
+    /// Ask every gateway at once, and take the first good response
+    Race,
+}
+
+impl FromStr for GatewayStrategy {
+    type Err = SyncError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "order" => Ok(GatewayStrategy::InOrder),
+            "race" => Ok(GatewayStrategy::Race),
+            other => Err(SyncError::Config(format!("Unknown gateway strategy: {}", other))),
+        }
+    }
+}
+
+impl fmt::Display for GatewayStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GatewayStrategy::InOrder => write!(f, "order"),
+            GatewayStrategy::Race => write!(f, "race"),
+        }
+    }
+}
+
+/// A gateway, and how it has been doing lately
+struct Gateway {
+    url: String,
+    health: Mutex<Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    /// Failures since the last success
+    failures: u32,
+    /// When the gateway can be tried again, if it is cooling down
+    skip_until: Option<Instant>,
+}
+
+/// GatewayClient - Reads content from a list of IPFS HTTP gateways, checking every response against its CID
+pub struct GatewayClient {
+    gateways: Vec<Arc<Gateway>>,
+    strategy: GatewayStrategy,
+    timeout: Duration,
+    cooldown: Duration,
+    max_failures: u32,
+    /// The Reqwest Client
+    reqwest_client: Client,
+}
+
+impl GatewayClient {
+    /// Create a new GatewayClient
+    /// # Arguments
+    /// * `urls` - The gateways to read from, e.g. `https://ipfs.io`. Tried in this order
+    /// * `strategy` - Whether to try the gateways in order or race them
+    pub fn new(urls: Vec<String>, strategy: GatewayStrategy) -> Self {
+        let gateways = urls
+            .into_iter()
+            .map(|url| {
+                Arc::new(Gateway {
+                    url: url.trim_end_matches('/').to_string(),
+                    health: Mutex::new(Health::default()),
+                })
+            })
+            .collect();
+        Self {
+            gateways,
+            strategy,
+            timeout: DEFAULT_TIMEOUT,
+            cooldown: DEFAULT_COOLDOWN,
+            max_failures: DEFAULT_MAX_FAILURES,
+            reqwest_client: Client::new(),
+        }
+    }
+
+    /// Create a GatewayClient from the configured gateways, strategy, timeout and cool-down
+    /// # Arguments
+    /// * `store` - The store configuration
+    /// # Returns
+    /// * `Option<GatewayClient>` - None if no gateways are configured
+    pub fn from_config(store: &StoreConfig) -> Option<Self> {
+        if store.gateways.is_empty() {
+            return None;
+        }
+        Some(
+            Self::new(store.gateways.clone(), store.gateway_strategy)
+                .with_timeout(Duration::from_secs(store.gateway_timeout))
+                .with_cooldown(Duration::from_secs(store.gateway_cooldown), DEFAULT_MAX_FAILURES),
+        )
+    }
+
+    /// Set how long to wait on each gateway
+    /// # Arguments
+    /// * `timeout` - The timeout for one request to one gateway
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set when and for how long failing gateways are skipped
+    /// # Arguments
+    /// * `cooldown` - How long to skip a gateway for
+    /// * `max_failures` - How many failures in a row start a cool-down
+    pub fn with_cooldown(mut self, cooldown: Duration, max_failures: u32) -> Self {
+        self.cooldown = cooldown;
+        self.max_failures = max_failures;
+        self
+    }
+
+    /// Stream the content of a CID from the gateways into a Download, checking it on the way.
+    /// A gateway that fails part way, or sends the wrong content, is thrown away and the next one tried
+    /// # Arguments
+    /// * `cid` - The CID of the content
+    /// * `download` - Where to write the content. Left checked if a gateway had it
+    /// # Returns
+    /// * `Result<(), SyncError>` - If no gateway had it, an IntegrityError if any of them sent the
+    ///   wrong content, otherwise the last error
+    pub async fn get_into(&self, cid: &Cid, download: &mut Download) -> Result<(), SyncError> {
+        let gateways = self.available();
+        let mut last_error = SyncError::Config("No gateways available: all are cooling down".to_string());
+        let mut integrity_error = None;
+        let mut fail = |e: SyncError| match e {
+            SyncError::Integrity(_) => integrity_error = Some(e),
+            e => last_error = e,
+        };
+        match self.strategy {
+            GatewayStrategy::InOrder => {
+                for gateway in gateways {
+                    let result = match self.request(&gateway, cid).await {
+                        Ok(res) => self.stream(&gateway, cid, res, download).await,
+                        Err(e) => Err(e),
+                    };
+                    match result {
+                        Ok(()) => return Ok(()),
+                        Err(e) => fail(e),
+                    }
+                }
+            }
+            GatewayStrategy::Race => {
+                // Race the requests, and stream from whichever gateway answers first
+                let mut tasks = JoinSet::new();
+                for gateway in gateways {
+                    let client = self.clone_for_task();
+                    let cid = cid.clone();
+                    tasks.spawn(async move {
+                        let res = client.request(&gateway, &cid).await;
+                        (gateway, res)
+                    });
+                }
+                while let Some(joined) = tasks.join_next().await {
+                    let (gateway, res) = joined.map_err(|e| SyncError::Io(std::io::Error::other(e)))?;
+                    let result = match res {
+                        Ok(res) => self.stream(&gateway, cid, res, download).await,
+                        Err(e) => Err(e),
+                    };
+                    match result {
+                        Ok(()) => {
+                            // Let the slower gateways finish rather than cancel them, so those that
+                            // fail or time out are still recorded. What they send is dropped
+                            tasks.detach_all();
+                            return Ok(());
+                        }
+                        Err(e) => fail(e),
+                    }
+                }
+            }
+        }
+        Err(integrity_error.unwrap_or(last_error))
+    }
+
+    /// The gateways that aren't cooling down, in order
+    fn available(&self) -> Vec<Arc<Gateway>> {
+        let now = Instant::now();
+        self.gateways
+            .iter()
+            .filter(|gateway| match gateway.health.lock().unwrap().skip_until {
+                Some(skip_until) => skip_until <= now,
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// A copy that shares the gateways and their health, to move into a task
+    fn clone_for_task(&self) -> Self {
+        Self {
+            gateways: self.gateways.clone(),
+            strategy: self.strategy,
+            timeout: self.timeout,
+            cooldown: self.cooldown,
+            max_failures: self.max_failures,
+            reqwest_client: self.reqwest_client.clone(),
+        }
+    }
+
+    /// Ask one gateway for the content of a CID, and record it if the gateway fails
+    async fn request(&self, gateway: &Gateway, cid: &Cid) -> Result<Response, SyncError> {
+        let result = async {
+            let res = self
+                .reqwest_client
+                .get(format!("{}/ipfs/{}", gateway.url, cid))
+                .timeout(self.timeout)
+                .send()
+                .await?;
+            if !res.status().is_success() {
+                return Err(SyncError::StoreStatus {
+                    url: res.url().to_string(),
+                    status: res.status().as_u16(),
+                });
+            }
+            Ok(res)
+        }
+        .await;
+        if let Err(e) = &result {
+            self.failed(gateway, cid, e);
+        }
+        result
+    }
+
+    /// Stream a gateway's response into a Download and check it, and record how it went.
+    /// On failure the Download is reset for the next gateway
+    async fn stream(&self, gateway: &Gateway, cid: &Cid, mut res: Response, download: &mut Download) -> Result<(), SyncError> {
+        let result = async {
+            while let Some(chunk) = res.chunk().await? {
+                download.write(&chunk).await?;
+            }
+            download.check().await
+        }
+        .await;
+        match &result {
+            Ok(()) => *gateway.health.lock().unwrap() = Health::default(),
+            Err(e) => {
+                self.failed(gateway, cid, e);
+                download.reset().await?;
+            }
+        }
+        result
+    }
+
+    /// Report a gateway that failed, and start its cool-down if it keeps failing. A gateway that
+    /// sent the wrong content can't be trusted, so it cools down straight away
+    fn failed(&self, gateway: &Gateway, cid: &Cid, error: &SyncError) {
+        let mut health = gateway.health.lock().unwrap();
+        if let SyncError::Integrity(_) = error {
+            log::warn!("Gateway {} sent the wrong content for {}: {}", gateway.url, cid, error);
+            health.failures = self.max_failures;
+        } else {
+            log::warn!("Gateway {} could not serve {}: {}", gateway.url, cid, error);
+            health.failures += 1;
+        }
+        if health.failures >= self.max_failures {
+            health.failures = 0;
+            health.skip_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::TempDir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Serve the same response to every request, counting the requests
+    async fn serve(status: u16, body: &'static [u8], delay: Duration) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut buf = [0; 1024];
+                    let _ = socket.read(&mut buf).await;
+                    tokio::time::sleep(delay).await;
+                    let head = format!(
+                        "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        body.len()
+                    );
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    let _ = socket.write_all(body).await;
+                });
+            }
+        });
+        (url, hits)
+    }
+
+    /// Read a CID through the gateways, as `StoreClient::get` does
+    async fn get(client: &GatewayClient, cid: &Cid) -> Result<Vec<u8>, SyncError> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("content");
+        let mut download = Download::new(cid.clone(), path.clone()).await?;
+        if let Err(e) = client.get_into(cid, &mut download).await {
+            download.abort().await;
+            return Err(e);
+        }
+        download.finish().await?;
+        Ok(std::fs::read(path)?)
+    }
+
+    #[tokio::test]
+    /// Fall through failing and lying gateways to one that works
+    async fn test_in_order() {
+        let cid = Cid::from(b"hello".as_slice());
+        let (down, _) = serve(500, b"", Duration::ZERO).await;
+        let (lying, _) = serve(200, b"jello", Duration::ZERO).await;
+        let (good, _) = serve(200, b"hello", Duration::ZERO).await;
+
+        let client = GatewayClient::new(vec![lying.clone(), down.clone()], GatewayStrategy::InOrder);
+        let err = get(&client, &cid).await.unwrap_err();
+        // A substituted response isn't hidden behind a later gateway being down
+        assert!(matches!(err, SyncError::Integrity(_)));
+        // And the gateway that sent it isn't trusted again until its cool-down is over
+        assert_eq!(client.available().len(), 1);
+
+        let client = GatewayClient::new(vec![down, lying, good], GatewayStrategy::InOrder);
+        assert_eq!(get(&client, &cid).await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    /// The fastest good gateway wins a race, and slow gateways time out
+    async fn test_race() {
+        let cid = Cid::from(b"hello".as_slice());
+        let (slow, _) = serve(200, b"hello", Duration::from_secs(5)).await;
+        let (lying, _) = serve(200, b"jello", Duration::ZERO).await;
+        let (good, _) = serve(200, b"hello", Duration::from_millis(50)).await;
+
+        let client = GatewayClient::new(vec![slow.clone(), lying, good], GatewayStrategy::Race);
+        assert_eq!(get(&client, &cid).await.unwrap(), b"hello");
+
+        let client = GatewayClient::new(vec![slow], GatewayStrategy::Race)
+            .with_timeout(Duration::from_millis(100));
+        assert!(get(&client, &cid).await.is_err());
+    }
+
+    #[tokio::test]
+    /// Only gateways that actually failed are recorded in a race, including losers that time out after it is won
+    async fn test_race_health() {
+        let cid = Cid::from(b"hello".as_slice());
+        let (down, _) = serve(500, b"", Duration::ZERO).await;
+        let (fast, _) = serve(200, b"hello", Duration::ZERO).await;
+        let (good, _) = serve(200, b"hello", Duration::from_millis(50)).await;
+        let (slow, _) = serve(200, b"hello", Duration::from_secs(5)).await;
+
+        // The failing gateway cools down, but the slow one only lost
+        let client = GatewayClient::new(vec![down, good, slow.clone()], GatewayStrategy::Race)
+            .with_cooldown(Duration::from_secs(60), 1);
+        assert_eq!(get(&client, &cid).await.unwrap(), b"hello");
+        assert_eq!(client.available().len(), 2);
+
+        // A loser that times out after the race is won is still recorded
+        let client = GatewayClient::new(vec![fast, slow], GatewayStrategy::Race)
+            .with_timeout(Duration::from_millis(200))
+            .with_cooldown(Duration::from_secs(60), 1);
+        assert_eq!(get(&client, &cid).await.unwrap(), b"hello");
+        assert_eq!(client.available().len(), 2);
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(client.available().len(), 1);
+    }
+
+    #[tokio::test]
+    /// A gateway that keeps failing is skipped until its cool-down is over
+    async fn test_cooldown() {
+        let cid = Cid::from(b"hello".as_slice());
+        let (down, down_hits) = serve(500, b"", Duration::ZERO).await;
+        let (good, _) = serve(200, b"hello", Duration::ZERO).await;
+
+        let client = GatewayClient::new(vec![down, good], GatewayStrategy::InOrder)
+            .with_cooldown(Duration::from_millis(200), 2);
+        for _ in 0..4 {
+            assert_eq!(get(&client, &cid).await.unwrap(), b"hello");
+        }
+        assert_eq!(down_hits.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        get(&client, &cid).await.unwrap();
+        assert_eq!(down_hits.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,141 @@
+/* Kubo Client */
+
+use crate::types::{cid::Cid, crud_file::CrudFile};
+use crate::config::StoreConfig;
+use crate::error::{Result, SyncError};
+use async_trait::async_trait;
+use reqwest::{multipart, Body, Client, Response};
+use serde::Deserialize;
+use tokio::fs::File;
+use tokio_util::codec::{BytesCodec, FramedRead};
+
+use super::{Download, Store};
+
+/// KuboClient - A struct for managing Requests to an IPFS Kubo node's HTTP RPC API
+pub struct KuboClient {
+    /// The Kubo RPC API URL, e.g. `http://127.0.0.1:5001`
+    pub kubo_api_url: String,
+    /// The Reqwest Client
+    reqwest_client: Client,
+}
+
+impl KuboClient {
+    /// Create a new KuboClient from the store configuration
+    /// # Arguments
+    /// * `store` - The store configuration
+    pub fn from_config(store: &StoreConfig) -> Self {
+        Self::new(store.kubo_api_url.clone())
+    }
+
+    /// Create a new KuboClient
+    /// # Arguments
+    /// * `kubo_api_url` - The URL of the Kubo RPC API to use.
+    pub fn new(kubo_api_url: String) -> Self {
+        let reqwest_client = Client::new();
+        Self {
+            kubo_api_url,
+            reqwest_client,
+        }
+    }
+
+    /// Call an RPC method. Kubo only accepts POSTs
+    /// # Arguments
+    /// * `method` - The method to call, e.g. `pin/rm`
+    /// * `cid` - The CID to pass as the `arg`
+    /// # Returns
+    /// * `Result<Response, SyncError>` - The successful response
+    async fn call(&self, method: &str, cid: &Cid) -> Result<Response, SyncError> {
+        let res = self
+            .reqwest_client
+            .post(format!("{}/api/v0/{}", self.kubo_api_url, method))
+            .query(&[("arg", cid.to_string())])
+            .send()
+            .await?;
+        if res.status().is_success() {
+            Ok(res)
+        } else {
+            Err(SyncError::StoreStatus {
+                url: res.url().to_string(),
+                status: res.status().as_u16(),
+            })
+        }
+    }
+}
+
+#[async_trait]
+impl Store for KuboClient {
+    /// Add and pin a File on the Kubo node
+    /// # Arguments
+    /// * `crud_file` - The CrudFile to add
+    /// # Returns
+    /// * `Result<Cid, SyncError>` - The CID the node reports for the file
+    async fn put(&self, crud_file: CrudFile) -> Result<Cid, SyncError> {
+        let file = File::open(crud_file.path).await?;
+        let file_body = Body::wrap_stream(FramedRead::new(file, BytesCodec::new()));
+        let part = multipart::Part::stream(file_body).file_name(crud_file.filename);
+        let form = multipart::Form::new().part("file", part);
+        let res = self
+            .reqwest_client
+            .post(format!("{}/api/v0/add", self.kubo_api_url))
+            // Match the CIDs we compute locally
+            .query(&[
+                ("cid-version", "1"),
+                ("raw-leaves", "true"),
+                ("chunker", "size-262144"),
+                ("pin", "true"),
+            ])
+            .multipart(form)
+            .send()
+            .await?;
+        if res.status().is_success() {
+            let added: AddResponse = res.json().await?;
+            Cid::from_str(added.hash)
+        } else {
+            Err(SyncError::StoreStatus {
+                url: res.url().to_string(),
+                status: res.status().as_u16(),
+            })
+        }
+    }
+
+    /// Stream the content of a CID from the Kubo node into a Download
+    /// # Arguments
+    /// * `cid` - The CID of the content
+    /// * `download` - Where to write the content
+    /// # Returns
+    /// * `Result<(), SyncError>` - Errors if the content could not be read
+    async fn get_into(&self, cid: Cid, download: &mut Download) -> Result<(), SyncError> {
+        let mut res = self.call("cat", &cid).await?;
+        while let Some(chunk) = res.chunk().await? {
+            download.write(&chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Check whether the Kubo node has the CID pinned
+    /// # Arguments
+    /// * `cid` - The CID of the content
+    /// # Returns
+    /// * `Result<bool, SyncError>` - Whether the content is pinned
+    async fn has(&self, cid: Cid) -> Result<bool, SyncError> {
+        // Kubo errors rather than returning an empty list for CIDs that aren't pinned
+        Ok(self.call("pin/ls", &cid).await.is_ok())
+    }
+
+    /// Unpin a CID on the Kubo node
+    /// # Arguments
+    /// * `cid` - The CID of the content
+    /// # Returns
+    /// * `Result<(), SyncError>` - Errors if the content could not be unpinned
+    async fn unpin(&self, cid: Cid) -> Result<(), SyncError> {
+        self.call("pin/rm", &cid).await?;
+        Ok(())
+    }
+}
+
+/// The response from `/api/v0/add`. We only need the CID
+#[derive(Debug, Deserialize)]
+struct AddResponse {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
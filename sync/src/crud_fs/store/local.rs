@@ -0,0 +1,103 @@
+/* Local Store */
+
+use crate::types::{cid::{Cid, CidMode}, crud_file::CrudFile};
+use crate::config::StoreConfig;
+use crate::error::{Result, SyncError};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+
+use super::{Download, Store};
+
+/// How much of a blob is read at once
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// LocalStore - A content-addressed directory on the local filesystem
+/// Each blob is stored in a file named after its CID
+pub struct LocalStore {
+    /// The directory blobs are stored in
+    pub root: PathBuf,
+    /// How blobs are named
+    pub cid_mode: CidMode,
+}
+
+impl LocalStore {
+    /// Create a new LocalStore from the store configuration
+    /// # Arguments
+    /// * `store` - The store configuration
+    pub fn from_config(store: &StoreConfig) -> Self {
+        Self::new(store.local_store_path.clone()).with_cid_mode(store.cid_mode)
+    }
+
+    /// Create a new LocalStore
+    /// # Arguments
+    /// * `root` - The directory to store blobs in. Created on first put
+    pub fn new(root: PathBuf) -> Self {
+        Self { root, cid_mode: CidMode::default() }
+    }
+
+    /// Name blobs by Cids computed some other way than `ipfs add` does
+    /// # Arguments
+    /// * `cid_mode` - How blobs are named
+    pub fn with_cid_mode(mut self, cid_mode: CidMode) -> Self {
+        self.cid_mode = cid_mode;
+        self
+    }
+
+    fn blob_path(&self, cid: &Cid) -> PathBuf {
+        self.root.join(cid.to_string())
+    }
+}
+
+#[async_trait]
+impl Store for LocalStore {
+    /// Copy a File into the store
+    /// # Arguments
+    /// * `crud_file` - The CrudFile to copy
+    /// # Returns
+    /// * `Result<Cid, SyncError>` - The CID of the file's content
+    async fn put(&self, crud_file: CrudFile) -> Result<Cid, SyncError> {
+        let data = tokio::fs::read(&crud_file.path).await?;
+        let cid = Cid::from_reader(data.as_slice(), self.cid_mode)?;
+        tokio::fs::create_dir_all(&self.root).await?;
+        tokio::fs::write(self.blob_path(&cid), data).await?;
+        Ok(cid)
+    }
+
+    /// Stream a blob from the store into a Download
+    /// # Arguments
+    /// * `cid` - The CID of the content
+    /// * `download` - Where to write the content
+    /// # Returns
+    /// * `Result<(), SyncError>` - Errors if the blob could not be read
+    async fn get_into(&self, cid: Cid, download: &mut Download) -> Result<(), SyncError> {
+        let mut file = tokio::fs::File::open(self.blob_path(&cid)).await?;
+        let mut buffer = vec![0; BUFFER_SIZE];
+        loop {
+            let count = file.read(&mut buffer).await?;
+            if count == 0 {
+                return Ok(());
+            }
+            download.write(&buffer[..count]).await?;
+        }
+    }
+
+    /// Check whether a blob is in the store
+    /// # Arguments
+    /// * `cid` - The CID of the content
+    /// # Returns
+    /// * `Result<bool, SyncError>` - Whether the blob exists
+    async fn has(&self, cid: Cid) -> Result<bool, SyncError> {
+        Ok(self.blob_path(&cid).is_file())
+    }
+
+    /// Remove a blob from the store
+    /// # Arguments
+    /// * `cid` - The CID of the content
+    /// # Returns
+    /// * `Result<(), SyncError>` - Errors if the blob could not be removed
+    async fn unpin(&self, cid: Cid) -> Result<(), SyncError> {
+        tokio::fs::remove_file(self.blob_path(&cid)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use crate::types::{cid::Cid, crud_file::CrudFile};
+use crate::config::{Config, StoreConfig};
+use crate::error::{Result, SyncError};
+use async_trait::async_trait;
+use std::path::PathBuf;
+
+pub mod download;
+pub mod estuary;
+pub mod gateway;
+pub mod kubo;
+pub mod local;
+
+pub use download::{Download, IntegrityError};
+pub use estuary::EstuaryClient;
+pub use gateway::{GatewayClient, GatewayStrategy};
+pub use kubo::KuboClient;
+pub use local::LocalStore;
+
+/// A content-addressed store that CrudFs content is pinned to
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Put a CrudFile's content into the store
+    /// # Arguments
+    /// - `crud_file` - The CrudFile to put into the store
+    /// # Returns
+    /// - `Result<Cid, SyncError>` - The Cid the store reports for the content
+    async fn put(&self, crud_file: CrudFile) -> Result<Cid, SyncError>;
+
+    /// Stream content from the store into a Download, a chunk at a time
+    /// # Arguments
+    /// - `cid` - The Cid of the content
+    /// - `download` - Where to write the content
+    /// # Returns
+    /// - `Result<(), SyncError>` - Errors if the content could not be read or written
+    async fn get_into(&self, cid: Cid, download: &mut Download) -> Result<(), SyncError>;
+
+    /// Check whether the store has content
+    /// # Arguments
+    /// - `cid` - The Cid of the content
+    /// # Returns
+    /// - `Result<bool, SyncError>` - Whether the content is in the store
+    async fn has(&self, cid: Cid) -> Result<bool, SyncError>;
+
+    /// Unpin content from the store
+    /// # Arguments
+    /// - `cid` - The Cid of the content
+    /// # Returns
+    /// - `Result<(), SyncError>` - The result of the operation
+    async fn unpin(&self, cid: Cid) -> Result<(), SyncError>;
+}
+
+pub struct StoreClient {
+    store: Box<dyn Store>,
+    /// Gateways to read through before falling back on the store
+    gateways: Option<GatewayClient>,
+}
+
+impl StoreClient {
+    /// Choose a Store from `STORE_BACKEND`: `kubo` (the default), `estuary` or `local`,
+    /// and read through the configured gateways, if any
+    /// # Returns
+    /// - `Result<StoreClient, SyncError>` - A configuration error if the environment is incomplete
+    pub fn from_env() -> Result<Self, SyncError> {
+        Self::from_config(&Config::from_env()?.store)
+    }
+
+    /// Create the configured Store, and read through the configured gateways, if any
+    /// # Arguments
+    /// - `store` - The store configuration
+    /// # Returns
+    /// - `Result<StoreClient, SyncError>` - A configuration error if the configuration is incomplete
+    pub fn from_config(config: &StoreConfig) -> Result<Self, SyncError> {
+        let store: Box<dyn Store> = match config.backend.as_str() {
+            "estuary" => Box::new(EstuaryClient::from_config(config)?),
+            "kubo" => Box::new(KuboClient::from_config(config)),
+            "local" => Box::new(LocalStore::from_config(config)),
+            other => return Err(SyncError::Config(format!("Unknown store backend: {}", other))),
+        };
+        let client = Self::new(store);
+        Ok(match GatewayClient::from_config(config) {
+            Some(gateways) => client.with_gateways(gateways),
+            None => client,
+        })
+    }
+
+    /// Create the configured Store for reading only. Estuary serves content without an API key,
+    /// so one isn't required; puts and unpins will be refused by Estuary instead
+    /// # Arguments
+    /// - `store` - The store configuration
+    /// # Returns
+    /// - `Result<StoreClient, SyncError>` - A configuration error if the configuration is incomplete
+    pub fn for_reading(store: &StoreConfig) -> Result<Self, SyncError> {
+        let mut store = store.clone();
+        store.estuary_api_key.get_or_insert_with(String::new);
+        Self::from_config(&store)
+    }
+
+    pub fn new(store: Box<dyn Store>) -> Self {
+        Self {
+            store,
+            gateways: None,
+        }
+    }
+
+    /// Read through IPFS gateways first, and only fall back on the store if none of them work
+    /// # Arguments
+    /// - `gateways` - The gateways to read through
+    pub fn with_gateways(mut self, gateways: GatewayClient) -> Self {
+        self.gateways = Some(gateways);
+        self
+    }
+
+    /// Create a StoreClient from the name of a Store, configured from the environment
+    /// # Arguments
+    /// - `name` - One of `estuary`, `kubo` or `local`
+    /// # Returns
+    /// - `Result<StoreClient, SyncError>` - Errors if the name is unknown
+    pub fn from_name(name: &str) -> Result<Self, SyncError> {
+        let mut store = Config::from_env()?.store;
+        store.backend = name.to_string();
+        Ok(Self::new(Self::from_config(&store)?.store))
+    }
+
+    /// Put a CrudFile into the store - placed from the current directory
+    /// # Arguments
+    /// - `crud_file` - The CrudFile to put into the store
+    /// # Returns
+    /// - `Result<Cid, SyncError>` - The Cid the store reports for the content
+    pub async fn put(&self, crud_file: CrudFile) -> Result<Cid, SyncError> {
+        self.store.put(crud_file).await
+    }
+
+    /// Get a CrudFile from the store - placed from the current directory
+    /// The content is only written to the path once it matches the Cid
+    /// # Arguments
+    /// - `cid` - The Cid of the content to get from the store
+    /// - `path` - The path to write the content to
+    /// # Returns
+    /// - `Result<CrudFile, SyncError>` - The CrudFile for the written path, or an IntegrityError
+    pub async fn get(&self, cid: Cid, path: PathBuf) -> Result<CrudFile, SyncError> {
+        let mut download = Download::new(cid.clone(), path.clone()).await?;
+        let result = match &self.gateways {
+            // The store is the last resort, so gateway errors aren't fatal
+            Some(gateways) => match gateways.get_into(&cid, &mut download).await {
+                Ok(()) => Ok(()),
+                Err(e) => {
+                    log::warn!("Reading {} from the store, since no gateway served it: {}", cid, e);
+                    self.store.get_into(cid, &mut download).await
+                }
+            },
+            None => self.store.get_into(cid, &mut download).await,
+        };
+        if let Err(e) = result {
+            download.abort().await;
+            return Err(e);
+        }
+        // Overwrite the file if it exists
+        download.finish().await?;
+        // Return the crud file from the path
+        CrudFile::new(path)
+    }
+
+    /// Read content from the store into memory. It is streamed through a temp file like any
+    /// other download, so only verified content is returned
+    /// # Arguments
+    /// - `cid` - The Cid of the content to read
+    /// # Returns
+    /// - `Result<Vec<u8>, SyncError>` - The content, or an IntegrityError
+    pub async fn cat(&self, cid: Cid) -> Result<Vec<u8>, SyncError> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("content");
+        self.get(cid, path.clone()).await?;
+        Ok(tokio::fs::read(path).await?)
+    }
+
+    /// Check whether the store has content
+    /// # Arguments
+    /// - `cid` - The Cid of the content
+    /// # Returns
+    /// - `Result<bool, SyncError>` - Whether the content is in the store
+    pub async fn has(&self, cid: Cid) -> Result<bool, SyncError> {
+        self.store.has(cid).await
+    }
+
+    /// Unpin content from the store
+    /// # Arguments
+    /// - `cid` - The Cid of the content to unpin
+    /// # Returns
+    /// - `Result<(), SyncError>` - The result of the operation
+    pub async fn unpin(&self, cid: Cid) -> Result<(), SyncError> {
+        self.store.unpin(cid).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    /// Put and Get a file from the store
+    async fn test_put_get() {
+        use super::*;
+        use std::fs::File;
+        use std::io::{Read, Write};
+
+        // Initialize the Client from the .env file
+        dotenv::from_path("./../../env/.env").ok();
+        let client = StoreClient::from_env().unwrap();
+
+        // Create test file
+        // Create a test directory
+        let test_dir = PathBuf::from("test");
+        std::fs::create_dir_all(&test_dir).unwrap();
+        let path = PathBuf::from("test/file.txt");
+        let mut file = File::create(&path).unwrap();
+        let message = "helll wooord";
+        let data = message.as_bytes();
+        file.write_all(data).unwrap();
+
+        file.write_all(data).unwrap();
+
+        // Get a Crud file from the path
+        let crud_file = CrudFile::new(path).unwrap();
+        // Put the file into the store
+        client.put(crud_file.clone()).await.unwrap();
+        // Get the file from the store. Put into a new path
+        let new_path = PathBuf::from("test/file2.txt");
+        let new_crud_file = client
+            .get(crud_file.cid.clone(), new_path.clone())
+            .await
+            .unwrap();
+        // Assert that the new file is the same as the old file
+        // Assert the Cid is the same
+        assert_eq!(crud_file.cid, new_crud_file.cid);
+        assert_eq!(new_path, new_crud_file.path);
+        // Assert the content of their paths are the same
+        // Read the content of the new file
+        let mut new_file = File::open(new_crud_file.path).unwrap();
+        let mut new_data: [u8; 12] = [0; 12];
+        new_file.read_exact(&mut new_data).unwrap();
+
+        // Read the content of the old file
+        assert_eq!(data, new_data);
+        // Remove the test directory
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[tokio::test]
+    /// Put, Get and Unpin a file from a LocalStore
+    async fn test_local_store() {
+        use super::*;
+
+        let temp = tempfile::tempdir().unwrap();
+        let test_dir = temp.path();
+        let client = StoreClient::new(Box::new(LocalStore::new(test_dir.join("store"))));
+
+        // Create test file
+        let path = test_dir.join("file.txt");
+        std::fs::write(&path, "helll wooord").unwrap();
+        let crud_file = CrudFile::new(path).unwrap();
+
+        // Put the file into the store. A local store uses the same Cid as we do
+        let cid = client.put(crud_file.clone()).await.unwrap();
+        assert_eq!(cid, crud_file.cid);
+        assert!(client.has(cid.clone()).await.unwrap());
+
+        // Get the file from the store. Put into a new path
+        let new_path = test_dir.join("file2.txt");
+        let new_crud_file = client.get(cid.clone(), new_path.clone()).await.unwrap();
+        assert_eq!(crud_file.cid, new_crud_file.cid);
+        assert_eq!(std::fs::read(new_path).unwrap(), b"helll wooord");
+
+        // Unpin the file
+        client.unpin(cid.clone()).await.unwrap();
+        assert!(!client.has(cid).await.unwrap());
+    }
+}
@@ -0,0 +1,248 @@
+use notify::{RecursiveMode, Watcher};
+use std::{future::Future, path::PathBuf, pin::Pin, time::Duration};
+use tokio::{sync::mpsc, time::sleep};
+use sync::{
+    config::Config,
+    error::{Result, SyncError},
+    utils::{path::ContentRoot, walk::walk_dir},
+};
+
+use crate::{
+    lock::{ManifestLock, LOCK_TIMEOUT},
+    manifest::Manifest,
+    plan,
+};
+
+/// Listen for the signals that stop the daemon: SIGINT or SIGTERM
+/// # Returns
+/// * `std::io::Result<impl Future<Output = ()>>` - Completes on either signal. Errors if the handlers can't be set up
+#[cfg(unix)]
+fn shutdown_signal() -> std::io::Result<impl Future<Output = ()>> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    Ok(async move {
+        tokio::select! {
+            _ = sigint.recv() => {},
+            _ = sigterm.recv() => {},
+        }
+    })
+}
+
+/// Listen for the signals that stop the daemon. Off unix, that is only Ctrl-C
+/// # Returns
+/// * `std::io::Result<impl Future<Output = ()>>` - Completes on Ctrl-C
+#[cfg(not(unix))]
+fn shutdown_signal() -> std::io::Result<impl Future<Output = ()>> {
+    Ok(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+}
+
+/// Why the daemon stopped waiting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wake {
+    /// Something changed, and has settled
+    Sync,
+    /// A signal arrived, or the watcher is gone
+    Shutdown,
+}
+
+/// Watches a content directory and keeps the backend and manifest in sync with it
+/// # Fields
+/// * `dir` - The content directory to watch
+/// * `manifest_path` - The path to the manifest file
+/// * `debounce` - How long the directory has to be quiet before we sync
+/// * `config` - The configuration to open CrudFs with
+pub struct Daemon {
+    dir: PathBuf,
+    manifest_path: PathBuf,
+    debounce: Duration,
+    config: Config,
+}
+
+impl Daemon {
+    /// Create a new Daemon
+    /// # Arguments
+    /// * `dir` - The content directory to watch
+    /// * `manifest_path` - The path to the manifest file
+    /// * `debounce` - How long the directory has to be quiet before we sync
+    /// * `config` - The configuration to open CrudFs with
+    pub fn new(dir: PathBuf, manifest_path: PathBuf, debounce: Duration, config: Config) -> Self {
+        Self {
+            dir,
+            manifest_path,
+            debounce,
+            config,
+        }
+    }
+
+    /// Run until SIGINT or SIGTERM
+    /// A sync that is in flight when a signal arrives is finished, and the manifest
+    /// written, before we return
+    /// # Returns
+    /// * `Result<(), SyncError>` - Errors if the watcher or signal handlers can't be set up
+    pub async fn run(&self) -> Result<(), SyncError> {
+        let shutdown = shutdown_signal()?;
+
+        // Forward filesystem events into the runtime
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                let _ = tx.send(event);
+            }
+        })
+        .map_err(watch_error)?;
+        watcher
+            .watch(&self.dir, RecursiveMode::Recursive)
+            .map_err(watch_error)?;
+        println!("Watching directory: {}", self.dir.display());
+
+        // Catch up on anything that changed while we weren't running
+        self.sync().await;
+
+        // Stop on a signal, once any sync in flight is done
+        tokio::pin!(shutdown);
+        while next_sync(&mut rx, self.debounce, &mut shutdown).await == Wake::Sync {
+            self.sync().await;
+        }
+        println!("Shutting down");
+        Ok(())
+    }
+
+    /// Sync the directory once, logging rather than returning errors so the daemon keeps running
+    async fn sync(&self) {
+        if let Err(e) = self.try_sync().await {
+            eprintln!("Could not sync: {}", e);
+        }
+    }
+
+    async fn try_sync(&self) -> Result<(), SyncError> {
+        // Hold the manifest until it is written, so we don't race a `sync` run from the CLI
+        let manifest_path = self.manifest_path.clone();
+        let lock = tokio::task::spawn_blocking(move || ManifestLock::acquire(&manifest_path, "daemon", LOCK_TIMEOUT))
+            .await
+            .map_err(|e| SyncError::Io(std::io::Error::other(e)))??;
+        if let Some(holder) = lock.stale() {
+            println!("Warning: {} didn't finish; run `sync status` to check the manifest", holder);
+        }
+        let content_root = ContentRoot::from_config(&self.config);
+        let mut manifest = Manifest::read(&self.manifest_path, &content_root)?;
+        let local_files = walk_dir(&self.dir)?;
+        let own_files = plan::own_files(&self.config, &self.manifest_path);
+        let (strip_front_matter, cid_mode) = (self.config.strip_front_matter, self.config.store.cid_mode);
+        let plan = |manifest: &Manifest, local_files| {
+            plan::plan(manifest, &content_root, &self.dir, local_files, &own_files, strip_front_matter, cid_mode)
+        };
+        let mut actions = plan(&manifest, local_files.clone())?;
+        if actions.is_empty() {
+            return Ok(());
+        }
+        let crud_fs = manifest.crud_fs(&self.config)?;
+        // Finish any move that was interrupted, then plan what is left
+        let finished = plan::finish_moves(&crud_fs, &mut manifest, &actions).await;
+        manifest.write(&self.manifest_path)?;
+        if !finished?.is_empty() {
+            actions = plan(&manifest, local_files)?;
+        }
+        // Keep what we pushed even if a later action fails
+        let result = plan::apply(&crud_fs, &mut manifest, actions).await;
+        manifest.write(&self.manifest_path)?;
+        result
+    }
+}
+
+/// Wait for something to change, then for the burst of edits after it to settle
+/// # Arguments
+/// * `rx` - The filesystem events
+/// * `debounce` - How long the events have to stop for
+/// * `shutdown` - Completes when we should stop
+/// # Returns
+/// * `Wake` - Sync once the events settle. Shutdown on a signal, or once the events stop for good
+async fn next_sync<T, F: Future<Output = ()>>(
+    rx: &mut mpsc::UnboundedReceiver<T>,
+    debounce: Duration,
+    shutdown: &mut Pin<&mut F>,
+) -> Wake {
+    tokio::select! {
+        _ = shutdown.as_mut() => return Wake::Shutdown,
+        event = rx.recv() => if event.is_none() { return Wake::Shutdown },
+    }
+    loop {
+        tokio::select! {
+            _ = shutdown.as_mut() => return Wake::Shutdown,
+            event = rx.recv() => match event {
+                Some(_) => continue,
+                // The watcher is gone, so nothing will change again
+                None => return Wake::Shutdown,
+            },
+            _ = sleep(debounce) => return Wake::Sync,
+        }
+    }
+}
+
+/// The watcher's errors are IO errors, or wrap one
+fn watch_error(e: notify::Error) -> SyncError {
+    match e.kind {
+        notify::ErrorKind::Io(e) => SyncError::Io(e),
+        _ => SyncError::Io(std::io::Error::other(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{sync::oneshot, time::{timeout, Instant}};
+
+    const DEBOUNCE: Duration = Duration::from_millis(50);
+
+    #[tokio::test]
+    /// A burst of events is one sync, once it has been quiet for the debounce
+    async fn test_debounce() {
+        // Keep a sender, so only the debounce ends the wait
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let shutdown = std::future::pending::<()>();
+        tokio::pin!(shutdown);
+        let start = Instant::now();
+        tokio::spawn({
+            let tx = tx.clone();
+            async move {
+                for _ in 0..3 {
+                    tx.send(()).unwrap();
+                    sleep(DEBOUNCE / 5).await;
+                }
+            }
+        });
+        assert_eq!(next_sync(&mut rx, DEBOUNCE, &mut shutdown).await, Wake::Sync);
+        assert!(start.elapsed() >= DEBOUNCE + DEBOUNCE / 5 * 2);
+        assert!(rx.try_recv().is_err());
+        drop(tx);
+    }
+
+    #[tokio::test]
+    /// A signal or a closed channel stops the daemon, rather than spinning on it
+    async fn test_shutdown() {
+        let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        tokio::pin!(shutdown);
+        tx.send(()).unwrap();
+        stop.send(()).unwrap();
+        assert_eq!(next_sync(&mut rx, Duration::from_secs(60), &mut shutdown).await, Wake::Shutdown);
+
+        // The watcher going away while waiting, and while debouncing
+        let shutdown = std::future::pending::<()>();
+        tokio::pin!(shutdown);
+        drop(tx);
+        let wake = timeout(DEBOUNCE, next_sync(&mut rx, Duration::from_secs(60), &mut shutdown)).await;
+        assert_eq!(wake.unwrap(), Wake::Shutdown);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tx.send(()).unwrap();
+        tx.send(()).unwrap();
+        drop(tx);
+        let wake = timeout(DEBOUNCE, next_sync(&mut rx, Duration::from_secs(60), &mut shutdown)).await;
+        assert_eq!(wake.unwrap(), Wake::Shutdown);
+    }
+}
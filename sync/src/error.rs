@@ -0,0 +1,180 @@
+/* Errors */
+
+use ethers::{
+    abi::{self, ParamType, Token},
+    contract::ContractError,
+    providers::{Middleware, ProviderError},
+    signers::WalletError,
+    utils::hex,
+};
+use thiserror::Error;
+
+pub use crate::crud_fs::store::IntegrityError;
+
+/// The selector of Solidity's `Error(string)`, which `require` reverts with
+const REVERT_SELECTOR: &str = "08c379a0";
+
+/// A Result that fails with a SyncError
+pub type Result<T, E = SyncError> = std::result::Result<T, E>;
+
+/// Everything that can go wrong in `sync`
+#[derive(Debug, Error)]
+pub enum SyncError {
+    /// The contract reverted, with its revert string, e.g. `File already exists.`
+    #[error("Contract reverted: {0}")]
+    Revert(String),
+    /// The RPC node couldn't be reached, or failed for some other reason
+    #[error("RPC error: {0}")]
+    Rpc(String),
+    /// A store or gateway answered with an error status
+    #[error("Store error: {url} returned {status}")]
+    StoreStatus { url: String, status: u16 },
+    /// A store or gateway couldn't be reached
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    /// A CID couldn't be parsed
+    #[error("Invalid CID: {0}")]
+    Cid(#[from] cid::Error),
+    /// Content didn't match its CID. Boxed, as it carries two CIDs
+    #[error(transparent)]
+    Integrity(Box<IntegrityError>),
+    /// The manifest couldn't be read or written
+    #[error("Manifest error: {0}")]
+    Manifest(String),
+    /// Something is missing or wrong in the environment or config
+    #[error("Configuration error: {0}")]
+    Config(String),
+    /// A path can't be used as a CrudFs path
+    #[error("Invalid path: {0}")]
+    Path(String),
+    /// Reading or writing local files failed
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    /// JSON couldn't be encoded or decoded
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    /// Data from the chain, a store or a CAR file is malformed
+    #[error("Malformed data: {0}")]
+    Decode(String),
+    /// A key couldn't be loaded, or the signer refused or failed to sign
+    #[error("Signer error: {0}")]
+    Signer(String),
+    /// A write was attempted through a client that has no signer
+    #[error("Read-only client: {0}")]
+    ReadOnly(String),
+    /// A file's metadata is too large, has a value of the wrong type, or doesn't match the schema
+    #[error("Invalid metadata: {0}")]
+    Metadata(String),
+    /// The history index couldn't be read or written
+    #[error("History error: {0}")]
+    History(String),
+}
+
+impl From<IntegrityError> for SyncError {
+    fn from(e: IntegrityError) -> Self {
+        SyncError::Integrity(Box::new(e))
+    }
+}
+
+impl From<rusqlite::Error> for SyncError {
+    fn from(e: rusqlite::Error) -> Self {
+        SyncError::History(e.to_string())
+    }
+}
+
+impl From<WalletError> for SyncError {
+    fn from(e: WalletError) -> Self {
+        SyncError::Signer(e.to_string())
+    }
+}
+
+impl From<cid::multihash::Error> for SyncError {
+    fn from(e: cid::multihash::Error) -> Self {
+        SyncError::Cid(cid::Error::from(e))
+    }
+}
+
+impl<M: Middleware> From<ContractError<M>> for SyncError {
+    /// Pull the revert string out of a failed call or transaction, if it reverted
+    fn from(e: ContractError<M>) -> Self {
+        let message = e.to_string();
+        if let Some(reason) = revert_reason(&message) {
+            return SyncError::Revert(reason);
+        }
+        match e {
+            ContractError::DecodingError(_)
+            | ContractError::AbiError(_)
+            | ContractError::DetokenizationError(_) => SyncError::Decode(message),
+            _ => SyncError::Rpc(message),
+        }
+    }
+}
+
+impl From<ProviderError> for SyncError {
+    /// Waiting on a transaction can also surface a revert
+    fn from(e: ProviderError) -> Self {
+        let message = e.to_string();
+        match revert_reason(&message) {
+            Some(reason) => SyncError::Revert(reason),
+            None => SyncError::Rpc(message),
+        }
+    }
+}
+
+/// Find the revert string in an RPC error.
+/// Nodes put it in the error data as an ABI encoded `Error(string)`, in the message, or both
+/// # Arguments
+/// * `message` - The error, as ethers formats it
+/// # Returns
+/// * `Option<String>` - The revert string, if the error is a revert
+pub fn revert_reason(message: &str) -> Option<String> {
+    // Prefer the encoded reason: it can't be confused with the rest of the message
+    if let Some(start) = message.find(REVERT_SELECTOR) {
+        let data: String = message[start..]
+            .chars()
+            .take_while(|c| c.is_ascii_hexdigit())
+            .collect();
+        let decoded = hex::decode(&data[REVERT_SELECTOR.len()..])
+            .ok()
+            .and_then(|bytes| abi::decode(&[ParamType::String], &bytes).ok());
+        if let Some(Token::String(reason)) = decoded.and_then(|tokens| tokens.into_iter().next()) {
+            return Some(reason);
+        }
+    }
+    // geth style: `execution reverted: File already exists.`
+    if let Some(start) = message.find("execution reverted: ") {
+        let rest = &message[start + "execution reverted: ".len()..];
+        let end = rest.find(", data:").unwrap_or(rest.len());
+        return Some(rest[..end].trim_end_matches(')').to_string());
+    }
+    // hardhat and anvil style: `reverted with reason string 'File already exists.'`
+    if let Some(start) = message.find("reverted with reason string '") {
+        let rest = &message[start + "reverted with reason string '".len()..];
+        return rest.find('\'').map(|end| rest[..end].to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Revert strings are found however the node reports them
+    fn test_revert_reason() {
+        let encoded = hex::encode(abi::encode(&[Token::String("File already exists.".to_string())]));
+        let data = format!(
+            "(code: 3, message: execution reverted, data: Some(String(\"0x{}{}\")))",
+            REVERT_SELECTOR, encoded
+        );
+        assert_eq!(revert_reason(&data).unwrap(), "File already exists.");
+
+        let geth = "(code: -32000, message: execution reverted: File does not exist., data: None)";
+        assert_eq!(revert_reason(geth).unwrap(), "File does not exist.");
+
+        let hardhat = "VM Exception while processing transaction: reverted with reason string 'CID cannot be empty'";
+        assert_eq!(revert_reason(hardhat).unwrap(), "CID cannot be empty");
+
+        assert!(revert_reason("(code: -32000, message: nonce too low, data: None)").is_none());
+    }
+}
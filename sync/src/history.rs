@@ -0,0 +1,493 @@
+/* History */
+
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use ethers::utils::hex;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{fmt, path::Path, str::FromStr, time::Duration};
+
+use crate::crud_fs::backend::eth::CrudFsContractEvents;
+use crate::error::{Result, SyncError};
+use crate::types::{cid::Cid, metadata::Metadata};
+
+/// Where the history index is kept, unless configured otherwise
+pub const DEFAULT_HISTORY_PATH: &str = ".history.db";
+
+/// How many blocks to read logs from at once, unless configured otherwise.
+/// Most RPC providers cap `eth_getLogs` at a few thousand blocks
+pub const DEFAULT_LOG_PAGE_SIZE: u64 = 1000;
+
+/// How long to wait for another sync writing to the index
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        name TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS revisions (
+        key TEXT NOT NULL,
+        change TEXT NOT NULL,
+        cid TEXT,
+        metadata TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        block_number INTEGER NOT NULL,
+        log_index INTEGER NOT NULL,
+        tx_hash TEXT NOT NULL,
+        PRIMARY KEY (block_number, log_index)
+    );
+    CREATE INDEX IF NOT EXISTS revisions_by_key ON revisions (key, block_number, log_index);
+";
+
+/// What a revision did to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// `CreateFile`
+    Create,
+    /// `UpdateFile`
+    Update,
+    /// `DeleteFile`
+    Delete,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Create => write!(f, "create"),
+            Change::Update => write!(f, "update"),
+            Change::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+impl FromStr for Change {
+    type Err = SyncError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(Change::Create),
+            "update" => Ok(Change::Update),
+            "delete" => Ok(Change::Delete),
+            other => Err(SyncError::History(format!("Unknown change {:?}", other))),
+        }
+    }
+}
+
+/// Revision - One change to a file, from the event the contract emitted for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Revision {
+    /// The key of the file
+    pub key: [u8; 32],
+    /// What the change was
+    pub change: Change,
+    /// The CID the file was set to. None for deletes
+    pub cid: Option<Cid>,
+    /// The metadata the file was set to, as it was emitted. Empty for deletes.
+    /// The contract takes any string, so it is only parsed to be shown
+    pub metadata: String,
+    /// The timestamp of the block the change was made in
+    pub timestamp: u64,
+    /// The block the change was made in
+    pub block_number: u64,
+    /// The position of the event in its block
+    pub log_index: u64,
+    /// The transaction that made the change
+    pub tx_hash: [u8; 32],
+}
+
+impl Revision {
+    /// Decode a CrudFs event
+    /// # Arguments
+    /// * `event` - The event
+    /// * `block_number` - The block it was emitted in
+    /// * `log_index` - Its position in the block
+    /// * `tx_hash` - The transaction that emitted it
+    /// # Returns
+    /// * `Result<Option<Revision>, SyncError>` - The revision, or None for events that don't change files.
+    ///   A decode error if the CID is malformed.
+    ///   `DeleteFile` carries no timestamp, so a delete's is zero until it is set from its block
+    pub fn from_event(
+        event: CrudFsContractEvents,
+        block_number: u64,
+        log_index: u64,
+        tx_hash: [u8; 32],
+    ) -> Result<Option<Self>, SyncError> {
+        let (key, change, cid, metadata, timestamp) = match event {
+            CrudFsContractEvents::CreateFileFilter(e) => (e.key, Change::Create, Some(e.cid), e.metadata, e.timestamp),
+            CrudFsContractEvents::UpdateFileFilter(e) => (e.key, Change::Update, Some(e.cid), e.metadata, e.timestamp),
+            CrudFsContractEvents::DeleteFileFilter(e) => (e.key, Change::Delete, None, String::new(), 0.into()),
+            // Ownership changes aren't changes to files
+            CrudFsContractEvents::OwnershipTransferredFilter(_) => return Ok(None),
+        };
+        Ok(Some(Self {
+            key,
+            change,
+            cid: cid.map(|cid| decode_cid(&cid)).transpose()?,
+            metadata,
+            timestamp: timestamp.as_u64(),
+            block_number,
+            log_index,
+            tx_hash,
+        }))
+    }
+
+    /// The metadata, if it is a JSON object of strings as this crate writes it
+    pub fn parsed_metadata(&self) -> Option<Metadata> {
+        serde_json::from_str(&self.metadata).ok()
+    }
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = Utc
+            .timestamp_opt(self.timestamp as i64, 0)
+            .single()
+            .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+            .unwrap_or_else(|| self.timestamp.to_string());
+        write!(f, "{} {} ", time, self.change)?;
+        if let Some(cid) = &self.cid {
+            write!(f, "{} ", cid)?;
+        }
+        write!(f, "(block {}, tx 0x{})", self.block_number, hex::encode(self.tx_hash))?;
+        match self.parsed_metadata() {
+            Some(metadata) if metadata.is_empty() => {}
+            Some(metadata) => {
+                // Sort the keys, so the same metadata always reads the same
+                let metadata: std::collections::BTreeMap<_, _> = metadata.iter().collect();
+                write!(f, "\n    {}", serde_json::to_string(&metadata).unwrap_or_default())?;
+            }
+            // Anything else someone put on chain is shown as it is
+            None if self.metadata.is_empty() => {}
+            None => write!(f, "\n    {}", self.metadata)?,
+        }
+        Ok(())
+    }
+}
+
+/// Parse a CID from an event
+fn decode_cid(cid: &str) -> Result<Cid, SyncError> {
+    use ethers::abi::{Token, Tokenizable};
+    Cid::from_token(Token::String(cid.to_string())).map_err(|e| SyncError::Decode(e.to_string()))
+}
+
+/// EventSource - Where the indexer reads CrudFs events from
+#[async_trait]
+pub trait EventSource: Send + Sync {
+    /// The number of the latest block
+    async fn block_number(&self) -> Result<u64, SyncError>;
+
+    /// Every revision made in a range of blocks, oldest first
+    /// # Arguments
+    /// * `from_block` - The first block, inclusive
+    /// * `to_block` - The last block, inclusive
+    async fn revisions(&self, from_block: u64, to_block: u64) -> Result<Vec<Revision>, SyncError>;
+}
+
+/// History - A local SQLite index of every revision of every file, built from the contract's logs.
+/// The contract only keeps the latest CID of each file; its events keep the rest
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    /// Open the index at a path, creating it if it doesn't exist
+    /// # Arguments
+    /// * `path` - Where the index is kept
+    /// * `contract_address` - The contract it indexes
+    /// # Returns
+    /// * `Result<History, SyncError>` - A history error if it can't be opened, or indexes another contract
+    pub fn open(path: &Path, contract_address: &str) -> Result<Self, SyncError> {
+        let conn = Connection::open(path)
+            .map_err(|e| SyncError::History(format!("Could not open {}: {}", path.display(), e)))?;
+        Self::init(conn, contract_address)
+    }
+
+    /// Open an index that only lives in memory
+    /// # Arguments
+    /// * `contract_address` - The contract it indexes
+    pub fn in_memory(contract_address: &str) -> Result<Self, SyncError> {
+        Self::init(Connection::open_in_memory()?, contract_address)
+    }
+
+    fn init(conn: Connection, contract_address: &str) -> Result<Self, SyncError> {
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch(SCHEMA)?;
+        let history = Self { conn };
+        let contract_address = contract_address.to_lowercase();
+        match history.meta("contract_address")? {
+            None => history.set_meta("contract_address", &contract_address)?,
+            Some(indexed) if indexed == contract_address => {}
+            Some(indexed) => {
+                return Err(SyncError::History(format!(
+                    "The index is of contract {}, not {}. Set history_path to index another contract",
+                    indexed, contract_address
+                )))
+            }
+        }
+        Ok(history)
+    }
+
+    /// The last block indexed
+    /// # Returns
+    /// * `Result<Option<u64>, SyncError>` - None if nothing has been indexed yet
+    pub fn last_block(&self) -> Result<Option<u64>, SyncError> {
+        self.meta("last_block")?
+            .map(|block| {
+                block
+                    .parse()
+                    .map_err(|_| SyncError::History(format!("Invalid last block {:?}", block)))
+            })
+            .transpose()
+    }
+
+    /// Record the revisions made in a range of blocks, and that the range is indexed.
+    /// Recording a revision again keeps the one copy
+    /// # Arguments
+    /// * `revisions` - The revisions
+    /// * `to_block` - The last block of the range
+    pub fn record(&mut self, revisions: &[Revision], to_block: u64) -> Result<(), SyncError> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR IGNORE INTO revisions
+                 (key, change, cid, metadata, timestamp, block_number, log_index, tx_hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for revision in revisions {
+                insert.execute(params![
+                    hex::encode(revision.key),
+                    revision.change.to_string(),
+                    revision.cid.as_ref().map(|cid| cid.to_string()),
+                    revision.metadata,
+                    revision.timestamp as i64,
+                    revision.block_number as i64,
+                    revision.log_index as i64,
+                    hex::encode(revision.tx_hash),
+                ])?;
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO meta (name, value) VALUES ('last_block', ?1)",
+                params![to_block.to_string()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Every revision of a file, oldest first
+    /// # Arguments
+    /// * `key` - The key of the file
+    pub fn revisions(&self, key: [u8; 32]) -> Result<Vec<Revision>, SyncError> {
+        let mut select = self.conn.prepare(
+            "SELECT change, cid, metadata, timestamp, block_number, log_index, tx_hash FROM revisions
+             WHERE key = ?1 ORDER BY block_number, log_index",
+        )?;
+        let rows = select.query_map(params![hex::encode(key)], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, i64>(5)?,
+                row.get::<_, String>(6)?,
+            ))
+        })?;
+        let mut revisions = Vec::new();
+        for row in rows {
+            let (change, cid, metadata, timestamp, block_number, log_index, tx_hash) = row?;
+            let mut hash = [0u8; 32];
+            hex::decode_to_slice(&tx_hash, &mut hash)
+                .map_err(|_| SyncError::History(format!("Invalid transaction hash {:?}", tx_hash)))?;
+            revisions.push(Revision {
+                key,
+                change: change.parse()?,
+                cid: cid.map(|cid| decode_cid(&cid)).transpose()?,
+                metadata,
+                timestamp: timestamp as u64,
+                block_number: block_number as u64,
+                log_index: log_index as u64,
+                tx_hash: hash,
+            });
+        }
+        Ok(revisions)
+    }
+
+    fn meta(&self, name: &str) -> Result<Option<String>, SyncError> {
+        Ok(self
+            .conn
+            .query_row("SELECT value FROM meta WHERE name = ?1", params![name], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn set_meta(&self, name: &str, value: &str) -> Result<(), SyncError> {
+        self.conn
+            .execute("INSERT OR REPLACE INTO meta (name, value) VALUES (?1, ?2)", params![name, value])?;
+        Ok(())
+    }
+}
+
+/// Bring the index up to the latest block, reading logs a page of blocks at a time.
+/// Picks up after the last block indexed, and halves the page when the node refuses a range,
+/// as providers cap how many blocks or logs one request may cover
+/// # Arguments
+/// * `source` - Where to read events from
+/// * `history` - The index
+/// * `deploy_block` - The block the contract was deployed in. Nothing before it is read
+/// * `page_size` - How many blocks to read at once
+/// # Returns
+/// * `Result<usize, SyncError>` - How many revisions were added
+pub async fn index(
+    source: &dyn EventSource,
+    history: &mut History,
+    deploy_block: u64,
+    page_size: u64,
+) -> Result<usize, SyncError> {
+    let head = source.block_number().await?;
+    let mut from_block = match history.last_block()? {
+        Some(last_block) => (last_block + 1).max(deploy_block),
+        None => deploy_block,
+    };
+    let mut page_size = page_size.max(1);
+    let mut added = 0;
+    while from_block <= head {
+        let to_block = head.min(from_block.saturating_add(page_size - 1));
+        match source.revisions(from_block, to_block).await {
+            Ok(revisions) => {
+                history.record(&revisions, to_block)?;
+                added += revisions.len();
+                from_block = to_block + 1;
+            }
+            Err(SyncError::Rpc(_)) if to_block > from_block => page_size = (to_block - from_block).div_ceil(2),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Events spread over blocks, behind a node that refuses ranges of more than `max_range` blocks
+    struct Chain {
+        head: u64,
+        revisions: Vec<Revision>,
+        max_range: u64,
+        requests: Mutex<Vec<(u64, u64)>>,
+    }
+
+    #[async_trait]
+    impl EventSource for Chain {
+        async fn block_number(&self) -> Result<u64, SyncError> {
+            Ok(self.head)
+        }
+
+        async fn revisions(&self, from_block: u64, to_block: u64) -> Result<Vec<Revision>, SyncError> {
+            self.requests.lock().unwrap().push((from_block, to_block));
+            if to_block - from_block + 1 > self.max_range {
+                return Err(SyncError::Rpc("block range is too wide".to_string()));
+            }
+            Ok(self
+                .revisions
+                .iter()
+                .filter(|revision| (from_block..=to_block).contains(&revision.block_number))
+                .cloned()
+                .collect())
+        }
+    }
+
+    fn revision(key: u8, change: Change, block_number: u64) -> Revision {
+        let metadata = match change {
+            Change::Delete => String::new(),
+            _ => format!("{{\"title\":\"Revision {}\"}}", block_number),
+        };
+        Revision {
+            key: [key; 32],
+            change,
+            cid: (change != Change::Delete).then(|| Cid::from(block_number.to_string().as_bytes())),
+            metadata,
+            timestamp: 1_675_209_600 + block_number,
+            block_number,
+            log_index: 0,
+            tx_hash: [block_number as u8; 32],
+        }
+    }
+
+    #[tokio::test]
+    /// The index reads every page from the deploy block on, narrowing pages the node refuses
+    async fn test_index() {
+        let revisions = vec![
+            revision(1, Change::Create, 10),
+            revision(2, Change::Create, 12),
+            revision(1, Change::Update, 25),
+            revision(1, Change::Delete, 40),
+        ];
+        let mut chain = Chain {
+            head: 45,
+            revisions: revisions.clone(),
+            max_range: 8,
+            requests: Mutex::new(Vec::new()),
+        };
+        let mut history = History::in_memory("0xABC").unwrap();
+        assert_eq!(index(&chain, &mut history, 10, 20).await.unwrap(), 4);
+        assert_eq!(history.last_block().unwrap(), Some(45));
+        let requests = chain.requests.lock().unwrap().clone();
+        assert_eq!(requests[..3], [(10, 29), (10, 19), (10, 14)]);
+        assert!(requests.iter().all(|(from, _)| *from >= 10));
+        let file = history.revisions([1; 32]).unwrap();
+        assert_eq!(file, vec![revisions[0].clone(), revisions[2].clone(), revisions[3].clone()]);
+        assert_eq!(history.revisions([2; 32]).unwrap(), vec![revisions[1].clone()]);
+
+        // Later, only new blocks are read
+        chain.head = 50;
+        chain.revisions.push(revision(2, Change::Update, 48));
+        chain.requests.lock().unwrap().clear();
+        assert_eq!(index(&chain, &mut history, 10, 20).await.unwrap(), 1);
+        assert_eq!(chain.requests.lock().unwrap()[0].0, 46);
+        assert_eq!(history.revisions([2; 32]).unwrap().len(), 2);
+
+        // Recording a revision twice keeps one copy
+        history.record(&chain.revisions, 50).unwrap();
+        assert_eq!(history.revisions([1; 32]).unwrap().len(), 3);
+    }
+
+    #[test]
+    /// An index belongs to one contract
+    fn test_open() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("history.db");
+        let mut history = History::open(&path, "0xABC").unwrap();
+        history.record(&[revision(1, Change::Create, 1)], 1).unwrap();
+        drop(history);
+        let history = History::open(&path, "0xabc").unwrap();
+        assert_eq!(history.last_block().unwrap(), Some(1));
+        assert_eq!(history.revisions([1; 32]).unwrap().len(), 1);
+        assert!(matches!(History::open(&path, "0xdef"), Err(SyncError::History(_))));
+    }
+
+    #[test]
+    /// Revisions print their time, change, CID and transaction, then their metadata
+    fn test_display() {
+        let shown = revision(1, Change::Update, 2).to_string();
+        assert!(shown.starts_with("2023-02-01T00:00:02Z update "), "{}", shown);
+        assert!(shown.contains(&format!("(block 2, tx 0x{})", hex::encode([2u8; 32]))));
+        assert!(shown.ends_with("\n    {\"title\":\"Revision 2\"}"));
+        let shown = revision(1, Change::Delete, 3).to_string();
+        assert!(!shown.contains('\n'));
+    }
+
+    #[test]
+    /// Metadata that isn't JSON is indexed and shown as it is, rather than stopping the index
+    fn test_raw_metadata() {
+        let mut raw = revision(1, Change::Create, 1);
+        raw.metadata = "title=Hello".to_string();
+        let mut history = History::in_memory("0xABC").unwrap();
+        history.record(&[raw.clone(), revision(1, Change::Update, 2)], 2).unwrap();
+        let revisions = history.revisions([1; 32]).unwrap();
+        assert_eq!(revisions[0], raw);
+        assert_eq!(revisions[0].parsed_metadata(), None);
+        assert!(revisions[0].to_string().ends_with("\n    title=Hello"));
+        assert_eq!(revisions[1].parsed_metadata().unwrap()["title"], "Revision 2");
+    }
+}
@@ -1,4 +1,6 @@
+pub mod config;
+pub mod error;
 pub mod types;
 pub mod utils;
 pub mod crud_fs;
-
+pub mod history;
@@ -0,0 +1,246 @@
+/* Manifest Lock */
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use sync::error::{Result, SyncError};
+
+/// How long to wait for another sync to release the manifest
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often to retry a held lock
+const LOCK_RETRY: Duration = Duration::from_millis(100);
+
+lazy_static! {
+    /// The lock files this process holds, so they can be released on `std::process::exit`
+    static ref HELD: Mutex<Vec<(PathBuf, File)>> = Mutex::new(Vec::new());
+}
+
+/// Who holds a lock. Written into the lock file, and cleared on release
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockHolder {
+    /// The process holding the lock
+    pub pid: u32,
+    /// The subcommand it is running, e.g. `push`
+    pub command: String,
+    /// When it took the lock, as RFC 3339
+    pub since: String,
+}
+
+impl std::fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "process {} (`sync {}`, since {})", self.pid, self.command, self.since)
+    }
+}
+
+/// ManifestLock - An advisory lock (flock) on `<manifest>.lock`, so the CLI and the daemon
+/// don't overwrite each other's changes. Hold it from reading the manifest until it is written.
+/// The kernel drops the lock if we crash, but the holder recorded in the file stays behind,
+/// which is how we detect, and report, a sync that didn't finish
+pub struct ManifestLock {
+    path: PathBuf,
+    file: File,
+    stale: Option<LockHolder>,
+}
+
+impl ManifestLock {
+    /// Take the lock on a manifest, waiting for whoever holds it
+    /// # Arguments
+    /// * `manifest_path` - The path to the manifest file
+    /// * `command` - What we are about to do, to tell anyone waiting on us
+    /// * `timeout` - How long to wait
+    /// # Returns
+    /// * `Result<ManifestLock, SyncError>` - A manifest error naming the holder if we timed out
+    pub fn acquire(manifest_path: &Path, command: &str, timeout: Duration) -> Result<Self, SyncError> {
+        let path = lock_path(manifest_path);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| SyncError::Manifest(format!("Could not open {}: {}", path.display(), e)))?;
+        let start = Instant::now();
+        let mut waiting = false;
+        loop {
+            if try_flock(&file).map_err(|e| SyncError::Manifest(format!("Could not lock {}: {}", path.display(), e)))? {
+                break;
+            }
+            let holder = read_holder(&mut file);
+            if start.elapsed() >= timeout {
+                return Err(SyncError::Manifest(match holder {
+                    Some(holder) if !is_running(holder.pid) => format!(
+                        "{} is held by {}, which is no longer running. If no other sync is running, delete it",
+                        path.display(),
+                        holder
+                    ),
+                    Some(holder) => format!("{} is held by {}", path.display(), holder),
+                    None => format!("{} is held by another process", path.display()),
+                }));
+            }
+            if !waiting {
+                waiting = true;
+                match &holder {
+                    Some(holder) => eprintln!("Waiting for {}, held by {}", path.display(), holder),
+                    None => eprintln!("Waiting for {}", path.display()),
+                }
+            }
+            std::thread::sleep(LOCK_RETRY);
+        }
+        // Anyone recorded here exited without releasing the lock
+        let stale = read_holder(&mut file);
+        let holder = LockHolder {
+            pid: std::process::id(),
+            command: command.to_string(),
+            since: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        };
+        write_holder(&mut file, Some(&holder))
+            .map_err(|e| SyncError::Manifest(format!("Could not write {}: {}", path.display(), e)))?;
+        if let Ok(clone) = file.try_clone() {
+            HELD.lock().unwrap().push((path.clone(), clone));
+        }
+        Ok(Self { path, file, stale })
+    }
+
+    /// The holder of a lock that was never released, if we found one. Its sync was interrupted,
+    /// so the manifest may be missing what it pushed
+    pub fn stale(&self) -> Option<&LockHolder> {
+        self.stale.as_ref()
+    }
+}
+
+impl Drop for ManifestLock {
+    fn drop(&mut self) {
+        HELD.lock().unwrap().retain(|(path, _)| path != &self.path);
+        // Closing the file releases the flock
+        let _ = write_holder(&mut self.file, None);
+    }
+}
+
+/// Release every lock this process holds. `std::process::exit` skips destructors, so call this
+/// first, or our lock looks stale to the next sync
+pub fn release_all() {
+    for (_, mut file) in HELD.lock().unwrap().drain(..) {
+        let _ = write_holder(&mut file, None);
+    }
+}
+
+/// The lock file of a manifest: `manifest.json.lock` next to `manifest.json`
+/// # Arguments
+/// * `manifest_path` - The path to the manifest file
+pub fn lock_path(manifest_path: &Path) -> PathBuf {
+    let mut file_name = manifest_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".lock");
+    manifest_path.with_file_name(file_name)
+}
+
+/// Try to take an exclusive flock without blocking
+/// # Returns
+/// * `std::io::Result<bool>` - Whether we got it
+#[cfg(unix)]
+fn try_flock(file: &File) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    let res = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if res == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+/// Try to take an exclusive lock without blocking. Off unix there is no flock, so use the
+/// platform's own file lock, which is also dropped when the holder exits
+/// # Returns
+/// * `std::io::Result<bool>` - Whether we got it
+#[cfg(not(unix))]
+fn try_flock(file: &File) -> std::io::Result<bool> {
+    match file.try_lock() {
+        Ok(()) => Ok(true),
+        Err(std::fs::TryLockError::WouldBlock) => Ok(false),
+        Err(std::fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Whether a process is running
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let res = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether a process is running. Off unix we can't tell, so assume it is, and name it without
+/// suggesting the lock be deleted
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+/// Read who the lock file says holds it
+fn read_holder(file: &mut File) -> Option<LockHolder> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Record, or with None clear, who holds the lock
+fn write_holder(file: &mut File, holder: Option<&LockHolder>) -> std::io::Result<()> {
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    if let Some(holder) = holder {
+        file.write_all(serde_json::to_string(holder)?.as_bytes())?;
+    }
+    file.sync_data()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// A held lock keeps others out until it is dropped, and a crashed holder is reported
+    fn test_lock() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let manifest_path = dir.join("manifest.json");
+        assert_eq!(lock_path(&manifest_path), dir.join("manifest.json.lock"));
+
+        let lock = ManifestLock::acquire(&manifest_path, "push", Duration::ZERO).unwrap();
+        assert_eq!(lock.stale(), None);
+        let err = ManifestLock::acquire(&manifest_path, "daemon", Duration::ZERO).err().unwrap();
+        assert!(matches!(err, SyncError::Manifest(_)));
+        assert!(err.to_string().contains(&format!("process {} (`sync push`", std::process::id())));
+        drop(lock);
+        let lock = ManifestLock::acquire(&manifest_path, "daemon", Duration::ZERO).unwrap();
+        assert_eq!(lock.stale(), None);
+        drop(lock);
+
+        // A holder that exited without releasing the lock leaves itself behind
+        #[cfg(unix)]
+        {
+            let mut child = std::process::Command::new("true").spawn().unwrap();
+            let pid = child.id();
+            child.wait().unwrap();
+            let holder = LockHolder {
+                pid,
+                command: "push".to_string(),
+                since: "2023-02-01T00:00:00Z".to_string(),
+            };
+            std::fs::write(lock_path(&manifest_path), serde_json::to_string(&holder).unwrap()).unwrap();
+            let lock = ManifestLock::acquire(&manifest_path, "push", Duration::ZERO).unwrap();
+            assert_eq!(lock.stale(), Some(&holder));
+            assert!(!is_running(pid));
+            drop(lock);
+            assert_eq!(std::fs::read_to_string(lock_path(&manifest_path)).unwrap(), "");
+        }
+    }
+}
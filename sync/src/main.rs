@@ -1,26 +1,41 @@
 use clap::Parser;
-use std::io::stdin;
-use std::path::PathBuf;
-use tokio;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
 use lazy_static::lazy_static;
 
 mod manifest;
+mod lock;
 mod args;
+mod status;
+mod plan;
+mod daemon;
+use ethers::{signers::Signer, utils::{hex, to_checksum}};
 use sync::{
-    crud_fs::crud_fs::CrudFs,
+    config::{Config, ConfigLayer, SignerSource},
+    crud_fs::backend::{ReadOnlyBackend, SyncSigner},
+    error::SyncError,
+    history::{self, History},
     types::{
         cid::Cid,
-        metadata::Metadata,
-        crud_file::CrudFile
-    }
+        metadata::{Metadata, ALIASES, MOVED_TO},
+    },
+    utils::{car::Car, front_matter, hash::hash_path, keystore, path::ContentRoot, walk::walk_dir},
 };
 
 use crate::{
-    manifest::Manifest,
+    manifest::{Manifest, MANIFEST_VERSION},
+    lock::{ManifestLock, LOCK_TIMEOUT},
     args::{
         SyncArgs,
-        SubcommandType
-    }
+        SubcommandType,
+        ConfigArgs,
+        ConfigCommand,
+        WalletCommand,
+        ManifestCommand,
+    },
+    plan::Action,
+    status::Status,
+    daemon::Daemon,
 };
 
 lazy_static! {
@@ -32,6 +47,18 @@ lazy_static! {
 async fn main() {
     // Parse the command line arguments
     let args = SyncArgs::parse();
+    // Report warnings on stderr
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+    // Resolve the configuration
+    let config = load_config(&args.config).unwrap_or_else(|e| match args.subcommand {
+        // `cat` keeps stdout for content
+        SubcommandType::Cat(_) => fail_to_stderr("Could not load config", e),
+        _ => fail("Could not load config", e),
+    });
+    // Files are tracked by their path from the content root
+    let content_root = ContentRoot::from_config(&config);
     // Execute the subcommand
     match args.subcommand {
         SubcommandType::Create(args) => {
@@ -40,42 +67,767 @@ async fn main() {
             let path = args.path;
             // Get the Metadata from the args
             let metadata: Metadata = match args.metadata {
-                Some(metadata) => serde_json::from_str(&metadata).unwrap(),
-                None => serde_json::from_str("{}").unwrap(),
+                Some(metadata) => serde_json::from_str(&metadata)
+                    .unwrap_or_else(|e| fail("Invalid metadata", SyncError::from(e))),
+                None => Metadata::new(),
             };
-            // Get the manifest
+            // Read the rest from the file's front matter
+            let metadata = front_matter::metadata(&path, Metadata::new(), metadata)
+                .unwrap_or_else(|e| fail("Could not read front matter", e));
+            // Get the manifest, and hold it until we're done
+            let manifest_path = args.manifest.clone().unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let _lock = lock_manifest(&manifest_path, "create");
             let mut manifest: Manifest = match args.manifest {
-                Some(manifest_path) => Manifest::read(&manifest_path).unwrap(),
-                None => Manifest::read(&DEFAULT_MANIFEST_PATH).unwrap_or_else(|_| {
-                    Manifest::new("".to_string()).write(&DEFAULT_MANIFEST_PATH).unwrap();
+                Some(manifest_path) => Manifest::read(&manifest_path, &content_root)
+                    .unwrap_or_else(|e| fail("Could not read manifest", e)),
+                None => Manifest::read(&DEFAULT_MANIFEST_PATH, &content_root).unwrap_or_else(|_| {
+                    Manifest::new(config.contract_address.clone().unwrap_or_default())
+                        .write(&DEFAULT_MANIFEST_PATH)
+                        .unwrap_or_else(|e| fail("Could not write manifest", e));
                     println!("Manifest Uninitialized");
                     println!("I went and made a template ror you, go fill it out!");
-                    std::process::exit(0);
+                    exit(0);
                 }),
             };
             // Check if the file already exists
-            if manifest.contains(&path).unwrap() {
+            let content_path = content_root.relative(&path).unwrap_or_else(|e| fail("Invalid path", e));
+            if manifest.contains(&content_path).unwrap_or_else(|e| fail("Invalid path", e)) {
                 println!("File already exists in the manifest");
-                println!("Eventually you will be able to update the file (this is a todo)");
-                std::process::exit(0);
+                println!("Use `update` to push changes to the file");
+                exit(0);
             }
             // Initialize the CrudFs
-            let mut crud_fs = CrudFs::new(manifest.contract_address.clone());
+            let crud_fs = manifest
+                .crud_fs(&config)
+                .unwrap_or_else(|e| fail("Could not initialize CrudFs", e));
             // Get the CID from the path
-            let cid = Cid::try_from(&path).unwrap();
-            println!("-> Creating with CID: {}", cid.to_string());
-            println!("-> Creating with Metadata: {}", serde_json::to_string(&metadata).unwrap());
+            let cid = crud_fs.cid(&path).unwrap_or_else(|e| fail("Could not hash file", e));
+            println!("-> Creating with CID: {}", cid);
+            let metadata_json = serde_json::to_string(&metadata).unwrap_or_else(|e| fail("Invalid metadata", e.into()));
+            println!("-> Creating with Metadata: {}", metadata_json);
             // Create a new CrudFile with CrudFs
             let crud_file= crud_fs.create(
                 path, cid, metadata
-            ).await.unwrap_or_else(|e| {
-                println!("Could not push to CrudFs: {}", e);
-                std::process::exit(0);
-            });
+            ).await.unwrap_or_else(|e| fail("Could not push to CrudFs", e));
             // Add the CrudFile to the manifest
-            let _ = manifest.add(crud_file.clone()).unwrap();
+            manifest.add(crud_file.clone()).unwrap_or_else(|e| fail("Could not update manifest", e));
+            // Write the manifest to the manifest file
+            manifest.write(&manifest_path).unwrap_or_else(|e| fail("Could not write manifest", e));
+        }
+        SubcommandType::Update(args) => {
+            println!("Updating file: {}", args.path.display());
+            // Get the path to the file to update
+            let path = args.path;
+            // Get the manifest, and hold it until we're done
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let _lock = lock_manifest(&manifest_path, "update");
+            let mut manifest = Manifest::read(&manifest_path, &content_root).unwrap_or_else(|e| fail("Could not read manifest", e));
+            // Get the tracked CrudFile for the path
+            let content_path = content_root.relative(&path).unwrap_or_else(|e| fail("Invalid path", e));
+            let mut crud_file = match manifest.get(&content_path).unwrap_or_else(|e| fail("Invalid path", e)) {
+                Some(crud_file) => crud_file.clone(),
+                None => {
+                    println!("File does not exist in the manifest");
+                    println!("Use `create` to add the file");
+                    exit(0);
+                }
+            };
+            // Merge any new Metadata, and the file's front matter, into the current Metadata
+            let overrides: Metadata = match args.metadata {
+                Some(metadata) => serde_json::from_str(&metadata)
+                    .unwrap_or_else(|e| fail("Invalid metadata", SyncError::from(e))),
+                None => Metadata::new(),
+            };
+            let metadata = front_matter::metadata(&path, crud_file.metadata.clone(), overrides)
+                .unwrap_or_else(|e| fail("Could not read front matter", e));
+            let metadata_changed = metadata != crud_file.metadata;
+            crud_file.set_metadata(metadata).unwrap_or_else(|e| fail("Invalid metadata", e));
+            // Don't send a transaction if nothing changed
+            let cid = front_matter::cid(&path, config.strip_front_matter, config.store.cid_mode)
+                .unwrap_or_else(|e| fail("Could not hash file", e));
+            if cid == crud_file.cid && !metadata_changed {
+                println!("File is already up to date");
+                exit(0);
+            }
+            // Initialize the CrudFs
+            let crud_fs = manifest
+                .crud_fs(&config)
+                .unwrap_or_else(|e| fail("Could not initialize CrudFs", e));
+            println!("-> Updating with CID: {}", cid);
+            println!("-> Updating with Metadata: {}", serde_json::to_string(&crud_file.metadata).unwrap());
+            // Update the CrudFile with CrudFs
+            let crud_file = crud_fs.update(crud_file).await.unwrap_or_else(|e| fail("Could not update CrudFs", e));
+            // Replace the CrudFile in the manifest
+            manifest.add(crud_file).unwrap_or_else(|e| fail("Could not update manifest", e));
+            // Write the manifest to the manifest file
+            manifest.write(&manifest_path).unwrap_or_else(|e| fail("Could not write manifest", e));
+        }
+        SubcommandType::Rm(args) => {
+            println!("Removing file: {}", args.path.display());
+            // Get the path to the file to remove
+            let path = args.path;
+            // Get the manifest, and hold it until we're done
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let _lock = lock_manifest(&manifest_path, "rm");
+            let mut manifest = Manifest::read(&manifest_path, &content_root).unwrap_or_else(|e| fail("Could not read manifest", e));
+            // Check that the file is tracked
+            let content_path = content_root.relative(&path).unwrap_or_else(|e| fail("Invalid path", e));
+            let key = match manifest.get(&content_path).unwrap_or_else(|e| fail("Invalid path", e)) {
+                Some(tracked) => tracked.key,
+                None => {
+                    println!("File does not exist in the manifest");
+                    exit(0);
+                }
+            };
+            // Initialize the CrudFs
+            let crud_fs = manifest
+                .crud_fs(&config)
+                .unwrap_or_else(|e| fail("Could not initialize CrudFs", e));
+            // Delete the CrudFile with CrudFs
+            // By the key it is tracked under, which is its key on chain
+            let crud_file = crud_fs.delete_key(key).await.unwrap_or_else(|e| fail("Could not delete from CrudFs", e));
+            println!("-> Removed CID: {}", crud_file.cid);
+            // The file is gone from the chain, so stop tracking it before anything else can fail
+            manifest.rm(&content_path).unwrap_or_else(|e| fail("Could not update manifest", e));
+            // Write the manifest to the manifest file
+            manifest.write(&manifest_path).unwrap_or_else(|e| fail("Could not write manifest", e));
+            // Optionally unpin its content. Failing to is only a warning: the delete went through
+            if args.unpin {
+                match crud_fs.unpin(crud_file.cid.clone()).await {
+                    Ok(()) => println!("-> Unpinned CID: {}", crud_file.cid),
+                    Err(e) => println!("Warning: could not unpin {}: {}", crud_file.cid, e),
+                }
+            }
+            // Keep the file on disk unless asked to purge it
+            let keep_local = args.keep_local || !args.purge_local;
+            if !keep_local {
+                std::fs::remove_file(&path).unwrap_or_else(|e| {
+                    println!("Could not remove local file: {}", e);
+                });
+            }
+        }
+        SubcommandType::Mv(args) => {
+            println!("Moving file: {} -> {}", args.from.display(), args.to.display());
+            // Get the manifest, and hold it until we're done
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let _lock = lock_manifest(&manifest_path, "mv");
+            let mut manifest = Manifest::read(&manifest_path, &content_root).unwrap_or_else(|e| fail("Could not read manifest", e));
+            // Check that the file is tracked
+            let content_path = content_root.relative(&args.from).unwrap_or_else(|e| fail("Invalid path", e));
+            if !manifest.contains(&content_path).unwrap_or_else(|e| fail("Invalid path", e)) {
+                println!("File does not exist in the manifest");
+                exit(0);
+            }
+            // Move the file on disk, unless an earlier attempt already did
+            match (args.from.exists(), args.to.exists()) {
+                (true, false) => {
+                    if let Some(parent) = args.to.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                        std::fs::create_dir_all(parent).unwrap_or_else(|e| fail("Could not create directory", e.into()));
+                    }
+                    std::fs::rename(&args.from, &args.to).unwrap_or_else(|e| fail("Could not move file", e.into()));
+                }
+                (false, true) => println!("-> Already moved on disk"),
+                (true, true) => fail("Could not move file", SyncError::Path(format!("{} already exists", args.to.display()))),
+                (false, false) => fail("Could not move file", SyncError::Path(format!("{} does not exist", args.from.display()))),
+            }
+            // Initialize the CrudFs
+            let crud_fs = manifest
+                .crud_fs(&config)
+                .unwrap_or_else(|e| fail("Could not initialize CrudFs", e));
+            // Move the CrudFile with CrudFs
+            let crud_file = crud_fs.rename(args.from, args.to).await.unwrap_or_else(|e| fail("Could not move in CrudFs", e));
+            println!("-> Moved to {} with CID: {}", crud_file.path.display(), crud_file.cid);
+            // Track the file at its new path
+            manifest.rm(&content_path).unwrap_or_else(|e| fail("Could not update manifest", e));
+            manifest.add(crud_file).unwrap_or_else(|e| fail("Could not update manifest", e));
+            // Write the manifest to the manifest file
+            manifest.write(&manifest_path).unwrap_or_else(|e| fail("Could not write manifest", e));
+        }
+        SubcommandType::Get(args) => {
+            println!("Getting file: {}", args.path.display());
+            // Get the path to the file to read, and where to put it
+            let path = args.path;
+            let output = args.output.unwrap_or_else(|| path.clone());
+            // Don't clobber local edits by accident
+            if output.exists() && !args.force {
+                println!("Use --force to overwrite it, --output to write elsewhere, or `cat` to print it");
+                fail("Refusing to overwrite", SyncError::Path(format!("{} already exists", output.display())));
+            }
+            // Get the manifest
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let manifest = Manifest::read(&manifest_path, &content_root).unwrap_or_else(|e| fail("Could not read manifest", e));
+            // Initialize the CrudFs
+            let crud_fs = manifest
+                .read_only_crud_fs(&config)
+                .unwrap_or_else(|e| fail("Could not initialize CrudFs", e));
+            if let Some(parent) = output.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent).unwrap_or_else(|e| fail("Could not create directory", e.into()));
+            }
+            // Stream the content to the output path, which is only replaced once it is verified
+            let crud_file = crud_fs
+                .read_to(path, output.clone())
+                .await
+                .unwrap_or_else(|e| fail("Could not read from CrudFs", e));
+            println!("-> Got CID: {}", crud_file.cid);
+            println!("-> Wrote to: {}", output.display());
+        }
+        SubcommandType::Cat(args) => {
+            // Get the manifest
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let manifest = Manifest::read(&manifest_path, &content_root).unwrap_or_else(|e| fail_to_stderr("Could not read manifest", e));
+            // Initialize the CrudFs
+            let crud_fs = manifest
+                .read_only_crud_fs(&config)
+                .unwrap_or_else(|e| fail_to_stderr("Could not initialize CrudFs", e));
+            // Stream the content to a temp file, so nothing reaches stdout until it is verified
+            let temp_file = tempfile::NamedTempFile::new()
+                .unwrap_or_else(|e| fail_to_stderr("Could not create a temp file", e.into()));
+            // Then copy it to stdout, a chunk at a time
+            let copied = match crud_fs.read_to(args.path, temp_file.path().to_path_buf()).await {
+                Ok(_) => std::fs::File::open(temp_file.path())
+                    .and_then(|mut file| std::io::copy(&mut file, &mut std::io::stdout().lock()))
+                    .map_err(|e| ("Could not write to stdout", e.into())),
+                Err(e) => Err(("Could not read from CrudFs", e)),
+            };
+            // Exiting skips destructors, so remove the temp file before reporting a failure
+            let _ = temp_file.close();
+            if let Err((context, e)) = copied {
+                fail_to_stderr(context, e);
+            }
+        }
+        SubcommandType::Pull(args) => {
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let _lock = lock_manifest(&manifest_path, "pull");
+            let existing = manifest_path
+                .exists()
+                .then(|| Manifest::read(&manifest_path, &content_root).unwrap_or_else(|e| fail("Could not read manifest", e)));
+            // Use the configured contract address, or fall back on the one in the manifest
+            let contract_address = match (&config.contract_address, &existing) {
+                (Some(contract_address), _) => contract_address.clone(),
+                (None, Some(manifest)) => manifest.contract_address.clone(),
+                (None, None) => {
+                    println!("No manifest found, pass --contract-address to pull");
+                    fail("Could not read manifest", SyncError::Manifest(format!("{} does not exist", manifest_path.display())))
+                }
+            };
+            println!("Pulling from contract: {}", contract_address);
+            // Merge into the manifest we have, so an interrupted pull never loses its entries.
+            // A manifest of another contract is started afresh
+            let mut manifest = match existing {
+                Some(manifest) if manifest.contract_address.eq_ignore_ascii_case(&contract_address) => manifest,
+                _ => Manifest::new(contract_address),
+            };
+            // Initialize the CrudFs
+            let crud_fs = manifest
+                .read_only_crud_fs(&config)
+                .unwrap_or_else(|e| fail("Could not initialize CrudFs", e));
+            // List every file in the backend
+            let crud_files = crud_fs.list().await.unwrap_or_else(|e| fail("Could not list CrudFs", e));
+            println!("-> Found {} files", crud_files.len());
+            let mut pulled = HashSet::new();
+            for crud_file in crud_files {
+                let path = crud_file.path.clone();
+                // Don't write outside of the working directory
+                if path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+                    println!("-> Skipping {}: path is not relative", path.display());
+                    continue;
+                }
+                // Moved files are pulled at their new path
+                if let Some(moved_to) = crud_file.moved_to() {
+                    println!("-> Skipping {}: moved to {}", path.display(), moved_to.display());
+                    continue;
+                }
+                // Skip files we already have
+                let local = content_root.local(&path);
+                let up_to_date = match front_matter::cid(&local, config.strip_front_matter, config.store.cid_mode) {
+                    Ok(cid) => cid == crud_file.cid,
+                    Err(_) => false,
+                };
+                if up_to_date {
+                    println!("-> Up to date: {}", path.display());
+                } else {
+                    println!("-> Pulling {} ({})", path.display(), crud_file.cid);
+                    if let Some(parent) = local.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                        std::fs::create_dir_all(parent).unwrap_or_else(|e| fail("Could not create directory", e.into()));
+                    }
+                    crud_fs
+                        .download(crud_file.cid.clone(), local.clone())
+                        .await
+                        .unwrap_or_else(|e| fail(&format!("Could not fetch {}", path.display()), e));
+                    restore_front_matter(&config, &local, &crud_file.metadata);
+                }
+                // Record progress as we go. Entries are only added here, so running pull again
+                // after an interruption picks up where it stopped
+                pulled.insert(hex::encode(crud_file.key));
+                manifest.add(crud_file).unwrap_or_else(|e| fail("Could not update manifest", e));
+                manifest.write(&manifest_path).unwrap_or_else(|e| fail("Could not write manifest", e));
+            }
+            // Once everything is pulled, drop the entries that are no longer on chain
+            manifest.files.retain(|key, _| pulled.contains(key));
+            manifest.write(&manifest_path).unwrap_or_else(|e| fail("Could not write manifest", e));
+        }
+        SubcommandType::Status(args) => {
+            // Get the manifest
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let manifest = Manifest::read(&manifest_path, &content_root).unwrap_or_else(|e| fail("Could not read manifest", e));
+            // Find untracked files, if we were given a directory
+            let local_files = match args.dir.or_else(|| config.content_root.clone()) {
+                Some(dir) => walk_dir(&dir)
+                    .and_then(|files| plan::without_own_files(files, &plan::own_files(&config, &manifest_path)))
+                    .unwrap_or_else(|e| fail("Could not read directory", e)),
+                None => Vec::new(),
+            };
+            // Initialize the CrudFs
+            let crud_fs = manifest
+                .read_only_crud_fs(&config)
+                .unwrap_or_else(|e| fail("Could not initialize CrudFs", e));
+            // List every file in the backend
+            let chain_files = crud_fs.list().await.unwrap_or_else(|e| fail("Could not list CrudFs", e));
+            // Report every path that is out of sync
+            let (strip_front_matter, cid_mode) = (config.strip_front_matter, config.store.cid_mode);
+            let report = status::status(&manifest, &content_root, chain_files, local_files, strip_front_matter, cid_mode)
+                .unwrap_or_else(|e| fail("Could not compare", e));
+            let mut in_sync = true;
+            let mut unfinished = false;
+            for (path, statuses) in report {
+                for status in statuses {
+                    in_sync = false;
+                    unfinished |= status == Status::MoveUnfinished;
+                    println!("{:>18}: {}", status, path.display());
+                }
+            }
+            if in_sync {
+                println!("Everything is in sync");
+            }
+            if unfinished {
+                println!("Run `sync push` to finish the moves");
+            }
+            if !manifest.stale().is_empty() {
+                println!("Files with a stale key were created before paths were normalized; see `sync manifest migrate`");
+            }
+        }
+        SubcommandType::Log(args) => {
+            // Use the configured contract address, or fall back on the one in the manifest
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let contract_address = match &config.contract_address {
+                Some(contract_address) => contract_address.clone(),
+                None => Manifest::read(&manifest_path, &content_root)
+                    .map(|manifest| manifest.contract_address)
+                    .unwrap_or_else(|e| fail("Could not read manifest", e)),
+            };
+            let path = content_root.relative(&args.path).unwrap_or_else(|e| fail("Invalid path", e));
+            let mut history = History::open(&config.history_path, &contract_address)
+                .unwrap_or_else(|e| fail("Could not open history", e));
+            // Catch the index up with the chain
+            if !args.offline {
+                let mut config = config.clone();
+                config.contract_address = Some(contract_address);
+                let backend = ReadOnlyBackend::from_config(&config)
+                    .unwrap_or_else(|e| fail("Could not initialize backend", e));
+                let added = history::index(&backend, &mut history, config.deploy_block, config.log_page_size)
+                    .await
+                    .unwrap_or_else(|e| fail("Could not index logs", e));
+                if added > 0 {
+                    println!("Indexed {} new revisions", added);
+                }
+            }
+            let key = hash_path(&path).unwrap_or_else(|e| fail("Invalid path", e));
+            let revisions = history.revisions(key).unwrap_or_else(|e| fail("Could not read history", e));
+            if revisions.is_empty() {
+                println!("No history for {}", path.display());
+                exit(0);
+            }
+            for revision in &revisions {
+                println!("{}", revision);
+            }
+            // Point at the rest of the history of a file that moved
+            let metadata = revisions.iter().rev().filter_map(|revision| revision.parsed_metadata()).find(|m| !m.is_empty());
+            if let Some(moved_to) = metadata.as_ref().and_then(|metadata| metadata.get(MOVED_TO)) {
+                println!("Moved to {}; see `sync log {}`", moved_to, moved_to);
+            } else if let Some(aliases) = metadata.as_ref().and_then(|metadata| metadata.get(ALIASES)) {
+                let aliases: Vec<String> = serde_json::from_str(aliases).unwrap_or_default();
+                println!("Moved from {}; see `sync log` of each for their history", aliases.join(", "));
+            }
+        }
+        SubcommandType::Push(args) => {
+            let dir = content_dir(args.dir, &config);
+            println!("Pushing directory: {}", dir.display());
+            // Get the manifest, and hold it until we're done. A dry run doesn't write it
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let _lock = (!args.dry_run).then(|| lock_manifest(&manifest_path, "push"));
+            let mut manifest = Manifest::read(&manifest_path, &content_root).unwrap_or_else(|e| fail("Could not read manifest", e));
+            // Work out what needs to change
+            let local_files = walk_dir(&dir).unwrap_or_else(|e| fail("Could not read directory", e));
+            let own_files = plan::own_files(&config, &manifest_path);
+            let (strip_front_matter, cid_mode) = (config.strip_front_matter, config.store.cid_mode);
+            let plan = |manifest: &Manifest, local_files| {
+                plan::plan(manifest, &content_root, &dir, local_files, &own_files, strip_front_matter, cid_mode)
+                    .unwrap_or_else(|e| fail("Could not plan push", e))
+            };
+            let mut actions = plan(&manifest, local_files.clone());
+            if actions.is_empty() {
+                println!("Everything is up to date");
+                exit(0);
+            }
+            // Initialize the CrudFs
+            let crud_fs = manifest
+                .crud_fs(&config)
+                .unwrap_or_else(|e| fail("Could not initialize CrudFs", e));
+            // Finish any move a previous run was interrupted in, then plan what is left
+            if !args.dry_run {
+                let finished = plan::finish_moves(&crud_fs, &mut manifest, &actions).await;
+                manifest.write(&manifest_path).unwrap_or_else(|e| fail("Could not write manifest", e));
+                if !finished.unwrap_or_else(|e| fail("Could not finish move", e)).is_empty() {
+                    actions = plan(&manifest, local_files);
+                }
+            }
+            if args.dry_run {
+                let mut total_gas = ethers::types::U256::zero();
+                for action in actions {
+                    let gas = match &action {
+                        Action::Create { path, cid, metadata } => {
+                            crud_fs.estimate_create(path.clone(), cid.clone(), metadata.clone()).await
+                        }
+                        Action::Update { crud_file, cid } => {
+                            crud_fs.estimate_update(crud_file, cid.clone()).await
+                        }
+                        Action::Rename { crud_file, path } => {
+                            crud_fs.estimate_rename(content_root.local(&crud_file.path), path.clone()).await
+                        }
+                        Action::Delete { crud_file } => {
+                            crud_fs.estimate_delete(content_root.local(&crud_file.path)).await
+                        }
+                    }
+                    .unwrap_or_else(|e| fail(&format!("Could not estimate gas for {}", action), e));
+                    total_gas += gas;
+                    println!("-> {} ~{} gas", action, gas);
+                }
+                let gas_price = crud_fs.gas_price().await.unwrap_or_else(|e| fail("Could not get gas price", e));
+                println!("Estimated gas: {}", total_gas);
+                println!(
+                    "Estimated cost: {} at {} gwei",
+                    ethers::utils::format_ether(total_gas * gas_price),
+                    ethers::utils::format_units(gas_price, "gwei")
+                        .unwrap_or_else(|e| fail("Could not format the gas price", SyncError::Decode(e.to_string())))
+                );
+                exit(0);
+            }
+            // Stop on the first failure, but keep what we already pushed
+            let result = plan::apply(&crud_fs, &mut manifest, actions).await;
             // Write the manifest to the manifest file
-            let _ = manifest.write(&DEFAULT_MANIFEST_PATH).unwrap();
+            manifest.write(&manifest_path).unwrap_or_else(|e| fail("Could not write manifest", e));
+            if let Err(e) = result {
+                fail("Could not push to CrudFs", e);
+            }
+        }
+        SubcommandType::Daemon(args) => {
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let daemon = Daemon::new(
+                content_dir(args.dir, &config),
+                manifest_path,
+                std::time::Duration::from_millis(args.debounce),
+                config,
+            );
+            daemon.run().await.unwrap_or_else(|e| fail("Daemon failed", e));
+        }
+        SubcommandType::Export(args) => {
+            println!("Exporting to: {}", args.car.display());
+            // Get the manifest
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let manifest = Manifest::read(&manifest_path, &content_root).unwrap_or_else(|e| fail("Could not read manifest", e));
+            // Export in a stable order
+            let mut crud_files: Vec<_> = manifest.files.values().collect();
+            crud_files.sort_by(|a, b| a.path.cmp(&b.path));
+            let mut car = Car::new(Vec::new());
+            let mut seen = HashSet::new();
+            for crud_file in crud_files {
+                // Rebuild the file's blocks from disk. They have to match what we pushed
+                let blocks = front_matter::content(&content_root.local(&crud_file.path), config.strip_front_matter)
+                    .and_then(|data| crud_file.cid.blocks(&data))
+                    .unwrap_or_else(|e| {
+                        println!("Run `sync push` or `sync pull` first");
+                        fail(&format!("Could not export {}", crud_file.path.display()), e)
+                    });
+                println!("-> {} ({} blocks)", crud_file.path.display(), blocks.len());
+                car.roots.push(blocks.last().unwrap().0);
+                // Files can share blocks, but each only needs to be written once
+                for (cid, block) in blocks {
+                    if seen.insert(cid) {
+                        car.add(cid, block);
+                    }
+                }
+            }
+            let bytes = if args.v2 { car.to_v2() } else { car.to_v1() };
+            std::fs::write(&args.car, bytes).unwrap_or_else(|e| fail("Could not write CAR", e.into()));
+            println!("-> Wrote {} files in {} blocks", car.roots.len(), car.blocks.len());
+        }
+        SubcommandType::Import(args) => {
+            println!("Importing from: {}", args.car.display());
+            // Get the manifest
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let _lock = lock_manifest(&manifest_path, "import");
+            let manifest = Manifest::read(&manifest_path, &content_root).unwrap_or_else(|e| fail("Could not read manifest", e));
+            // Read the CAR, checking every block against its CID
+            let car = std::fs::read(&args.car)
+                .map_err(SyncError::from)
+                .and_then(|bytes| Car::decode(&bytes))
+                .unwrap_or_else(|e| fail("Could not read CAR", e));
+            let blocks: HashMap<_, _> = car.blocks.into_iter().collect();
+            for root in car.roots {
+                let cid = Cid::from(root);
+                // The manifest tells us where each root belongs
+                let crud_files: Vec<_> = manifest.files.values().filter(|crud_file| crud_file.cid == cid).collect();
+                if crud_files.is_empty() {
+                    println!("-> Skipping {}: not in the manifest", cid);
+                    continue;
+                }
+                let data = sync::types::unixfs::read_file(&root, &blocks).unwrap_or_else(|e| fail(&format!("Could not unpack {}", cid), e));
+                for crud_file in crud_files {
+                    let path = &crud_file.path;
+                    // Don't write outside of the working directory
+                    if path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+                        println!("-> Skipping {}: path is not relative", path.display());
+                        continue;
+                    }
+                    let local = content_root.local(path);
+                    // Leave files that already hold this content alone, and don't clobber local edits by accident
+                    if local.exists() {
+                        let up_to_date = front_matter::cid(&local, config.strip_front_matter, config.store.cid_mode)
+                            .is_ok_and(|local_cid| local_cid == cid);
+                        if up_to_date {
+                            println!("-> Up to date: {}", path.display());
+                            continue;
+                        }
+                        if !args.force {
+                            println!("-> Skipping {}: it already exists, use --force to overwrite it", path.display());
+                            continue;
+                        }
+                    }
+                    println!("-> Unpacking {} ({})", path.display(), cid);
+                    if let Some(parent) = local.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                        std::fs::create_dir_all(parent).unwrap_or_else(|e| fail("Could not create directory", e.into()));
+                    }
+                    std::fs::write(&local, &data).unwrap_or_else(|e| fail("Could not write file", e.into()));
+                    restore_front_matter(&config, &local, &crud_file.metadata);
+                }
+            }
+        }
+        SubcommandType::Config(ConfigCommand::Show) => {
+            print!("{}", config.show());
+        }
+        SubcommandType::Wallet(WalletCommand::New) => {
+            let path = Config::require(&config.keystore, "keystore", "KEYSTORE")
+                .unwrap_or_else(|e| fail("No keystore path", e));
+            let passphrase = keystore::passphrase(&path, true)
+                .unwrap_or_else(|e| fail("Could not read passphrase", e));
+            let address = keystore::create(&path, &passphrase)
+                .unwrap_or_else(|e| fail("Could not create keystore", e));
+            println!("Created keystore: {}", path.display());
+            println!("-> Address: {}", to_checksum(&address, None));
+        }
+        SubcommandType::Wallet(WalletCommand::Import) => {
+            let path = Config::require(&config.keystore, "keystore", "KEYSTORE")
+                .unwrap_or_else(|e| fail("No keystore path", e));
+            // Move a key out of the environment, or take it from the terminal without echoing it
+            let private_key = match &config.private_key {
+                Some(private_key) => {
+                    println!("Importing the private key from PRIVATE_KEY");
+                    private_key.clone()
+                }
+                None => dialoguer::Password::new()
+                    .with_prompt("Private key")
+                    .interact()
+                    .unwrap_or_else(|e| fail("Could not read private key", e.into())),
+            };
+            let passphrase = keystore::passphrase(&path, true)
+                .unwrap_or_else(|e| fail("Could not read passphrase", e));
+            let address = keystore::import(&path, &private_key, &passphrase)
+                .unwrap_or_else(|e| fail("Could not import key", e));
+            println!("Created keystore: {}", path.display());
+            println!("-> Address: {}", to_checksum(&address, None));
+            println!("You can now remove PRIVATE_KEY, and set `keystore` in sync.toml");
+        }
+        SubcommandType::Wallet(WalletCommand::Address) => {
+            // Keystores usually record their address, so we don't need the passphrase
+            let recorded = match (config.signer, &config.keystore) {
+                (SignerSource::Keystore, Some(path)) => keystore::address(path)
+                    .unwrap_or_else(|e| fail("Could not read keystore", e)),
+                _ => None,
+            };
+            let address = match recorded {
+                Some(address) => address,
+                None => SyncSigner::from_config(&config, config.chain_id.unwrap_or_default())
+                    .unwrap_or_else(|e| fail("Could not load signer", e))
+                    .address(),
+            };
+            println!("{}", to_checksum(&address, None));
+        }
+        SubcommandType::Manifest(ManifestCommand::Migrate(args)) => {
+            let manifest_path = args.manifest.unwrap_or_else(|| DEFAULT_MANIFEST_PATH.clone());
+            let _lock = (!args.check).then(|| lock_manifest(&manifest_path, "manifest migrate"));
+            let (manifest, version) = Manifest::read_versioned(&manifest_path, &content_root)
+                .unwrap_or_else(|e| fail("Could not read manifest", e));
+            if version == MANIFEST_VERSION {
+                println!("{} is at version {}, nothing to migrate", manifest_path.display(), version);
+            } else if args.check {
+                fail(
+                    "Manifest is out of date",
+                    SyncError::Manifest(format!(
+                        "{} is at version {}, not {}. Run `sync manifest migrate`",
+                        manifest_path.display(),
+                        version,
+                        MANIFEST_VERSION
+                    )),
+                );
+            } else {
+                manifest.write(&manifest_path).unwrap_or_else(|e| fail("Could not write manifest", e));
+                println!("Migrated {} from version {} to {}", manifest_path.display(), version, MANIFEST_VERSION);
+            }
+            // Files created before paths were normalized are on chain under their path as it was typed
+            let stale = manifest.stale();
+            if !stale.is_empty() {
+                println!("{} files are on chain under their path as it was typed, not its normalized form:", stale.len());
+                for crud_file in stale {
+                    println!("-> {} (key 0x{})", crud_file.path.display(), hex::encode(crud_file.key));
+                }
+                println!("Updates and deletes still reach them. To rekey one, `sync rm` it and `sync create` it again");
+            }
         }
     }
 }
+
+/// Resolve the configuration, with the CLI flags on top
+/// # Arguments
+/// * `args` - The configuration flags
+/// # Returns
+/// * `Result<Config, SyncError>` - Errors if the config file or profile is missing or invalid
+fn load_config(args: &ConfigArgs) -> Result<Config, SyncError> {
+    let flags = ConfigLayer {
+        rpc_url: args.rpc_url.clone(),
+        chain_id: args.chain_id,
+        contract_address: args.contract_address.clone(),
+        signer: args.signer.clone(),
+        keystore: args.keystore.clone(),
+        store: args.store.clone(),
+        content_root: args.content_root.clone(),
+        strip_front_matter: match (args.strip_front_matter, args.no_strip_front_matter) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+        ..Default::default()
+    };
+    Config::load(args.config.as_deref(), args.profile.as_deref(), flags)
+}
+
+/// The content directory to use: the one given, or else the configured content root
+/// # Arguments
+/// * `dir` - The directory given on the command line, if any
+/// * `config` - The resolved configuration
+/// # Returns
+/// * `PathBuf` - The directory. Exits if neither is set
+fn content_dir(dir: Option<PathBuf>, config: &Config) -> PathBuf {
+    dir.or_else(|| config.content_root.clone()).unwrap_or_else(|| {
+        fail(
+            "No content directory",
+            SyncError::Config("pass a directory, or set content_root in sync.toml or CONTENT_ROOT".to_string()),
+        )
+    })
+}
+
+/// Put the front matter back on a post that was uploaded without it
+/// # Arguments
+/// * `config` - The resolved configuration. Nothing is done unless it strips front matter
+/// * `local` - The file, as it was downloaded
+/// * `metadata` - The file's metadata on chain
+fn restore_front_matter(config: &Config, local: &Path, metadata: &Metadata) {
+    if config.strip_front_matter && front_matter::is_markdown(local) {
+        let body = std::fs::read(local).unwrap_or_else(|e| fail("Could not read file", e.into()));
+        let data = front_matter::restore(metadata, &body).unwrap_or_else(|e| fail("Could not write front matter", e));
+        std::fs::write(local, data).unwrap_or_else(|e| fail("Could not write file", e.into()));
+    }
+}
+
+/// Take the lock on a manifest for the rest of the command
+/// # Arguments
+/// * `manifest_path` - The path to the manifest file
+/// * `command` - The subcommand we are running
+/// # Returns
+/// * `ManifestLock` - The lock. Exits if another sync holds it for too long
+fn lock_manifest(manifest_path: &Path, command: &str) -> ManifestLock {
+    let lock = ManifestLock::acquire(manifest_path, command, LOCK_TIMEOUT)
+        .unwrap_or_else(|e| fail("Could not lock manifest", e));
+    if let Some(holder) = lock.stale() {
+        println!("Warning: {} didn't finish; run `sync status` to check the manifest", holder);
+    }
+    lock
+}
+
+/// Release the manifest lock, if we hold it, and exit. `std::process::exit` skips destructors
+/// # Arguments
+/// * `code` - The exit code
+fn exit(code: i32) -> ! {
+    lock::release_all();
+    std::process::exit(code);
+}
+
+/// The exit code for each kind of error, so scripts can tell failures apart
+/// # Arguments
+/// * `error` - The error
+/// # Returns
+/// * `i32` - The exit code
+fn exit_code(error: &SyncError) -> i32 {
+    match error {
+        SyncError::Revert(_) => 10,
+        SyncError::Rpc(_) => 11,
+        SyncError::StoreStatus { .. } => 12,
+        SyncError::Http(_) => 13,
+        SyncError::Cid(_) => 14,
+        SyncError::Integrity(_) => 15,
+        SyncError::Manifest(_) => 16,
+        SyncError::Config(_) => 17,
+        SyncError::Path(_) => 18,
+        SyncError::Io(_) => 19,
+        SyncError::Json(_) => 20,
+        SyncError::Decode(_) => 21,
+        SyncError::Signer(_) => 22,
+        SyncError::ReadOnly(_) => 23,
+        SyncError::Metadata(_) => 24,
+        SyncError::History(_) => 25,
+    }
+}
+
+/// Report an error and exit with its exit code
+/// # Arguments
+/// * `context` - What we were doing
+/// * `error` - What went wrong
+fn fail(context: &str, error: SyncError) -> ! {
+    println!("{}: {}", context, error);
+    exit(exit_code(&error));
+}
+
+/// Like `fail`, but report on stderr so stdout only carries content
+fn fail_to_stderr(context: &str, error: SyncError) -> ! {
+    eprintln!("{}: {}", context, error);
+    exit(exit_code(&error));
+}
+
+/// Prints this crate's warnings, e.g. from gateways that failed, to stderr so they never mix
+/// with content on stdout
+struct StderrLogger;
+
+static LOGGER: StderrLogger = StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn && metadata.target().starts_with("sync")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("Warning: {}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
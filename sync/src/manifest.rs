@@ -1,27 +1,37 @@
-use anyhow::{Error, Result, anyhow};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::File, io::{Write, Read}, path::PathBuf};
+use serde_json::{Map, Value};
+use std::{collections::HashMap, fs::File, io::{Write, Read}, path::{Path, PathBuf}};
 use ethers::utils::hex;
 use sync::{
-    utils::hash::hash_path,
-    types::{
-        cid::Cid,
-        crud_file::CrudFile
-    }
+    config::Config,
+    crud_fs::crud_fs::CrudFs,
+    error::{Result, SyncError},
+    utils::{hash::hash_path, path::ContentRoot},
+    types::crud_file::CrudFile
 };
 
+/// The version of the manifest layout this client reads and writes
+pub const MANIFEST_VERSION: u64 = 2;
+
+/// A migration from one manifest layout to the next
+type Migration = fn(&mut Map<String, Value>, &ContentRoot) -> Result<(), SyncError>;
+
+/// The migrations from each old layout, indexed by the version they migrate from
+const MIGRATIONS: [Migration; MANIFEST_VERSION as usize] = [migrate_v0, migrate_v1];
 
-// TODO (amiller68): Obake this
 /// Our manifest data structure. This tracks all the files in the local filesystem
 /// This is used to determine which files need to be synced and how to sync them
+/// Change its layout by bumping MANIFEST_VERSION and adding a migration from the old one
 /// # Fields
+/// * `version` - The version of the manifest layout
+/// * `contract_address` - The address of the contract that serves as our CrudFs backend
 /// * `files` - The list of files in the manifest
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Manifest {
+    /// The version of the manifest layout
+    pub version: u64,
     /// The address of the contract that serves as our CrudFs backend
     pub contract_address: String,
-    // /// The api of the Estuary node that serves as our CrudFs store
-    // pub estuary_api: String,
     /// The list of files in the manifest
     pub files: HashMap<String, CrudFile>,
 }
@@ -31,73 +41,356 @@ impl Manifest {
     /// Create a new Manifest instance
     /// # Arguments
     /// * `contract_address` - The address of the contract that serves as our CrudFs backend
-    pub fn new(
-        contract_address: String,
-        // estuary_api: String,
-    ) -> Self {
+    pub fn new(contract_address: String) -> Self {
         Self {
+            version: MANIFEST_VERSION,
             contract_address,
-            // estuary_api,
             files: HashMap::new(),
         }
     }
 
-    /// Read a manifest from a file
+    /// Read a manifest from a file, migrating it to the current layout if it is older
     /// # Arguments
     /// * `path` - The path to the manifest file
+    /// * `content_root` - What the paths in the manifest are relative to, for migrating old layouts
     /// # Returns
-    /// * `Result<Manifest, Error>` - The result
-    pub fn read(path: &PathBuf) -> Result<Manifest, Error> {
-        let mut file = File::open(path)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-        let manifest: Manifest = serde_json::from_str(&contents)?;
+    /// * `Result<Manifest, SyncError>` - The result
+    pub fn read(path: &PathBuf, content_root: &ContentRoot) -> Result<Manifest, SyncError> {
+        let (manifest, _) = Self::read_versioned(path, content_root)?;
         Ok(manifest)
     }
 
+    /// Read a manifest from a file, like `read`, along with the version it was written at
+    /// # Arguments
+    /// * `path` - The path to the manifest file
+    /// * `content_root` - What the paths in the manifest are relative to, for migrating old layouts
+    /// # Returns
+    /// * `Result<(Manifest, u64), SyncError>` - The manifest, and the version it was stored at.
+    ///   A manifest error if it was written by a newer client
+    pub fn read_versioned(path: &PathBuf, content_root: &ContentRoot) -> Result<(Manifest, u64), SyncError> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| SyncError::Manifest(format!("Could not read {}: {}", path.display(), e)))?;
+        Self::parse(&contents, content_root).map_err(|e| match e {
+            SyncError::Manifest(e) => SyncError::Manifest(format!("{}: {}", path.display(), e)),
+            e => SyncError::Manifest(format!("Could not parse {}: {}", path.display(), e)),
+        })
+    }
+
+    /// Parse a manifest, migrating it to the current layout if it is older
+    /// # Arguments
+    /// * `contents` - The manifest, as JSON
+    /// * `content_root` - What the paths in the manifest are relative to, for migrating old layouts
+    /// # Returns
+    /// * `Result<(Manifest, u64), SyncError>` - The manifest, and the version it was stored at
+    pub fn parse(contents: &str, content_root: &ContentRoot) -> Result<(Manifest, u64), SyncError> {
+        let mut object = match serde_json::from_str(contents)? {
+            Value::Object(object) => object,
+            _ => return Err(SyncError::Manifest("The manifest is not a JSON object".to_string())),
+        };
+        // Manifests from before we versioned them have no version
+        let version = match object.get("version") {
+            None => 0,
+            Some(version) => version
+                .as_u64()
+                .ok_or_else(|| SyncError::Manifest(format!("Invalid manifest version {}", version)))?,
+        };
+        if version > MANIFEST_VERSION {
+            return Err(SyncError::Manifest(format!(
+                "The manifest is at version {}, but this sync only reads up to version {}. Upgrade sync",
+                version, MANIFEST_VERSION
+            )));
+        }
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            migration(&mut object, content_root)?;
+            object.insert("version".to_string(), Value::from(from as u64 + 1));
+        }
+        let manifest = serde_json::from_value(Value::Object(object))?;
+        Ok((manifest, version))
+    }
+
     /// Write a manifest to a file
+    /// The manifest is written to a temp file next to it, synced, and renamed over it, so a crash
+    /// leaves either the old manifest or the new one, never a partial one
     /// # Arguments
     /// * `path` - The path to the manifest file
     /// # Returns
-    /// * `Result<(), Error>` - The result
-    pub fn write(&self, path: &PathBuf) -> Result<(), Error> {
-        let mut file = File::create(path)?;
+    /// * `Result<(), SyncError>` - The result
+    pub fn write(&self, path: &PathBuf) -> Result<(), SyncError> {
         let contents = serde_json::to_string_pretty(&self)?;
-        file.write_all(contents.as_bytes())?;
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(path.file_name().unwrap_or_default());
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp_path = path.with_file_name(temp_name);
+        let res = File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(contents.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| std::fs::rename(&temp_path, path))
+            .and_then(|_| sync_dir(path));
+        if let Err(e) = res {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(SyncError::Manifest(format!("Could not write {}: {}", path.display(), e)));
+        }
         Ok(())
     }
 
+    /// Open CrudFs for the configured contract, or for the one the manifest tracks if none is configured
+    /// # Arguments
+    /// * `config` - The resolved configuration
+    /// # Returns
+    /// * `Result<CrudFs, SyncError>` - A configuration error if the configuration is incomplete
+    pub fn crud_fs(&self, config: &Config) -> Result<CrudFs, SyncError> {
+        CrudFs::from_config(&self.config(config))
+    }
+
+    /// Open a read-only CrudFs, that needs no signer, like `crud_fs`
+    /// # Arguments
+    /// * `config` - The resolved configuration
+    /// # Returns
+    /// * `Result<CrudFs, SyncError>` - A configuration error if the configuration is incomplete
+    pub fn read_only_crud_fs(&self, config: &Config) -> Result<CrudFs, SyncError> {
+        CrudFs::read_only(&self.config(config))
+    }
+
+    /// The configuration, with the manifest's contract if none is configured
+    fn config(&self, config: &Config) -> Config {
+        let mut config = config.clone();
+        if config.contract_address.is_none() && !self.contract_address.is_empty() {
+            config.contract_address = Some(self.contract_address.clone());
+        }
+        config
+    }
+
     /// Check if a path is in the manifest
     /// # Arguments
     /// * `path` - The path to the file
     /// # Returns
-    /// * `Result<bool, Error>` - The result
-    pub fn contains(&self, path: &PathBuf) -> Result<bool, Error> {
+    /// * `Result<bool, SyncError>` - The result
+    pub fn contains(&self, path: &Path) -> Result<bool, SyncError> {
         let key = hash_path(path)?;
         let key_str = hex::encode(key);
         Ok(self.files.contains_key(&key_str))
     }
 
+    /// Get a file from the manifest
+    /// # Arguments
+    /// * `path` - The path to the file
+    /// # Returns
+    /// * `Result<Option<&CrudFile>, SyncError>` - The file, if it is tracked
+    pub fn get(&self, path: &Path) -> Result<Option<&CrudFile>, SyncError> {
+        let key = hash_path(path)?;
+        let key_str = hex::encode(key);
+        Ok(self.files.get(&key_str))
+    }
+
     /// Add a file to the manifest
     /// # Arguments
     /// * `crud_file` - The file to add
     /// # Returns
-    /// * `Result<(), Error>` - The result
-    pub fn add(&mut self, crud_file: CrudFile) -> Result<(), Error> {
+    /// * `Result<(), SyncError>` - The result
+    pub fn add(&mut self, crud_file: CrudFile) -> Result<(), SyncError> {
         let key_str = hex::encode(crud_file.key);
         self.files.insert(key_str, crud_file);
         Ok(())
     }
 
+    /// The files that are on chain under a different key than their path hashes to: those created
+    /// before paths were normalized, e.g. as `./hello.md`, and those whose path can't be keyed
+    /// # Returns
+    /// * `Vec<&CrudFile>` - The files, sorted by path
+    pub fn stale(&self) -> Vec<&CrudFile> {
+        let mut stale: Vec<&CrudFile> = self
+            .files
+            .values()
+            .filter(|crud_file| hash_path(&crud_file.path).map(|key| key != crud_file.key).unwrap_or(true))
+            .collect();
+        stale.sort_by(|a, b| a.path.cmp(&b.path));
+        stale
+    }
+
     /// Remove a file from the manifest
     /// # Arguments
     /// * `path` - The path to the file
     /// # Returns
-    /// * `Result<(), Error>` - The result
-    pub fn rm(&mut self, path: &PathBuf) -> Result<(), Error> {
+    /// * `Result<(), SyncError>` - The result
+    pub fn rm(&mut self, path: &Path) -> Result<(), SyncError> {
         let key = hash_path(path)?;
         let key_str = hex::encode(key);
         self.files.remove(&key_str);
         Ok(())
     }
 }
+
+/// Version 0 to 1: add the version, and drop `estuary_api`, which was never used.
+/// The store is configured rather than tracked in the manifest
+fn migrate_v0(object: &mut Map<String, Value>, _: &ContentRoot) -> Result<(), SyncError> {
+    object.remove("estuary_api");
+    object
+        .entry("contract_address")
+        .or_insert_with(|| Value::String(String::new()));
+    object.entry("files").or_insert_with(|| Value::Object(Map::new()));
+    Ok(())
+}
+
+/// Version 1 to 2: key files by their content path. Before paths were normalized, files were
+/// keyed by their path as it was typed from the working directory, so `./content/hello.md` isn't
+/// found by looking up `hello.md` under the content root `content`. Each path is made relative to
+/// the content root and its entry moved to the key it hashes to, so migrate from the directory the
+/// manifest was used from. The file's own `key` is its key on chain, so it is kept;
+/// `Manifest::stale` reports files where the two differ
+fn migrate_v1(object: &mut Map<String, Value>, content_root: &ContentRoot) -> Result<(), SyncError> {
+    let files = match object.remove("files") {
+        Some(Value::Object(files)) => files,
+        Some(_) => return Err(SyncError::Manifest("The manifest's files are not a JSON object".to_string())),
+        None => Map::new(),
+    };
+    let mut migrated = Map::new();
+    for (key, mut entry) in files {
+        let normalized = entry
+            .get("path")
+            .and_then(Value::as_str)
+            .and_then(|path| content_root.relative(Path::new(path)).ok());
+        // A path outside the content root can't be keyed; leave it where it is to be reported
+        let key = match normalized {
+            Some(path) => {
+                let key = hex::encode(hash_path(&path)?);
+                entry["path"] = Value::String(path.to_string_lossy().to_string());
+                key
+            }
+            None => key,
+        };
+        if migrated.insert(key, entry).is_some() {
+            return Err(SyncError::Manifest(
+                "Two files normalize to the same path; remove one from the manifest and migrate again".to_string(),
+            ));
+        }
+    }
+    object.insert("files".to_string(), Value::Object(migrated));
+    Ok(())
+}
+
+/// Sync the directory holding a file, so a rename into it survives a crash
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Writes replace the manifest whole, and leave no temp file behind
+    fn test_write() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("manifest.json");
+        std::fs::write(&path, "{ \"a much longer manifest than the one that replaces it\": [] }").unwrap();
+        Manifest::new("0x01".to_string()).write(&path).unwrap();
+        let manifest = Manifest::read(&path, &ContentRoot::default()).unwrap();
+        assert_eq!(manifest.contract_address, "0x01");
+        let entries: Vec<_> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(entries, vec!["manifest.json"]);
+
+        // Failing to write is a manifest error
+        let missing = dir.join("missing").join("manifest.json");
+        assert!(matches!(Manifest::new("".to_string()).write(&missing), Err(SyncError::Manifest(_))));
+    }
+
+    #[test]
+    /// Old layouts are migrated on read, and newer ones are refused
+    fn test_migrate() {
+        let v0 = r#"{"contract_address":"0x01","estuary_api":"https://api.estuary.tech","files":{}}"#;
+        let (manifest, version) = Manifest::parse(v0, &ContentRoot::default()).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(manifest.version, MANIFEST_VERSION);
+        assert_eq!(manifest.contract_address, "0x01");
+        let written = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(written["version"], MANIFEST_VERSION);
+        assert!(written.get("estuary_api").is_none());
+
+        // The current layout reads as it is
+        let (_, version) = Manifest::parse(&written.to_string(), &ContentRoot::default()).unwrap();
+        assert_eq!(version, MANIFEST_VERSION);
+
+        let newer = format!(r#"{{"version":{},"contract_address":"","files":{{}}}}"#, MANIFEST_VERSION + 1);
+        let err = Manifest::parse(&newer, &ContentRoot::default()).unwrap_err();
+        assert!(matches!(err, SyncError::Manifest(_)));
+        assert!(err.to_string().contains("Upgrade sync"), "{}", err);
+        assert!(matches!(Manifest::parse("[]", &ContentRoot::default()), Err(SyncError::Manifest(_))));
+        assert!(matches!(Manifest::parse(r#"{"version":"1"}"#, &ContentRoot::default()), Err(SyncError::Manifest(_))));
+    }
+
+    /// A file as a version 1 manifest held it: keyed by its path as it was typed
+    fn typed(path: &str) -> CrudFile {
+        CrudFile {
+            key: sync::utils::hash::hash_str(path),
+            filename: "hello.md".to_string(),
+            path: PathBuf::from(path),
+            cid: sync::types::cid::Cid::from(path.as_bytes()),
+            timestamp: 1,
+            metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    /// Version 1 files are re-keyed by their normalized path, and those on chain under another key are reported
+    fn test_migrate_v1() {
+        let (dotted, clean) = (typed("./posts/../hello.md"), typed("world.md"));
+        let v1 = serde_json::json!({
+            "version": 1,
+            "contract_address": "0x01",
+            "files": { hex::encode(dotted.key): dotted, hex::encode(clean.key): clean },
+        });
+        let (manifest, version) = Manifest::parse(&v1.to_string(), &ContentRoot::default()).unwrap();
+        assert_eq!(version, 1);
+        let hello = manifest.get(Path::new("hello.md")).unwrap().unwrap();
+        assert_eq!(hello.path, PathBuf::from("hello.md"));
+        // The key on chain is kept, and reported
+        assert_eq!(hello.key, dotted.key);
+        assert!(manifest.get(Path::new("world.md")).unwrap().is_some());
+        let stale: Vec<&Path> = manifest.stale().iter().map(|crud_file| crud_file.path.as_path()).collect();
+        assert_eq!(stale, vec![Path::new("hello.md")]);
+
+        // Two spellings of one path can't both be kept
+        let clash = serde_json::json!({
+            "version": 1,
+            "contract_address": "0x01",
+            "files": { "a": typed("./world.md"), "b": typed("world.md") },
+        });
+        assert!(matches!(Manifest::parse(&clash.to_string(), &ContentRoot::default()), Err(SyncError::Manifest(_))));
+    }
+
+    #[test]
+    /// Version 1 paths were typed from the working directory, so they are migrated to paths from the content root
+    fn test_migrate_v1_content_root() {
+        let (nested, absolute, outside) = (
+            typed("./content/posts/hello.md"),
+            typed(&std::env::current_dir().unwrap().join("content/world.md").to_string_lossy()),
+            typed("notes/todo.md"),
+        );
+        let v1 = serde_json::json!({
+            "version": 1,
+            "contract_address": "0x01",
+            "files": {
+                hex::encode(nested.key): nested,
+                hex::encode(absolute.key): absolute,
+                hex::encode(outside.key): outside,
+            },
+        });
+        let content_root = ContentRoot::new(Some(PathBuf::from("content")));
+        let (manifest, _) = Manifest::parse(&v1.to_string(), &content_root).unwrap();
+        let hello = manifest.get(Path::new("posts/hello.md")).unwrap().unwrap();
+        assert_eq!(hello.path, PathBuf::from("posts/hello.md"));
+        assert_eq!(hello.key, nested.key);
+        assert_eq!(manifest.get(Path::new("world.md")).unwrap().unwrap().path, PathBuf::from("world.md"));
+        // A file outside the content root is left as it was
+        assert_eq!(manifest.files[&hex::encode(outside.key)].path, PathBuf::from("notes/todo.md"));
+        assert_eq!(manifest.stale().len(), 2);
+    }
+}
@@ -0,0 +1,491 @@
+use std::{collections::HashMap, fmt, path::{Path, PathBuf}};
+use sync::{
+    config::Config,
+    crud_fs::crud_fs::CrudFs,
+    error::{Result, SyncError},
+    types::{
+        cid::{Cid, CidMode},
+        crud_file::CrudFile,
+        metadata::{Metadata, ALIASES, MOVED_TO},
+    },
+    utils::{front_matter, path::{absolute, normalize, ContentRoot}},
+};
+
+use crate::{lock::lock_path, manifest::Manifest};
+
+/// A single change needed to bring the backend in line with a content directory
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// Create a new file
+    Create { path: PathBuf, cid: Cid, metadata: Metadata },
+    /// Update a tracked file with new content or metadata. The CrudFile carries the metadata to set
+    Update { crud_file: CrudFile, cid: Cid },
+    /// Move a tracked file that is gone from disk to a new file with the same content
+    Rename { crud_file: CrudFile, path: PathBuf },
+    /// Delete a tracked file that is gone from disk
+    Delete { crud_file: CrudFile },
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Create { path, cid, .. } => write!(f, "create {} ({})", path.display(), cid),
+            Action::Update { crud_file, cid } => {
+                write!(f, "update {} ({} -> {})", crud_file.path.display(), crud_file.cid, cid)
+            }
+            Action::Rename { crud_file, path } => {
+                write!(f, "move {} -> {} ({})", crud_file.path.display(), path.display(), crud_file.cid)
+            }
+            Action::Delete { crud_file } => {
+                write!(f, "delete {} ({})", crud_file.path.display(), crud_file.cid)
+            }
+        }
+    }
+}
+
+/// Sync's own files, which are never content, even when they are in the content directory
+/// # Arguments
+/// * `config` - The resolved configuration
+/// * `manifest_path` - The path to the manifest file
+/// # Returns
+/// * `Vec<PathBuf>` - The manifest and its lock, the config file, the history index and its
+///   journal, and the local store's directory
+pub fn own_files(config: &Config, manifest_path: &Path) -> Vec<PathBuf> {
+    let mut journal = config.history_path.clone().into_os_string();
+    journal.push("-journal");
+    let mut files = vec![
+        manifest_path.to_path_buf(),
+        lock_path(manifest_path),
+        config.history_path.clone(),
+        PathBuf::from(journal),
+        config.store.local_store_path.clone(),
+    ];
+    files.extend(config.config_path.clone());
+    files
+}
+
+/// Leave sync's own files out of a list of local files, along with anything under its own directories
+/// # Arguments
+/// * `local_files` - The files
+/// * `own_files` - Sync's own files, from `own_files`
+/// # Returns
+/// * `Result<Vec<PathBuf>, SyncError>` - The files that are content
+pub fn without_own_files(local_files: Vec<PathBuf>, own_files: &[PathBuf]) -> Result<Vec<PathBuf>, SyncError> {
+    let own_files = own_files
+        .iter()
+        .map(|path| normalize(&absolute(path)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut files = Vec::new();
+    for path in local_files {
+        let absolute = normalize(&absolute(&path)?)?;
+        if !own_files.iter().any(|own| absolute.starts_with(own)) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Work out the creates, updates, moves and deletes needed to sync a directory
+/// # Arguments
+/// * `manifest` - The manifest
+/// * `content_root` - What the paths in the manifest are relative to
+/// * `dir` - The content directory. Only tracked files under it are considered for deletion
+/// * `local_files` - The files currently in the directory
+/// * `own_files` - Sync's own files, from `own_files`. They are never content
+/// * `strip_front_matter` - Whether markdown is uploaded without its front matter
+/// * `cid_mode` - How the Cids of uploaded files are computed
+/// # Returns
+/// * `Result<Vec<Action>, SyncError>` - The actions, creates and updates first, then moves, then deletes.
+///   Creates and moves carry paths on disk; updates, moves and deletes carry the tracked CrudFile
+pub fn plan(
+    manifest: &Manifest,
+    content_root: &ContentRoot,
+    dir: &Path,
+    local_files: Vec<PathBuf>,
+    own_files: &[PathBuf],
+    strip_front_matter: bool,
+    cid_mode: CidMode,
+) -> Result<Vec<Action>, SyncError> {
+    let mut actions = Vec::new();
+    for path in without_own_files(local_files, own_files)? {
+        let cid = front_matter::cid(&path, strip_front_matter, cid_mode)?;
+        match manifest.get(&content_root.relative(&path)?)? {
+            None => {
+                let metadata = front_matter::metadata(&path, Metadata::new(), Metadata::new())?;
+                actions.push(Action::Create { path, cid, metadata });
+            }
+            // Stripped front matter doesn't change the Cid, so its metadata has to be compared too
+            Some(crud_file) if crud_file.cid != cid || strip_front_matter => {
+                let metadata = front_matter::metadata(&path, base(&path, &crud_file.metadata), Metadata::new())?;
+                if crud_file.cid != cid || metadata != crud_file.metadata {
+                    let mut crud_file = crud_file.clone();
+                    crud_file.metadata = metadata;
+                    actions.push(Action::Update { crud_file, cid });
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    let dir = content_root.relative(dir)?;
+    let mut deletes: Vec<&CrudFile> = manifest
+        .files
+        .values()
+        .filter(|crud_file| crud_file.path.starts_with(&dir) && !content_root.local(&crud_file.path).is_file())
+        .collect();
+    deletes.sort_by(|a, b| a.path.cmp(&b.path));
+    // A tracked file that is gone, and a new one with the same content, were moved. Only pair them
+    // when no other file shares that content; otherwise they are created and deleted as usual
+    let mut moves = Vec::new();
+    let mut removes = Vec::new();
+    for crud_file in &deletes {
+        let same = |cid: &Cid| *cid == crud_file.cid;
+        let created = actions.iter().filter(|action| matches!(action, Action::Create { cid, .. } if same(cid))).count();
+        let deleted = deletes.iter().filter(|other| same(&other.cid)).count();
+        match actions.iter().position(|action| matches!(action, Action::Create { cid, .. } if same(cid))) {
+            Some(i) if created == 1 && deleted == 1 => {
+                if let Action::Create { path, .. } = actions.remove(i) {
+                    moves.push(Action::Rename { crud_file: (*crud_file).clone(), path });
+                }
+            }
+            _ => removes.push(Action::Delete { crud_file: (*crud_file).clone() }),
+        }
+    }
+    actions.extend(moves);
+    actions.extend(removes);
+    Ok(actions)
+}
+
+/// Find moves that stopped between their two transactions: the file was created at its new path
+/// with the old one in its `aliases`, but the old path was never redirected. The manifest is only
+/// updated once both went through, so it still tracks the old path and not the new one
+/// # Arguments
+/// * `manifest` - The manifest
+/// * `chain_files` - Every CrudFile on chain
+/// # Returns
+/// * `Result<Vec<(CrudFile, PathBuf)>, SyncError>` - The tracked CrudFile at each old path, and the path it was moving to
+pub fn unfinished_moves(manifest: &Manifest, chain_files: &[CrudFile]) -> Result<Vec<(CrudFile, PathBuf)>, SyncError> {
+    let chain: HashMap<[u8; 32], &CrudFile> = chain_files.iter().map(|crud_file| (crud_file.key, crud_file)).collect();
+    let mut moves = Vec::new();
+    for to in chain_files {
+        if to.moved_to().is_some() || manifest.get(&to.path)?.is_some() {
+            continue;
+        }
+        for from in to.aliases() {
+            let tracked = match manifest.get(&from)? {
+                Some(tracked) if from != to.path => tracked,
+                _ => continue,
+            };
+            let redirected = chain.get(&tracked.key).map(|old| old.moved_to().is_some()).unwrap_or(false);
+            if !redirected {
+                moves.push((tracked.clone(), to.path.clone()));
+            }
+        }
+    }
+    Ok(moves)
+}
+
+/// Finish the moves `unfinished_moves` finds by running them again, and record them in the
+/// manifest, which is not written. An interrupted move leaves a create and a delete in the plan,
+/// so the chain is only listed when the plan has both
+/// # Arguments
+/// * `crud_fs` - The CrudFs to finish the moves with
+/// * `manifest` - The manifest to record them in
+/// * `actions` - The plan for the directory
+/// # Returns
+/// * `Result<Vec<(PathBuf, PathBuf)>, SyncError>` - The old and new path of each move finished. Plan again if any were
+pub async fn finish_moves(
+    crud_fs: &CrudFs,
+    manifest: &mut Manifest,
+    actions: &[Action],
+) -> Result<Vec<(PathBuf, PathBuf)>, SyncError> {
+    let creates = actions.iter().any(|action| matches!(action, Action::Create { .. }));
+    let deletes = actions.iter().any(|action| matches!(action, Action::Delete { .. }));
+    if !creates || !deletes {
+        return Ok(Vec::new());
+    }
+    let chain_files = crud_fs.list().await?;
+    let mut finished = Vec::new();
+    for (crud_file, to) in unfinished_moves(manifest, &chain_files)? {
+        println!("-> finish move {} -> {}", crud_file.path.display(), to.display());
+        let content_root = crud_fs.content_root();
+        let moved = crud_fs.rename(content_root.local(&crud_file.path), content_root.local(&to)).await?;
+        manifest.rm(&crud_file.path)?;
+        manifest.add(moved)?;
+        finished.push((crud_file.path, to));
+    }
+    Ok(finished)
+}
+
+/// The metadata a tracked file's front matter is read over. Markdown starts afresh, so keys
+/// dropped from the front matter are dropped on chain too, and only keeps the keys `mv` manages.
+/// Other files have no front matter, and keep what is on chain
+/// # Arguments
+/// * `path` - The path to the file
+/// * `metadata` - The metadata on chain
+fn base(path: &Path, metadata: &Metadata) -> Metadata {
+    if !front_matter::is_markdown(path) {
+        return metadata.clone();
+    }
+    metadata
+        .iter()
+        .filter(|(key, _)| [ALIASES, MOVED_TO].contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Apply a plan to the backend and store, recording each change in the manifest
+/// Stops at the first failure. The manifest is not written
+/// # Arguments
+/// * `crud_fs` - The CrudFs to apply the actions with
+/// * `manifest` - The manifest to record changes in
+/// * `actions` - The actions to apply
+/// # Returns
+/// * `Result<(), SyncError>` - The first error encountered, if any
+pub async fn apply(crud_fs: &CrudFs, manifest: &mut Manifest, actions: Vec<Action>) -> Result<(), SyncError> {
+    for action in actions {
+        println!("-> {}", action);
+        match action {
+            Action::Create { path, cid, metadata } => {
+                let crud_file = crud_fs.create(path, cid, metadata).await?;
+                manifest.add(crud_file)?;
+            }
+            Action::Update { crud_file, .. } => {
+                let crud_file = crud_fs.update(crud_file).await?;
+                manifest.add(crud_file)?;
+            }
+            Action::Rename { crud_file, path } => {
+                let moved = crud_fs.rename(crud_fs.content_root().local(&crud_file.path), path).await?;
+                manifest.rm(&crud_file.path)?;
+                manifest.add(moved)?;
+            }
+            Action::Delete { crud_file } => {
+                crud_fs.delete_key(crud_file.key).await?;
+                manifest.rm(&crud_file.path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sync::utils::walk::walk_dir;
+
+    #[test]
+    fn test_plan() {
+        // Set up a content directory
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let unchanged = dir.join("unchanged.md");
+        let modified = dir.join("modified.md");
+        let created = dir.join("created.md");
+        let deleted = dir.join("deleted.md");
+        for path in [&unchanged, &modified, &deleted] {
+            std::fs::write(path, "old").unwrap();
+        }
+
+        // Track everything that is there now
+        let mut manifest = Manifest::new("".to_string());
+        for path in [&unchanged, &modified, &deleted] {
+            manifest.add(CrudFile::new(path.clone()).unwrap()).unwrap();
+        }
+
+        // Then make some changes
+        std::fs::write(&modified, "new").unwrap();
+        std::fs::write(&created, "new").unwrap();
+        std::fs::remove_file(&deleted).unwrap();
+
+        let actions = plan(&manifest, &ContentRoot::default(), dir, walk_dir(dir).unwrap(), &[], false, CidMode::default()).unwrap();
+        let new_cid = Cid::from(b"new".as_slice());
+        assert_eq!(actions.len(), 3);
+        assert!(matches!(&actions[0], Action::Create { path, cid, .. } if *path == created && *cid == new_cid));
+        assert!(matches!(&actions[1], Action::Update { crud_file, cid } if crud_file.path == modified && *cid == new_cid));
+        assert!(matches!(&actions[2], Action::Delete { crud_file } if crud_file.path == deleted));
+
+        // Under a content root, the manifest tracks paths relative to it
+        let content_root = ContentRoot::new(Some(dir.to_path_buf()));
+        let mut manifest = Manifest::new("".to_string());
+        let mut crud_file = CrudFile::new(unchanged.clone()).unwrap();
+        crud_file.path = PathBuf::from("unchanged.md");
+        crud_file.key = sync::utils::hash::hash_path(&crud_file.path).unwrap();
+        manifest.add(crud_file).unwrap();
+        let actions = plan(&manifest, &content_root, dir, vec![unchanged.clone()], &[], false, CidMode::default()).unwrap();
+        assert!(actions.is_empty(), "{:?}", actions);
+    }
+
+    #[test]
+    /// With front matter stripped, editing only the front matter is still an update
+    fn test_plan_front_matter() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let post = dir.join("post.md");
+        std::fs::write(&post, "---\ntitle: Hello\ndate: 2023-02-01\n---\nBody").unwrap();
+
+        let actions = plan(&Manifest::new("".to_string()), &ContentRoot::default(), dir, walk_dir(dir).unwrap(), &[], true, CidMode::default()).unwrap();
+        let (cid, metadata) = match &actions[..] {
+            [Action::Create { cid, metadata, .. }] => (cid.clone(), metadata.clone()),
+            other => panic!("Expected a create, got {:?}", other),
+        };
+        assert_eq!(cid, Cid::from(b"Body".as_slice()));
+        assert_eq!(metadata["title"], "Hello");
+
+        let mut manifest = Manifest::new("".to_string());
+        let mut crud_file = CrudFile::new(post.clone()).unwrap();
+        crud_file.cid = cid;
+        crud_file.metadata = metadata;
+        manifest.add(crud_file).unwrap();
+        assert!(plan(&manifest, &ContentRoot::default(), dir, walk_dir(dir).unwrap(), &[], true, CidMode::default()).unwrap().is_empty());
+
+        std::fs::write(&post, "---\ntitle: Hello again\ndate: 2023-02-01\n---\nBody").unwrap();
+        let actions = plan(&manifest, &ContentRoot::default(), dir, walk_dir(dir).unwrap(), &[], true, CidMode::default()).unwrap();
+        assert!(matches!(&actions[..], [Action::Update { crud_file, .. }] if crud_file.metadata["title"] == "Hello again"));
+
+        // Keys dropped from the front matter are dropped on chain, but aliases are kept
+        manifest.files.values_mut().for_each(|crud_file| {
+            crud_file.metadata.insert("draft".to_string(), "true".to_string());
+            crud_file.metadata.insert(ALIASES.to_string(), "[\"old.md\"]".to_string());
+        });
+        let actions = plan(&manifest, &ContentRoot::default(), dir, walk_dir(dir).unwrap(), &[], true, CidMode::default()).unwrap();
+        match &actions[..] {
+            [Action::Update { crud_file, .. }] => {
+                assert!(!crud_file.metadata.contains_key("draft"));
+                assert_eq!(crud_file.metadata[ALIASES], "[\"old.md\"]");
+            }
+            other => panic!("Expected an update, got {:?}", other),
+        }
+    }
+
+    #[test]
+    /// Pushing a directory that holds the manifest, config, history index and local store only pushes content
+    fn test_plan_own_files() {
+        use sync::config::ConfigLayer;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("store")).unwrap();
+        let manifest_path = dir.join("manifest.json");
+        let hello = dir.join("hello.md");
+        for path in [
+            manifest_path.clone(),
+            lock_path(&manifest_path),
+            dir.join("sync.toml"),
+            dir.join("history.db"),
+            dir.join("history.db-journal"),
+            dir.join("store").join("block"),
+            hello.clone(),
+        ] {
+            std::fs::write(path, "").unwrap();
+        }
+        let config = Config::load(
+            Some(&dir.join("sync.toml")),
+            None,
+            ConfigLayer {
+                history_path: Some(dir.join("history.db")),
+                local_store_path: Some(dir.join("store")),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let own_files = own_files(&config, &manifest_path);
+        let actions = plan(&Manifest::new("".to_string()), &ContentRoot::default(), dir, walk_dir(dir).unwrap(), &own_files, false, CidMode::default()).unwrap();
+        assert!(matches!(&actions[..], [Action::Create { path, .. }] if *path == hello), "{:?}", actions);
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        use sync::crud_fs::{backend::InMemoryBackend, store::{LocalStore, StoreClient}};
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let content = dir.join("content");
+        std::fs::create_dir_all(&content).unwrap();
+        let crud_fs = CrudFs::with_clients(
+            Box::new(InMemoryBackend::new()),
+            StoreClient::new(Box::new(LocalStore::new(dir.join("store")))),
+        )
+        .with_content_root(ContentRoot::new(Some(content.clone())));
+        let mut manifest = Manifest::new("".to_string());
+
+        // Push a new file, tracked by its path from the content root
+        let path = content.join("hello.md");
+        let key_path = PathBuf::from("hello.md");
+        std::fs::write(&path, "hello").unwrap();
+        let actions = plan(&manifest, crud_fs.content_root(), &content, walk_dir(&content).unwrap(), &[], false, CidMode::default()).unwrap();
+        apply(&crud_fs, &mut manifest, actions).await.unwrap();
+        assert_eq!(manifest.get(&key_path).unwrap().unwrap().cid, Cid::from(b"hello".as_slice()));
+        assert_eq!(crud_fs.list().await.unwrap()[0].path, key_path);
+
+        // Push an edit
+        std::fs::write(&path, "hello again").unwrap();
+        let actions = plan(&manifest, crud_fs.content_root(), &content, walk_dir(&content).unwrap(), &[], false, CidMode::default()).unwrap();
+        apply(&crud_fs, &mut manifest, actions).await.unwrap();
+        let cid = Cid::from(b"hello again".as_slice());
+        assert_eq!(manifest.get(&key_path).unwrap().unwrap().cid, cid);
+        assert_eq!(crud_fs.list().await.unwrap()[0].cid, cid);
+
+        // Push a move, which keeps the file's identity
+        let moved = content.join("posts/hello.md");
+        std::fs::create_dir_all(content.join("posts")).unwrap();
+        std::fs::rename(&path, &moved).unwrap();
+        let actions = plan(&manifest, crud_fs.content_root(), &content, walk_dir(&content).unwrap(), &[], false, CidMode::default()).unwrap();
+        assert!(matches!(&actions[..], [Action::Rename { crud_file, path }] if crud_file.path == key_path && *path == moved));
+        apply(&crud_fs, &mut manifest, actions).await.unwrap();
+        let key_path = PathBuf::from("posts/hello.md");
+        assert_eq!(manifest.files.len(), 1);
+        assert_eq!(manifest.get(&key_path).unwrap().unwrap().aliases(), vec![PathBuf::from("hello.md")]);
+        assert_eq!(crud_fs.read(path.clone()).await.unwrap().0.path, key_path);
+        let path = moved;
+
+        // Push a delete
+        std::fs::remove_file(&path).unwrap();
+        let actions = plan(&manifest, crud_fs.content_root(), &content, walk_dir(&content).unwrap(), &[], false, CidMode::default()).unwrap();
+        apply(&crud_fs, &mut manifest, actions).await.unwrap();
+        assert!(manifest.files.is_empty());
+        // Only the redirect from the old path is left
+        let left = crud_fs.list().await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].moved_to(), Some(key_path));
+    }
+
+    #[tokio::test]
+    /// A move that stopped after its first transaction is found, and finished on the next push
+    async fn test_finish_moves() {
+        use sync::crud_fs::{backend::InMemoryBackend, store::{LocalStore, StoreClient}};
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let content = dir.join("content");
+        std::fs::create_dir_all(&content).unwrap();
+        let crud_fs = CrudFs::with_clients(
+            Box::new(InMemoryBackend::new()),
+            StoreClient::new(Box::new(LocalStore::new(dir.join("store")))),
+        )
+        .with_content_root(ContentRoot::new(Some(content.clone())));
+        let mut manifest = Manifest::new("".to_string());
+        let (from, to) = (content.join("hello.md"), content.join("moved.md"));
+        std::fs::write(&from, "hello").unwrap();
+        let actions = plan(&manifest, crud_fs.content_root(), &content, walk_dir(&content).unwrap(), &[], false, CidMode::default()).unwrap();
+        apply(&crud_fs, &mut manifest, actions).await.unwrap();
+
+        // The file was created at its new path, and edited before the old path was redirected
+        std::fs::rename(&from, &to).unwrap();
+        let mut metadata = Metadata::new();
+        metadata.insert(ALIASES.to_string(), "[\"hello.md\"]".to_string());
+        crud_fs.create(to.clone(), Cid::from(b"hello".as_slice()), metadata).await.unwrap();
+        std::fs::write(&to, "hello again").unwrap();
+        let chain_files = crud_fs.list().await.unwrap();
+        let moves = unfinished_moves(&manifest, &chain_files).unwrap();
+        assert!(matches!(&moves[..], [(crud_file, path)] if crud_file.path == Path::new("hello.md") && path == Path::new("moved.md")));
+
+        let actions = plan(&manifest, crud_fs.content_root(), &content, walk_dir(&content).unwrap(), &[], false, CidMode::default()).unwrap();
+        let finished = finish_moves(&crud_fs, &mut manifest, &actions).await.unwrap();
+        assert_eq!(finished, vec![(PathBuf::from("hello.md"), PathBuf::from("moved.md"))]);
+        assert_eq!(crud_fs.read(from).await.unwrap().0.path, PathBuf::from("moved.md"));
+        assert!(unfinished_moves(&manifest, &crud_fs.list().await.unwrap()).unwrap().is_empty());
+        // Planning again picks up the edit
+        let actions = plan(&manifest, crud_fs.content_root(), &content, walk_dir(&content).unwrap(), &[], false, CidMode::default()).unwrap();
+        assert!(matches!(&actions[..], [Action::Update { crud_file, .. }] if crud_file.path == Path::new("moved.md")));
+    }
+}
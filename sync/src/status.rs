@@ -0,0 +1,269 @@
+use ethers::utils::hex;
+use std::{collections::{BTreeMap, BTreeSet}, fmt, path::PathBuf};
+use sync::{
+    error::{Result, SyncError},
+    utils::{front_matter, hash::hash_path, path::ContentRoot},
+    types::{
+        cid::{Cid, CidMode},
+        crud_file::CrudFile
+    }
+};
+
+use crate::{manifest::Manifest, plan::unfinished_moves};
+
+/// How a path differs between the local folder, the manifest and the backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// On disk, but neither in the manifest nor on chain
+    New,
+    /// On disk with a different Cid than the manifest records
+    Modified,
+    /// In the manifest, but gone from disk
+    DeletedLocally,
+    /// In the manifest, but not on chain
+    MissingOnChain,
+    /// On chain, but different from (or absent in) the manifest
+    DriftedOnChain,
+    /// Moved on chain, but the old path was never redirected. `push` finishes the move
+    MoveUnfinished,
+    /// On chain under a key its path no longer hashes to, as it was created before paths were normalized
+    StaleKey,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Status::New => "new",
+            Status::Modified => "modified",
+            Status::DeletedLocally => "deleted locally",
+            Status::MissingOnChain => "missing on chain",
+            Status::DriftedOnChain => "drifted on chain",
+            Status::MoveUnfinished => "move unfinished",
+            Status::StaleKey => "stale key",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Work out how a single path is out of sync
+/// # Arguments
+/// * `disk` - The Cid of the file on disk, if it exists
+/// * `manifest` - The CrudFile in the manifest, if tracked
+/// * `chain` - The CrudFile on chain, if it exists
+/// # Returns
+/// * `Vec<Status>` - Every way the path is out of sync. Empty if it is in sync
+pub fn diff(disk: Option<&Cid>, manifest: Option<&CrudFile>, chain: Option<&CrudFile>) -> Vec<Status> {
+    let mut statuses = Vec::new();
+    match manifest {
+        None => {
+            if chain.is_some() {
+                statuses.push(Status::DriftedOnChain);
+            } else if disk.is_some() {
+                statuses.push(Status::New);
+            }
+        }
+        Some(manifest) => {
+            match disk {
+                None => statuses.push(Status::DeletedLocally),
+                Some(cid) if *cid != manifest.cid => statuses.push(Status::Modified),
+                Some(_) => {}
+            }
+            match chain {
+                None => statuses.push(Status::MissingOnChain),
+                Some(chain) => {
+                    if chain.cid != manifest.cid
+                        || chain.metadata != manifest.metadata
+                        || chain.timestamp != manifest.timestamp
+                    {
+                        statuses.push(Status::DriftedOnChain);
+                    }
+                }
+            }
+        }
+    }
+    statuses
+}
+
+/// Compare the local folder, the manifest and the backend
+/// # Arguments
+/// * `manifest` - The manifest
+/// * `content_root` - What the paths in the manifest and on chain are relative to
+/// * `chain_files` - Every CrudFile on chain. Those left behind by a move are skipped, and moves
+///   that stopped before leaving one are reported at their old path
+/// * `local_files` - Extra paths on disk to check, besides those in the manifest
+/// * `strip_front_matter` - Whether markdown is uploaded without its front matter
+/// * `cid_mode` - How the Cids of uploaded files are computed
+/// # Returns
+/// * `Result<Vec<(PathBuf, Vec<Status>)>, SyncError>` - Every content path and how it is out of sync, sorted by path
+pub fn status(
+    manifest: &Manifest,
+    content_root: &ContentRoot,
+    chain_files: Vec<CrudFile>,
+    local_files: Vec<PathBuf>,
+    strip_front_matter: bool,
+    cid_mode: CidMode,
+) -> Result<Vec<(PathBuf, Vec<Status>)>, SyncError> {
+    let unfinished: BTreeSet<String> = unfinished_moves(manifest, &chain_files)?
+        .into_iter()
+        .map(|(crud_file, _)| hex::encode(crud_file.key))
+        .collect();
+    // Gather every path we know of, keyed by its hashed path
+    let mut paths: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for (key, crud_file) in manifest.files.iter() {
+        paths.insert(key.clone(), crud_file.path.clone());
+    }
+    let chain: BTreeMap<String, CrudFile> = chain_files
+        .into_iter()
+        .filter(|crud_file| crud_file.moved_to().is_none())
+        .map(|crud_file| (hex::encode(crud_file.key), crud_file))
+        .collect();
+    // Files tracked under a stale key are compared with the entry at their key on chain
+    let tracked_keys: BTreeMap<String, String> = manifest
+        .files
+        .iter()
+        .map(|(key, crud_file)| (hex::encode(crud_file.key), key.clone()))
+        .collect();
+    for (key, crud_file) in chain.iter() {
+        if tracked_keys.get(key).is_some_and(|tracked| tracked != key) {
+            continue;
+        }
+        paths.entry(key.clone()).or_insert_with(|| crud_file.path.clone());
+    }
+    for path in local_files {
+        let path = content_root.relative(&path)?;
+        paths.entry(hex::encode(hash_path(&path)?)).or_insert(path);
+    }
+
+    let mut report = Vec::new();
+    for (key, path) in paths {
+        let local = content_root.local(&path);
+        let disk = if local.is_file() {
+            Some(front_matter::cid(&local, strip_front_matter, cid_mode)?)
+        } else {
+            None
+        };
+        let tracked = manifest.files.get(&key);
+        let statuses = if unfinished.contains(&key) {
+            vec![Status::MoveUnfinished]
+        } else {
+            let chain_key = tracked.map(|crud_file| hex::encode(crud_file.key)).unwrap_or_else(|| key.clone());
+            let mut statuses = diff(disk.as_ref(), tracked, chain.get(&chain_key));
+            if chain_key != key {
+                statuses.push(Status::StaleKey);
+            }
+            statuses
+        };
+        report.push((path, statuses));
+    }
+    report.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sync::types::metadata::Metadata;
+
+    fn crud_file(cid: &Cid, timestamp: u64) -> CrudFile {
+        let path = PathBuf::from("content/hello.md");
+        CrudFile {
+            key: hash_path(&path).unwrap(),
+            filename: "hello.md".to_string(),
+            path,
+            cid: cid.clone(),
+            timestamp,
+            metadata: Metadata::new(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Cid::from(b"old".as_slice());
+        let new = Cid::from(b"new".as_slice());
+        let tracked = crud_file(&old, 1);
+
+        // In sync
+        assert!(diff(Some(&old), Some(&tracked), Some(&tracked)).is_empty());
+        // Untracked file on disk
+        assert_eq!(diff(Some(&new), None, None), vec![Status::New]);
+        // Edited on disk
+        assert_eq!(diff(Some(&new), Some(&tracked), Some(&tracked)), vec![Status::Modified]);
+        // Removed from disk
+        assert_eq!(diff(None, Some(&tracked), Some(&tracked)), vec![Status::DeletedLocally]);
+        // Never made it on chain
+        assert_eq!(diff(Some(&old), Some(&tracked), None), vec![Status::MissingOnChain]);
+        // Updated on chain by someone else
+        let updated = crud_file(&new, 2);
+        assert_eq!(diff(Some(&old), Some(&tracked), Some(&updated)), vec![Status::DriftedOnChain]);
+        // Created on chain by someone else
+        assert_eq!(diff(None, None, Some(&updated)), vec![Status::DriftedOnChain]);
+        // Edited on disk and on chain
+        assert_eq!(
+            diff(Some(&new), Some(&tracked), Some(&updated)),
+            vec![Status::Modified, Status::DriftedOnChain]
+        );
+    }
+
+    #[tokio::test]
+    /// A folder, its manifest and a backend compared end to end
+    async fn test_status() {
+        use crate::plan::{apply, plan};
+        use sync::{
+            crud_fs::{backend::InMemoryBackend, crud_fs::CrudFs, store::{LocalStore, StoreClient}},
+            utils::walk::walk_dir,
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        std::fs::create_dir_all(&content).unwrap();
+        let content_root = ContentRoot::new(Some(content.clone()));
+        let crud_fs = CrudFs::with_clients(
+            Box::new(InMemoryBackend::new()),
+            StoreClient::new(Box::new(LocalStore::new(dir.path().join("store")))),
+        )
+        .with_content_root(content_root.clone());
+
+        // Push two files
+        let mut manifest = Manifest::new("".to_string());
+        for name in ["synced.md", "drifted.md"] {
+            std::fs::write(content.join(name), name).unwrap();
+        }
+        let actions = plan(&manifest, &content_root, &content, walk_dir(&content).unwrap(), &[], false, CidMode::default()).unwrap();
+        apply(&crud_fs, &mut manifest, actions).await.unwrap();
+
+        // Someone else updates one of them on chain, and creates another
+        let drifted = manifest.get(&PathBuf::from("drifted.md")).unwrap().unwrap().clone();
+        std::fs::write(content.join("drifted.md"), "edited elsewhere").unwrap();
+        crud_fs.update(drifted).await.unwrap();
+        std::fs::write(content.join("drifted.md"), "drifted.md").unwrap();
+        let remote = content.join("remote.md");
+        std::fs::write(&remote, "remote").unwrap();
+        crud_fs.create(remote.clone(), Cid::from(b"remote".as_slice()), Default::default()).await.unwrap();
+        std::fs::remove_file(&remote).unwrap();
+
+        // A file is tracked that never made it on chain, and another is only on disk
+        std::fs::write(content.join("missing.md"), "missing").unwrap();
+        let mut missing = CrudFile::new(content.join("missing.md")).unwrap();
+        missing.path = PathBuf::from("missing.md");
+        missing.key = hash_path(&missing.path).unwrap();
+        manifest.add(missing).unwrap();
+        std::fs::write(content.join("new.md"), "new").unwrap();
+
+        let chain_files = crud_fs.list().await.unwrap();
+        let report = status(&manifest, &content_root, chain_files, walk_dir(&content).unwrap(), false, CidMode::default()).unwrap();
+        let report: Vec<(&str, Vec<Status>)> = report
+            .iter()
+            .map(|(path, statuses)| (path.to_str().unwrap(), statuses.clone()))
+            .collect();
+        assert_eq!(
+            report,
+            vec![
+                ("drifted.md", vec![Status::DriftedOnChain]),
+                ("missing.md", vec![Status::MissingOnChain]),
+                ("new.md", vec![Status::New]),
+                ("remote.md", vec![Status::DriftedOnChain]),
+                ("synced.md", vec![]),
+            ]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::{Error, Result};
+use crate::error::{IntegrityError, Result, SyncError};
 use cid::{
     multihash::{Code, Hasher, MultihashDigest, Sha2_256},
     Cid as _Cid,
@@ -6,17 +6,51 @@ use cid::{
 use ethers::abi::{InvalidOutputType, Token, Tokenizable};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::path::PathBuf;
-use std::{
-    convert::TryFrom,
-    fs::File,
-    io::{Read, Write},
-};
+use std::{convert::TryFrom, fmt, fs::File, io::Read, str::FromStr};
+
+use crate::types::unixfs::{UnixFsBuilder, UnixFsOptions, RAW_CODEC};
 
 #[derive(Debug, Clone)]
 pub struct Cid {
     cid: _Cid,
 }
 
+/// How a CID is computed from content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CidMode {
+    /// One raw block over the whole content. Only matches `ipfs add` for content that fits in one chunk
+    Raw,
+    /// A chunked UnixFS DAG, as `ipfs add` builds it
+    UnixFs(UnixFsOptions),
+}
+
+impl Default for CidMode {
+    /// Match `ipfs add --cid-version=1`
+    fn default() -> Self {
+        CidMode::UnixFs(UnixFsOptions::default())
+    }
+}
+
+impl FromStr for CidMode {
+    type Err = SyncError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unixfs" => Ok(CidMode::default()),
+            "raw" => Ok(CidMode::Raw),
+            other => Err(SyncError::Config(format!("Unknown CID mode: {}", other))),
+        }
+    }
+}
+
+impl fmt::Display for CidMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CidMode::Raw => write!(f, "raw"),
+            CidMode::UnixFs(_) => write!(f, "unixfs"),
+        }
+    }
+}
+
 impl Serialize for Cid {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -32,14 +66,14 @@ impl<'de> Deserialize<'de> for Cid {
         D: Deserializer<'de>,
     {
         let cid = String::deserialize(deserializer)?;
-        let _cid = _Cid::try_from(cid).unwrap();
+        let _cid = _Cid::try_from(cid).map_err(serde::de::Error::custom)?;
         Ok(Self { cid: _cid })
     }
 }
 
 /// Impl TryFrom for Cid for Files
 impl TryFrom<File> for Cid {
-    type Error = Error;
+    type Error = SyncError;
     /// Read the file and create a CID
     /// #Example
     /// ```
@@ -66,24 +100,34 @@ impl TryFrom<File> for Cid {
     /// std::fs::remove_file("test.txt").unwrap();
     /// ```
     fn try_from(file: File) -> Result<Self, Self::Error> {
-        let mut hasher = Sha2_256::default();
-        let mut buffer = [0; 1024];
-        let mut file = file;
-        loop {
-            let count = file.read(&mut buffer)?;
-            if count == 0 {
-                break;
-            }
-            hasher.update(&buffer[..count]);
-        }
-        let hash = Code::Sha2_256.wrap(&hasher.finalize()).unwrap();
-        let cid = _Cid::new_v1(0x55, hash);
-        Ok(Self { cid })
+        Cid::from_reader(file, CidMode::default())
+    }
+}
+
+/// Impl From for Cid for in-memory content
+impl From<&[u8]> for Cid {
+    /// Hash the bytes and create a CID, the same way as for Files
+    /// #Example
+    /// ```
+    /// use sync::types::cid::Cid;
+    /// let cid = Cid::from(b"hello".as_slice());
+    /// let expected = "bafkreibm6jg3ux5qumhcn2b3flc3tyu6dmlb4xa7u5bf44yegnrjhc4yeq";
+    /// assert_eq!(cid.to_string(), expected);
+    /// ```
+    fn from(data: &[u8]) -> Self {
+        // Reading from a slice can't fail
+        Cid::from_reader(data, CidMode::default()).unwrap()
+    }
+}
+
+impl From<_Cid> for Cid {
+    fn from(cid: _Cid) -> Self {
+        Self { cid }
     }
 }
 
 impl TryFrom<PathBuf> for Cid {
-    type Error = Error;
+    type Error = SyncError;
     fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
         let file = File::open(path)?;
         let cid = Cid::try_from(file)?;
@@ -92,7 +136,7 @@ impl TryFrom<PathBuf> for Cid {
 }
 
 impl TryFrom<&PathBuf> for Cid {
-    type Error = Error;
+    type Error = SyncError;
     fn try_from(path: &PathBuf) -> Result<Self, Self::Error> {
         let file = File::open(path)?;
         let cid = Cid::try_from(file)?;
@@ -104,10 +148,11 @@ impl Tokenizable for Cid {
     fn from_token(token: Token) -> Result<Self, InvalidOutputType> {
         // If the token is a string, convert it to a Cid
         if let Token::String(cid) = token {
-            let _cid = _Cid::try_from(cid).unwrap();
+            let _cid = _Cid::try_from(cid)
+                .map_err(|e| InvalidOutputType(format!("Invalid CID: {}", e)))?;
             Ok(Self { cid: _cid })
         } else {
-            return Err(InvalidOutputType("Token is not a string".to_string()));
+            Err(InvalidOutputType("Token is not a string".to_string()))
         }
     }
 
@@ -116,12 +161,117 @@ impl Tokenizable for Cid {
     }
 }
 
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.cid)
+    }
+}
+
 impl Cid {
-    pub fn to_string(&self) -> String {
-        self.cid.to_string()
+    /// Read content and create a CID
+    /// # Arguments
+    /// * `reader` - The content
+    /// * `mode` - How to compute the CID
+    /// # Returns
+    /// * `Result<Cid, SyncError>` - The CID, or an error if the content can't be read
+    pub fn from_reader<R: Read>(reader: R, mode: CidMode) -> Result<Self, SyncError> {
+        let cid = match mode {
+            CidMode::Raw => {
+                let mut hasher = Sha2_256::default();
+                let mut buffer = [0; 1024];
+                let mut reader = reader;
+                loop {
+                    let count = reader.read(&mut buffer)?;
+                    if count == 0 {
+                        break;
+                    }
+                    hasher.update(&buffer[..count]);
+                }
+                let hash = Code::Sha2_256.wrap(hasher.finalize())?;
+                _Cid::new_v1(RAW_CODEC, hash)
+            }
+            CidMode::UnixFs(options) => UnixFsBuilder::new(reader, options).build()?,
+        };
+        Ok(Self { cid })
     }
-    pub fn from_str(cid: String) -> Result<Self, Error> {
-        let _cid = _Cid::try_from(cid).unwrap();
+
+    /// Check whether content hashes to this CID
+    /// Raw CIDs are checked as one block, so CIDs from before chunking still verify.
+    /// UnixFS CIDs are checked with the default options, with either kind of leaf
+    /// # Arguments
+    /// * `data` - The content
+    ///
+    /// #Example
+    /// ```
+    /// use sync::types::cid::{Cid, CidMode};
+    /// // More than one chunk
+    /// let data = vec![0u8; 300_000];
+    /// let cid = Cid::from(data.as_slice());
+    /// assert!(cid.matches(&data));
+    /// // A CID from before chunking is different, but still verifies
+    /// let legacy = Cid::from_reader(data.as_slice(), CidMode::Raw).unwrap();
+    /// assert!(legacy != cid);
+    /// assert!(legacy.matches(&data));
+    /// assert!(!legacy.matches(b"something else"));
+    /// ```
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.modes()
+            .into_iter()
+            .any(|mode| Cid::from_reader(data, mode).map(|cid| cid == *self).unwrap_or(false))
+    }
+
+    /// The modes this CID could have been computed with, most likely first
+    /// # Returns
+    /// * `Vec<CidMode>` - Raw for raw CIDs, otherwise UnixFS with either kind of leaf
+    pub fn modes(&self) -> Vec<CidMode> {
+        if self.cid.codec() == RAW_CODEC {
+            vec![CidMode::Raw]
+        } else {
+            let options = UnixFsOptions::default();
+            vec![
+                CidMode::UnixFs(options),
+                CidMode::UnixFs(UnixFsOptions { raw_leaves: false, ..options }),
+            ]
+        }
+    }
+
+    /// Rebuild the blocks behind this CID from content, root last
+    /// # Arguments
+    /// * `data` - The content
+    /// # Returns
+    /// * `Result<Vec<(_Cid, Vec<u8>)>, SyncError>` - The blocks, or an error if the content doesn't match
+    pub fn blocks(&self, data: &[u8]) -> Result<Vec<(_Cid, Vec<u8>)>, SyncError> {
+        if self.cid.codec() == RAW_CODEC {
+            if Cid::from_reader(data, CidMode::Raw)? == *self {
+                return Ok(vec![(self.cid, data.to_vec())]);
+            }
+        } else {
+            for raw_leaves in [true, false] {
+                let options = UnixFsOptions { raw_leaves, ..Default::default() };
+                let mut blocks = Vec::new();
+                let mut on_block = |cid: _Cid, block: Vec<u8>| blocks.push((cid, block));
+                let root = UnixFsBuilder::new(data, options)
+                    .with_blocks(&mut on_block)
+                    .build()?;
+                if root == self.cid {
+                    return Ok(blocks);
+                }
+            }
+        }
+        Err(SyncError::from(IntegrityError {
+            expected: self.clone(),
+            actual: Cid::from(data),
+        }))
+    }
+
+    /// Parse a CID from its string form
+    /// # Arguments
+    /// * `cid` - The CID, e.g. `bafkrei...`
+    /// # Returns
+    /// * `Result<Cid, SyncError>` - The CID, or an error if it can't be parsed
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(cid: String) -> Result<Self, SyncError> {
+        let _cid = _Cid::try_from(cid)?;
         Ok(Self { cid: _cid })
     }
 }
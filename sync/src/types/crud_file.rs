@@ -1,12 +1,17 @@
-use anyhow::{Error, Result};
+use crate::error::{Result, SyncError};
 use ethers::abi::{InvalidOutputType, Token, Tokenizable};
 use serde::{Deserialize, Serialize};
-use std::io::ErrorKind::InvalidInput;
-use std::{collections::HashMap, fs::File, path::PathBuf};
+use std::{fs::File, path::PathBuf};
 
-use crate::utils::hash::hash_path;
+use crate::utils::{
+    hash::{hash_path, hash_str},
+    path::normalize,
+};
 // Use our own Cid struct
-use crate::types::{cid::Cid, metadata::Metadata};
+use crate::types::{
+    cid::Cid,
+    metadata::{Metadata, ALIASES, MOVED_TO},
+};
 
 // File Object - Represents a post entry in the manifest
 /// # Fields
@@ -32,21 +37,26 @@ pub struct CrudFile {
 }
 
 impl CrudFile {
-    /// New FileObject
+    /// New FileObject. The path is stored, and keyed, in canonical form
     /// # Arguments
     /// * `path` - The path to the file
     /// # Returns
-    /// Result<FileObject, Error>
+    /// Result<FileObject, SyncError>
     /// # Errors
-    /// * `Error` - If the path is invalid
-    /// * `Error` - If the file cannot be read
-    /// * `Error` - If the CID cannot be created
-    pub fn new(path: PathBuf) -> Result<Self, Error> {
+    /// * `SyncError::Path` - If the path is invalid
+    /// * `SyncError::Io` - If the file cannot be read
+    /// * `SyncError::Io` - If the CID cannot be created
+    pub fn new(path: PathBuf) -> Result<Self, SyncError> {
         let file = File::open(&path)?;
-        let filename = path.file_name().unwrap().to_str().unwrap().to_string();
+        let path = normalize(&path)?;
+        let filename = path
+            .file_name()
+            .and_then(|filename| filename.to_str())
+            .ok_or_else(|| SyncError::Path(format!("{} has no valid filename", path.display())))?
+            .to_string();
         let key = hash_path(&path)?;
         let cid = Cid::try_from(file)?;
-        let timestamp = 0 as u64;
+        let timestamp = 0;
         let metadata = Metadata::new();
         Ok(Self {
             path,
@@ -62,8 +72,8 @@ impl CrudFile {
     /// # Arguments
     /// * `metadata` - The metadata to set
     /// # Returns
-    /// Result<(), Error>
-    pub fn set_metadata(&mut self, metadata: Metadata) -> Result<(), Error> {
+    /// Result<(), SyncError>
+    pub fn set_metadata(&mut self, metadata: Metadata) -> Result<(), SyncError> {
         self.metadata = metadata;
         Ok(())
     }
@@ -72,11 +82,28 @@ impl CrudFile {
     /// # Arguments
     /// * `timestamp` - The timestamp to set
     /// # Returns
-    /// Result<(), Error>
-    pub fn set_timestamp(&mut self, timestamp: u64) -> Result<(), Error> {
+    /// Result<(), SyncError>
+    pub fn set_timestamp(&mut self, timestamp: u64) -> Result<(), SyncError> {
         self.timestamp = timestamp;
         Ok(())
     }
+
+    /// Where the file moved to, if this is the entry left at its old path
+    /// # Returns
+    /// Option<PathBuf> - The path to read instead
+    pub fn moved_to(&self) -> Option<PathBuf> {
+        self.metadata.get(MOVED_TO).map(PathBuf::from)
+    }
+
+    /// The paths the file was moved from, oldest first
+    /// # Returns
+    /// Vec<PathBuf> - The old paths. Empty if the file was never moved, or they can't be read
+    pub fn aliases(&self) -> Vec<PathBuf> {
+        self.metadata
+            .get(ALIASES)
+            .and_then(|aliases| serde_json::from_str::<Vec<PathBuf>>(aliases).ok())
+            .unwrap_or_default()
+    }
 }
 
 impl Tokenizable for CrudFile {
@@ -104,12 +131,11 @@ impl Tokenizable for CrudFile {
         };
         // Get the filename from the path
         let filename = match path.file_name() {
-            Some(filename) => filename.to_str().unwrap().to_string(),
+            Some(filename) => filename.to_string_lossy().to_string(),
             None => return Err(InvalidOutputType("Expected `String`".to_string())),
         };
-        // Determine the Key from the path
-        let key = hash_path(&path)
-            .map_err(|e| InvalidOutputType(format!("Could not hash path: {:?}", e)))?;
+        // Determine the Key from the path, exactly as the contract did
+        let key = hash_str(&path.to_string_lossy());
         // Get the CID
         let cid = match tokens.next() {
             Some(Token::String(cid)) => Cid::from_str(cid)
@@ -157,9 +183,10 @@ impl Tokenizable for CrudFile {
 
     /// Convert a FileObject into a Token (for the purpose of C and U functions)
     fn into_token(self) -> Token {
-        let path = self.path.to_str().unwrap();
+        let path = self.path.to_string_lossy();
         let cid = self.cid.to_string();
-        let metadata = serde_json::to_string(&self.metadata).unwrap();
+        // A map of strings always serializes
+        let metadata = serde_json::to_string(&self.metadata).unwrap_or_default();
         Token::Tuple(vec![
             Token::String(path.to_string()),
             Token::String(cid),
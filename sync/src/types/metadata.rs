@@ -1,3 +1,303 @@
-use std::collections::HashMap;
+use crate::config::Config;
+use crate::error::{Result, SyncError};
+use chrono::{DateTime, NaiveDate};
+use jsonschema::JSONSchema;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::path::Path;
 
 pub type Metadata = HashMap<String, String>;
+
+/// How large a file's metadata may be on chain, in bytes, unless configured otherwise
+pub const DEFAULT_MAX_METADATA_BYTES: usize = 4096;
+
+/// The key of the paths a file was moved from, as a JSON array, oldest first
+pub const ALIASES: &str = "aliases";
+
+/// The key of the entry left at a file's old path, holding the path it moved to
+pub const MOVED_TO: &str = "moved_to";
+
+/// PostMetadata - The metadata of a blog post, typed.
+/// On chain, metadata is a JSON object of strings, so `tags` is kept as a JSON array inside a
+/// string, as front matter writes it. Keys we don't know pass through untouched in `extra`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PostMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub author: Option<String>,
+    /// When the post was published, from `date`
+    pub date: Option<NaiveDate>,
+    /// Whether the post is a draft, from `draft`
+    pub draft: bool,
+    /// The MIME type of the post, from `content_type`
+    pub content_type: Option<String>,
+    /// The paths the post was moved from, oldest first, from `aliases`
+    pub aliases: Vec<String>,
+    /// Where the post moved to, if this is the entry left at its old path, from `moved_to`
+    pub moved_to: Option<String>,
+    /// Every other key, as it is
+    pub extra: BTreeMap<String, String>,
+}
+
+impl PostMetadata {
+    /// Read the metadata string stored on chain
+    /// # Arguments
+    /// * `json` - The metadata, as a JSON object of strings
+    /// # Returns
+    /// * `Result<PostMetadata, SyncError>` - A metadata error if a known key has the wrong type
+    pub fn from_json(json: &str) -> Result<Self, SyncError> {
+        let metadata: Metadata = serde_json::from_str(json)?;
+        Self::try_from(metadata)
+    }
+
+    /// Write the metadata string to store on chain
+    /// # Returns
+    /// * `Result<String, SyncError>` - The metadata, as a JSON object of strings
+    pub fn to_json(&self) -> Result<String, SyncError> {
+        Ok(serde_json::to_string(&Metadata::from(self.clone()))?)
+    }
+
+    /// The metadata with its real types, e.g. `tags` as an array and `draft` as a boolean.
+    /// This is what a JSON Schema is checked against
+    pub fn to_value(&self) -> Value {
+        let mut object: Map<String, Value> = self
+            .extra
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+            .collect();
+        let mut set = |key: &str, value: Option<Value>| {
+            if let Some(value) = value {
+                object.insert(key.to_string(), value);
+            }
+        };
+        set("title", self.title.clone().map(Value::String));
+        set("description", self.description.clone().map(Value::String));
+        set("tags", (!self.tags.is_empty()).then(|| json!(self.tags)));
+        set("author", self.author.clone().map(Value::String));
+        set("date", self.date.map(|date| Value::String(date.to_string())));
+        set("draft", Some(Value::Bool(self.draft)));
+        set("content_type", self.content_type.clone().map(Value::String));
+        set(ALIASES, (!self.aliases.is_empty()).then(|| json!(self.aliases)));
+        set(MOVED_TO, self.moved_to.clone().map(Value::String));
+        Value::Object(object)
+    }
+}
+
+impl TryFrom<Metadata> for PostMetadata {
+    type Error = SyncError;
+    fn try_from(metadata: Metadata) -> Result<Self, Self::Error> {
+        let mut post = PostMetadata::default();
+        for (key, value) in metadata {
+            match key.as_str() {
+                "title" => post.title = Some(value),
+                "description" => post.description = Some(value),
+                "tags" => post.tags = parse_list("tags", &value)?,
+                "author" => post.author = Some(value),
+                "date" => post.date = Some(parse_date(&value)?),
+                "draft" => {
+                    post.draft = value
+                        .parse()
+                        .map_err(|_| SyncError::Metadata(format!("draft must be true or false, not {:?}", value)))?
+                }
+                "content_type" => post.content_type = Some(value),
+                ALIASES => post.aliases = parse_list(ALIASES, &value)?,
+                MOVED_TO => post.moved_to = Some(value),
+                _ => {
+                    post.extra.insert(key, value);
+                }
+            }
+        }
+        Ok(post)
+    }
+}
+
+impl From<PostMetadata> for Metadata {
+    fn from(post: PostMetadata) -> Self {
+        let mut metadata: Metadata = post.extra.into_iter().collect();
+        let mut set = |key: &str, value: Option<String>| {
+            if let Some(value) = value {
+                metadata.insert(key.to_string(), value);
+            }
+        };
+        set("title", post.title);
+        set("description", post.description);
+        set("tags", (!post.tags.is_empty()).then(|| json!(post.tags).to_string()));
+        set("author", post.author);
+        set("date", post.date.map(|date| date.to_string()));
+        set("draft", post.draft.then(|| true.to_string()));
+        set("content_type", post.content_type);
+        set(ALIASES, (!post.aliases.is_empty()).then(|| json!(post.aliases).to_string()));
+        set(MOVED_TO, post.moved_to);
+        metadata
+    }
+}
+
+/// Lists, like tags, are a JSON array, as front matter lists are stored, or else separated by commas
+fn parse_list(key: &str, list: &str) -> Result<Vec<String>, SyncError> {
+    if list.trim_start().starts_with('[') {
+        return serde_json::from_str(list)
+            .map_err(|_| SyncError::Metadata(format!("{} must be a list of strings, not {}", key, list)));
+    }
+    Ok(list
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Dates are `YYYY-MM-DD`, or an RFC 3339 timestamp of which we keep the day
+fn parse_date(date: &str) -> Result<NaiveDate, SyncError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| DateTime::parse_from_rfc3339(date).map(|date| date.date_naive()))
+        .map_err(|_| SyncError::Metadata(format!("date must be YYYY-MM-DD, not {:?}", date)))
+}
+
+/// MetadataPolicy - What metadata has to satisfy before it is sent to the backend:
+/// a size limit, the types of PostMetadata, and optionally a JSON Schema
+pub struct MetadataPolicy {
+    max_bytes: usize,
+    schema: Option<JSONSchema>,
+}
+
+impl Default for MetadataPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_METADATA_BYTES)
+    }
+}
+
+impl MetadataPolicy {
+    /// Create a policy with a size limit and no schema
+    /// # Arguments
+    /// * `max_bytes` - How large the metadata may be on chain, in bytes
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            schema: None,
+        }
+    }
+
+    /// Create the configured policy, reading the schema at `metadata_schema` if there is one
+    /// # Arguments
+    /// * `config` - The resolved configuration
+    /// # Returns
+    /// * `Result<MetadataPolicy, SyncError>` - A configuration error if the schema can't be read
+    pub fn from_config(config: &Config) -> Result<Self, SyncError> {
+        let policy = Self::new(config.metadata_max_bytes);
+        match &config.metadata_schema {
+            Some(path) => {
+                let schema = std::fs::read_to_string(path)
+                    .map_err(|e| SyncError::Config(format!("Could not read {}: {}", path.display(), e)))?;
+                let schema: Value = serde_json::from_str(&schema)
+                    .map_err(|e| SyncError::Config(format!("Could not parse {}: {}", path.display(), e)))?;
+                policy.with_schema(&schema)
+            }
+            None => Ok(policy),
+        }
+    }
+
+    /// Check metadata against a JSON Schema as well
+    /// # Arguments
+    /// * `schema` - The schema, checked against PostMetadata::to_value
+    /// # Returns
+    /// * `Result<MetadataPolicy, SyncError>` - A configuration error if the schema is invalid
+    pub fn with_schema(mut self, schema: &Value) -> Result<Self, SyncError> {
+        let schema = JSONSchema::compile(schema)
+            .map_err(|e| SyncError::Config(format!("Invalid metadata schema: {}", e)))?;
+        self.schema = Some(schema);
+        Ok(self)
+    }
+
+    /// Check a file's metadata
+    /// # Arguments
+    /// * `path` - The path of the file, to name in errors
+    /// * `metadata` - The metadata
+    /// # Returns
+    /// * `Result<(), SyncError>` - A metadata error saying everything that is wrong
+    pub fn check(&self, path: &Path, metadata: &Metadata) -> Result<(), SyncError> {
+        let size = serde_json::to_string(metadata)?.len();
+        if size > self.max_bytes {
+            return Err(SyncError::Metadata(format!(
+                "{}: metadata is {} bytes, over the limit of {}",
+                path.display(),
+                size,
+                self.max_bytes
+            )));
+        }
+        let post = PostMetadata::try_from(metadata.clone())
+            .map_err(|e| SyncError::Metadata(format!("{}: {}", path.display(), e)))?;
+        if let Some(schema) = &self.schema {
+            let value = post.to_value();
+            let errors: Vec<String> = match schema.validate(&value) {
+                Ok(()) => Vec::new(),
+                Err(errors) => errors
+                    .map(|e| match e.instance_path.to_string().as_str() {
+                        "" => e.to_string(),
+                        key => format!("{}: {}", key.trim_start_matches('/'), e),
+                    })
+                    .collect(),
+            };
+            if !errors.is_empty() {
+                return Err(SyncError::Metadata(format!("{}: {}", path.display(), errors.join("; "))));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(pairs: &[(&str, &str)]) -> Metadata {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    /// Known keys are typed, unknown keys pass through, and it all survives a round trip
+    fn test_post_metadata() {
+        let json = r#"{"title":"Hello","tags":"[\"rust\",\"ipfs\"]","date":"2023-02-01","draft":"true","series":"intro"}"#;
+        let post = PostMetadata::from_json(json).unwrap();
+        assert_eq!(post.title.as_deref(), Some("Hello"));
+        assert_eq!(post.tags, vec!["rust", "ipfs"]);
+        assert_eq!(post.date, NaiveDate::from_ymd_opt(2023, 2, 1));
+        assert!(post.draft);
+        assert_eq!(post.extra["series"], "intro");
+        assert_eq!(PostMetadata::from_json(&post.to_json().unwrap()).unwrap(), post);
+        assert_eq!(post.to_value()["tags"], json!(["rust", "ipfs"]));
+
+        // Comma separated tags, and timestamps, are understood too
+        let post = PostMetadata::try_from(metadata(&[("tags", "a, b,"), ("date", "2023-02-01T10:00:00Z")])).unwrap();
+        assert_eq!(post.tags, vec!["a", "b"]);
+        assert_eq!(post.date, NaiveDate::from_ymd_opt(2023, 2, 1));
+
+        assert!(PostMetadata::try_from(metadata(&[("draft", "maybe")])).is_err());
+        assert!(PostMetadata::try_from(metadata(&[("date", "yesterday")])).is_err());
+    }
+
+    #[test]
+    /// Metadata that is too large or doesn't match the schema is refused
+    fn test_policy() {
+        let path = Path::new("post.md");
+        let policy = MetadataPolicy::new(64);
+        assert!(policy.check(path, &metadata(&[("title", "Hello")])).is_ok());
+        let large = "a".repeat(64);
+        let err = policy.check(path, &metadata(&[("title", &large)])).unwrap_err();
+        assert!(matches!(err, SyncError::Metadata(_)));
+
+        let schema = json!({
+            "type": "object",
+            "required": ["title"],
+            "properties": { "tags": { "type": "array", "maxItems": 1 } }
+        });
+        let policy = MetadataPolicy::default().with_schema(&schema).unwrap();
+        assert!(policy.check(path, &metadata(&[("title", "Hello"), ("series", "intro")])).is_ok());
+        let err = policy.check(path, &metadata(&[("tags", "a, b")])).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("title"), "{}", message);
+        assert!(message.contains("tags"), "{}", message);
+        assert!(MetadataPolicy::default().with_schema(&json!({"type": 1})).is_err());
+    }
+}
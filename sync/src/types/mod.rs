@@ -1,3 +1,4 @@
 pub mod cid;
 pub mod crud_file;
 pub mod metadata;
+pub mod unixfs;
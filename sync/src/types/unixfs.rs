@@ -0,0 +1,452 @@
+use crate::error::{Result, SyncError};
+use cid::{
+    multihash::{Code, MultihashDigest},
+    Cid as _Cid,
+};
+use std::{collections::HashMap, io::Read};
+
+/// The multicodec for raw blocks
+pub const RAW_CODEC: u64 = 0x55;
+/// The multicodec for dag-pb blocks
+pub const DAG_PB_CODEC: u64 = 0x70;
+/// The chunk size `ipfs add` uses by default (`size-262144`)
+pub const DEFAULT_CHUNK_SIZE: usize = 262144;
+/// The most links `ipfs add` puts in one node
+pub const DEFAULT_MAX_LINKS: usize = 174;
+
+/// The UnixFS `Data.DataType` for files
+const UNIXFS_FILE: u64 = 2;
+
+/// Options for building a UnixFS file DAG
+/// The defaults match `ipfs add --cid-version=1`
+/// # Fields
+/// * `chunk_size` - The size of each leaf, in bytes
+/// * `max_links` - The most children a node can have
+/// * `raw_leaves` - Whether leaves are raw blocks, rather than dag-pb UnixFS nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnixFsOptions {
+    pub chunk_size: usize,
+    pub max_links: usize,
+    pub raw_leaves: bool,
+}
+
+impl Default for UnixFsOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_links: DEFAULT_MAX_LINKS,
+            raw_leaves: true,
+        }
+    }
+}
+
+/// A built node, as seen from its parent
+struct Link {
+    cid: _Cid,
+    /// The size of the node's block plus all blocks below it
+    tsize: u64,
+    /// The number of bytes of file content below the node
+    filesize: u64,
+}
+
+/// Builds a UnixFS file DAG with a fixed-size chunker and the balanced layout,
+/// the way go-unixfs does for `ipfs add`
+pub struct UnixFsBuilder<'a, R: Read> {
+    reader: R,
+    options: UnixFsOptions,
+    /// The next chunk, if we've had to look ahead
+    peeked: Option<Vec<u8>>,
+    /// Called with every block we build
+    on_block: Option<&'a mut dyn FnMut(_Cid, Vec<u8>)>,
+}
+
+impl<'a, R: Read> UnixFsBuilder<'a, R> {
+    /// Create a new UnixFsBuilder
+    /// # Arguments
+    /// * `reader` - The file content
+    /// * `options` - How to chunk and lay out the DAG
+    pub fn new(reader: R, options: UnixFsOptions) -> Self {
+        Self {
+            reader,
+            options,
+            peeked: None,
+            on_block: None,
+        }
+    }
+
+    /// Hand every block to a callback as it is built, leaves first and the root last
+    /// # Arguments
+    /// * `on_block` - Called with the CID and bytes of each block
+    pub fn with_blocks(mut self, on_block: &'a mut dyn FnMut(_Cid, Vec<u8>)) -> Self {
+        self.on_block = Some(on_block);
+        self
+    }
+
+    /// Build the DAG
+    /// # Returns
+    /// * `Result<_Cid, SyncError>` - The root CID
+    pub fn build(mut self) -> Result<_Cid, SyncError> {
+        // The first root is a single leaf
+        let first = self.next_chunk()?;
+        let mut root = self.leaf(first)?;
+        // Each time the DAG is full, make it the first child of a deeper one
+        let mut depth = 1;
+        while !self.done()? {
+            root = self.fill(vec![root], depth)?;
+            depth += 1;
+        }
+        Ok(root.cid)
+    }
+
+    /// Fill a node of the given depth with children, until it is full or we run out of data
+    fn fill(&mut self, mut children: Vec<Link>, depth: usize) -> Result<Link, SyncError> {
+        while children.len() < self.options.max_links && !self.done()? {
+            let child = if depth == 1 {
+                let chunk = self.next_chunk()?;
+                self.leaf(chunk)?
+            } else {
+                self.fill(Vec::new(), depth - 1)?
+            };
+            children.push(child);
+        }
+        self.node(children)
+    }
+
+    /// Whether all the data has been chunked
+    fn done(&mut self) -> Result<bool, SyncError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.read_chunk()?);
+        }
+        Ok(self.peeked.as_ref().map(|chunk| chunk.is_empty()).unwrap_or(true))
+    }
+
+    /// Take the next chunk. Empty once the data runs out
+    fn next_chunk(&mut self) -> Result<Vec<u8>, SyncError> {
+        match self.peeked.take() {
+            Some(chunk) => Ok(chunk),
+            None => self.read_chunk(),
+        }
+    }
+
+    /// Read up to a full chunk from the reader
+    fn read_chunk(&mut self) -> Result<Vec<u8>, SyncError> {
+        let mut chunk = Vec::with_capacity(self.options.chunk_size);
+        (&mut self.reader)
+            .take(self.options.chunk_size as u64)
+            .read_to_end(&mut chunk)?;
+        Ok(chunk)
+    }
+
+    /// Build a leaf from a chunk. Without raw leaves, every leaf is a UnixFS `File` node, the first
+    /// of a multi-chunk file included: go-unixfs only writes `Raw` leaves in the trickle layout,
+    /// and the balanced layout `ipfs add` uses builds them all like a single-chunk root
+    fn leaf(&mut self, chunk: Vec<u8>) -> Result<Link, SyncError> {
+        let filesize = chunk.len() as u64;
+        if self.options.raw_leaves {
+            let cid = self.emit(RAW_CODEC, chunk);
+            return Ok(Link { cid, tsize: filesize, filesize });
+        }
+        let data = if chunk.is_empty() { None } else { Some(chunk.as_slice()) };
+        let block = encode_pb_node(&[], &encode_unixfs_file(data, filesize, &[]));
+        let tsize = block.len() as u64;
+        let cid = self.emit(DAG_PB_CODEC, block);
+        Ok(Link { cid, tsize, filesize })
+    }
+
+    /// Build an internal node over its children
+    fn node(&mut self, children: Vec<Link>) -> Result<Link, SyncError> {
+        let filesize = children.iter().map(|child| child.filesize).sum();
+        let blocksizes: Vec<u64> = children.iter().map(|child| child.filesize).collect();
+        let block = encode_pb_node(&children, &encode_unixfs_file(None, filesize, &blocksizes));
+        let tsize = block.len() as u64 + children.iter().map(|child| child.tsize).sum::<u64>();
+        let cid = self.emit(DAG_PB_CODEC, block);
+        Ok(Link { cid, tsize, filesize })
+    }
+
+    /// Hash a block, and hand it to the callback
+    fn emit(&mut self, codec: u64, block: Vec<u8>) -> _Cid {
+        let cid = _Cid::new_v1(codec, Code::Sha2_256.digest(&block));
+        if let Some(on_block) = self.on_block.as_mut() {
+            on_block(cid, block);
+        }
+        cid
+    }
+}
+
+/// Reassemble a file from the blocks of its UnixFS DAG
+/// # Arguments
+/// * `root` - The root CID of the file
+/// * `blocks` - The blocks of the DAG, by CID
+/// # Returns
+/// * `Result<Vec<u8>, SyncError>` - The file content, or an error if a block is missing or malformed
+pub fn read_file(root: &_Cid, blocks: &HashMap<_Cid, Vec<u8>>) -> Result<Vec<u8>, SyncError> {
+    let mut content = Vec::new();
+    read_node(root, blocks, &mut content)?;
+    Ok(content)
+}
+
+/// Append the content below a node, depth first
+fn read_node(cid: &_Cid, blocks: &HashMap<_Cid, Vec<u8>>, content: &mut Vec<u8>) -> Result<(), SyncError> {
+    let block = blocks
+        .get(cid)
+        .ok_or_else(|| SyncError::Decode(format!("Missing block: {}", cid)))?;
+    match cid.codec() {
+        RAW_CODEC => content.extend_from_slice(block),
+        DAG_PB_CODEC => {
+            let (links, data) = decode_pb_node(block)?;
+            // Leaves carry their content inline; internal nodes only link to it
+            if let Some(data) = decode_unixfs_data(&data)? {
+                content.extend_from_slice(&data);
+            }
+            for link in links.iter() {
+                read_node(link, blocks, content)?;
+            }
+        }
+        codec => return Err(SyncError::Decode(format!("Unsupported codec {:#x}: {}", codec, cid))),
+    }
+    Ok(())
+}
+
+/* Protobuf Encoding */
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn encode_bytes_field(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    encode_varint(buf, field << 3 | 2);
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn encode_varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    encode_varint(buf, field << 3);
+    encode_varint(buf, value);
+}
+
+/// Encode a UnixFS `Data` message for a file
+fn encode_unixfs_file(data: Option<&[u8]>, filesize: u64, blocksizes: &[u64]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_varint_field(&mut buf, 1, UNIXFS_FILE);
+    if let Some(data) = data {
+        encode_bytes_field(&mut buf, 2, data);
+    }
+    encode_varint_field(&mut buf, 3, filesize);
+    for blocksize in blocksizes {
+        encode_varint_field(&mut buf, 4, *blocksize);
+    }
+    buf
+}
+
+/// Encode a dag-pb `PBNode`. Links come before Data, as the dag-pb spec requires
+fn encode_pb_node(links: &[Link], data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for link in links {
+        let mut link_buf = Vec::new();
+        encode_bytes_field(&mut link_buf, 1, &link.cid.to_bytes());
+        // `ipfs add` always writes the (empty) name
+        encode_bytes_field(&mut link_buf, 2, b"");
+        encode_varint_field(&mut link_buf, 3, link.tsize);
+        encode_bytes_field(&mut buf, 2, &link_buf);
+    }
+    encode_bytes_field(&mut buf, 1, data);
+    buf
+}
+
+/* Protobuf Decoding */
+
+/// A decoded protobuf field value
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn decode_varint(bytes: &[u8], pos: &mut usize) -> Result<u64, SyncError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos).ok_or_else(|| SyncError::Decode("Truncated varint".to_string()))?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(SyncError::Decode("Varint too long".to_string()))
+}
+
+/// Split a protobuf message into its fields
+fn decode_fields(bytes: &[u8]) -> Result<Vec<(u64, Field<'_>)>, SyncError> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let key = decode_varint(bytes, &mut pos)?;
+        let value = match key & 7 {
+            0 => Field::Varint(decode_varint(bytes, &mut pos)?),
+            2 => {
+                // The length comes from the block, so check it can't overflow
+                let len = decode_varint(bytes, &mut pos)?;
+                let value = usize::try_from(len)
+                    .ok()
+                    .and_then(|len| pos.checked_add(len))
+                    .and_then(|end| bytes.get(pos..end))
+                    .ok_or_else(|| SyncError::Decode("Truncated protobuf field".to_string()))?;
+                pos += value.len();
+                Field::Bytes(value)
+            }
+            wire_type => return Err(SyncError::Decode(format!("Unsupported protobuf wire type: {}", wire_type))),
+        };
+        fields.push((key >> 3, value));
+    }
+    Ok(fields)
+}
+
+/// Decode a dag-pb `PBNode` into its link CIDs and Data
+fn decode_pb_node(bytes: &[u8]) -> Result<(Vec<_Cid>, Vec<u8>), SyncError> {
+    let mut links = Vec::new();
+    let mut data = Vec::new();
+    for (field, value) in decode_fields(bytes)? {
+        match (field, value) {
+            (1, Field::Bytes(value)) => data = value.to_vec(),
+            (2, Field::Bytes(link)) => {
+                for (field, value) in decode_fields(link)? {
+                    if let (1, Field::Bytes(cid)) = (field, value) {
+                        links.push(_Cid::try_from(cid)?);
+                    }
+                }
+            }
+            _ => return Err(SyncError::Decode("Malformed dag-pb node".to_string())),
+        }
+    }
+    Ok((links, data))
+}
+
+/// Decode the inline content of a UnixFS `Data` message for a file
+fn decode_unixfs_data(bytes: &[u8]) -> Result<Option<Vec<u8>>, SyncError> {
+    let mut data = None;
+    for (field, value) in decode_fields(bytes)? {
+        match (field, value) {
+            (1, Field::Varint(data_type)) if data_type != UNIXFS_FILE && data_type != 0 => {
+                return Err(SyncError::Decode("Not a UnixFS file".to_string()));
+            }
+            (2, Field::Bytes(value)) => data = Some(value.to_vec()),
+            _ => {}
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(data: &[u8], options: UnixFsOptions) -> _Cid {
+        UnixFsBuilder::new(data, options).build().unwrap()
+    }
+
+    #[test]
+    /// Single dag-pb leaves match the well known CIDv0s
+    fn test_pb_leaves() {
+        let options = UnixFsOptions { raw_leaves: false, ..Default::default() };
+        let empty = build(b"", options);
+        assert_eq!(
+            _Cid::new_v0(*empty.hash()).unwrap().to_string(),
+            "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+        );
+        let hello = build(b"hello\n", options);
+        assert_eq!(
+            _Cid::new_v0(*hello.hash()).unwrap().to_string(),
+            "QmZULkCELmmk5XNfCgTnCyFgAVxBRBXyDHGGMVoLFLiXEN"
+        );
+    }
+
+    #[test]
+    /// A file that fits in one chunk is just its raw leaf
+    fn test_raw_leaf() {
+        let cid = build(b"hello", UnixFsOptions::default());
+        assert_eq!(cid.codec(), RAW_CODEC);
+        assert_eq!(cid.to_string(), "bafkreibm6jg3ux5qumhcn2b3flc3tyu6dmlb4xa7u5bf44yegnrjhc4yeq");
+    }
+
+    #[test]
+    /// The balanced layout keeps every leaf at the same depth
+    fn test_balanced_layout() {
+        let options = UnixFsOptions { chunk_size: 2, max_links: 2, raw_leaves: true };
+        let mut blocks = Vec::new();
+        let mut on_block = |cid: _Cid, block: Vec<u8>| blocks.push((cid, block));
+        // 5 chunks with 2 links per node: a depth 3 tree
+        let root = UnixFsBuilder::new(b"aabbccdde".as_slice(), options)
+            .with_blocks(&mut on_block)
+            .build()
+            .unwrap();
+        let leaves = blocks.iter().filter(|(cid, _)| cid.codec() == RAW_CODEC).count();
+        let nodes = blocks.iter().filter(|(cid, _)| cid.codec() == DAG_PB_CODEC).count();
+        assert_eq!(leaves, 5);
+        // [[[a b] [c d]] [[e]]]: the partial subtree still has full depth
+        assert_eq!(nodes, 6);
+        assert_eq!(blocks.last().unwrap().0, root);
+        assert_eq!(root.codec(), DAG_PB_CODEC);
+    }
+
+    #[test]
+    /// With dag-pb leaves, every leaf of a multi-chunk file is a `File` node carrying its chunk,
+    /// and every internal node a `File` node listing its children's sizes
+    fn test_pb_leaf_types() {
+        let options = UnixFsOptions { chunk_size: 2, max_links: 2, raw_leaves: false };
+        let mut blocks = Vec::new();
+        let mut on_block = |cid: _Cid, block: Vec<u8>| blocks.push((cid, block));
+        UnixFsBuilder::new(b"aabbccdde".as_slice(), options)
+            .with_blocks(&mut on_block)
+            .build()
+            .unwrap();
+        let (mut leaves, mut nodes) = (Vec::new(), 0);
+        for (cid, block) in &blocks {
+            assert_eq!(cid.codec(), DAG_PB_CODEC);
+            let (links, data) = decode_pb_node(block).unwrap();
+            let fields = decode_fields(&data).unwrap();
+            assert!(matches!(fields[0], (1, Field::Varint(UNIXFS_FILE))));
+            if links.is_empty() {
+                leaves.push(decode_unixfs_data(&data).unwrap().unwrap());
+            } else {
+                let blocksizes = fields.iter().filter(|(field, _)| *field == 4).count();
+                assert_eq!(blocksizes, links.len());
+                nodes += 1;
+            }
+        }
+        assert_eq!(leaves, [b"aa".to_vec(), b"bb".to_vec(), b"cc".to_vec(), b"dd".to_vec(), b"e".to_vec()]);
+        assert_eq!(nodes, 6);
+    }
+
+    #[test]
+    /// Files read back the same, with raw or dag-pb leaves
+    fn test_read_file() {
+        let data = b"aabbccdde";
+        for raw_leaves in [true, false] {
+            let options = UnixFsOptions { chunk_size: 2, max_links: 2, raw_leaves };
+            let mut blocks = HashMap::new();
+            let mut on_block = |cid: _Cid, block: Vec<u8>| {
+                blocks.insert(cid, block);
+            };
+            let root = UnixFsBuilder::new(data.as_slice(), options)
+                .with_blocks(&mut on_block)
+                .build()
+                .unwrap();
+            assert_eq!(read_file(&root, &blocks).unwrap(), data);
+        }
+    }
+
+    #[test]
+    /// A field whose length runs past the end, or overflows, is a decode error rather than a panic
+    fn test_bad_field_length() {
+        for len in [u64::MAX, u64::MAX - 1, 100] {
+            let mut bytes = Vec::new();
+            encode_varint(&mut bytes, 2 << 3 | 2);
+            encode_varint(&mut bytes, len);
+            bytes.extend_from_slice(b"data");
+            assert!(matches!(decode_fields(&bytes), Err(SyncError::Decode(_))), "{}", len);
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use crate::error::{IntegrityError, Result, SyncError};
+use crate::types::cid::Cid;
+use cid::{
+    multihash::{Code, MultihashDigest},
+    Cid as _Cid,
+};
+use std::io::Cursor;
+
+/// The fixed CARv2 pragma: a CARv1-style header that just says `{"version": 2}`
+const CARV2_PRAGMA: [u8; 11] = [0x0a, 0xa1, 0x67, b'v', b'e', b'r', b's', b'i', b'o', b'n', 0x02];
+/// The length of the CARv2 header that follows the pragma
+const CARV2_HEADER_LEN: usize = 40;
+
+/// A Content Addressable aRchive: some root CIDs and the blocks behind them
+/// # Fields
+/// * `roots` - The root CIDs
+/// * `blocks` - Every block, in the order it was written
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Car {
+    pub roots: Vec<_Cid>,
+    pub blocks: Vec<(_Cid, Vec<u8>)>,
+}
+
+impl Car {
+    /// Create a new, empty Car
+    /// # Arguments
+    /// * `roots` - The root CIDs
+    pub fn new(roots: Vec<_Cid>) -> Self {
+        Self {
+            roots,
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Add a block
+    /// # Arguments
+    /// * `cid` - The CID of the block
+    /// * `data` - The block
+    pub fn add(&mut self, cid: _Cid, data: Vec<u8>) {
+        self.blocks.push((cid, data));
+    }
+
+    /// Encode as a CARv1 archive
+    /// # Returns
+    /// * `Vec<u8>` - The archive
+    pub fn to_v1(&self) -> Vec<u8> {
+        let header = encode_header(&self.roots);
+        let mut buf = Vec::new();
+        encode_varint(&mut buf, header.len() as u64);
+        buf.extend_from_slice(&header);
+        for (cid, data) in self.blocks.iter() {
+            let cid_bytes = cid.to_bytes();
+            encode_varint(&mut buf, (cid_bytes.len() + data.len()) as u64);
+            buf.extend_from_slice(&cid_bytes);
+            buf.extend_from_slice(data);
+        }
+        buf
+    }
+
+    /// Encode as a CARv2 archive, without an index
+    /// # Returns
+    /// * `Vec<u8>` - The archive
+    pub fn to_v2(&self) -> Vec<u8> {
+        let v1 = self.to_v1();
+        let mut buf = Vec::with_capacity(CARV2_PRAGMA.len() + CARV2_HEADER_LEN + v1.len());
+        buf.extend_from_slice(&CARV2_PRAGMA);
+        // Characteristics
+        buf.extend_from_slice(&[0; 16]);
+        // Data offset, data size and index offset (none)
+        buf.extend_from_slice(&((CARV2_PRAGMA.len() + CARV2_HEADER_LEN) as u64).to_le_bytes());
+        buf.extend_from_slice(&(v1.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&0u64.to_le_bytes());
+        buf.extend_from_slice(&v1);
+        buf
+    }
+
+    /// Decode a CARv1 or CARv2 archive, checking every block against its CID
+    /// # Arguments
+    /// * `bytes` - The archive
+    /// # Returns
+    /// * `Result<Car, SyncError>` - The archive, or an error if it is malformed or a block doesn't match
+    pub fn decode(bytes: &[u8]) -> Result<Self, SyncError> {
+        // Unwrap CARv2 down to the CARv1 payload
+        let bytes = if bytes.starts_with(&CARV2_PRAGMA) {
+            let header = bytes
+                .get(CARV2_PRAGMA.len()..CARV2_PRAGMA.len() + CARV2_HEADER_LEN)
+                .ok_or_else(|| SyncError::Decode("Truncated CARv2 header".to_string()))?;
+            let data_offset = usize::try_from(read_u64(&header[16..24])).unwrap_or(usize::MAX);
+            let data_size = read_u64(&header[24..32]);
+            take(bytes, data_offset, data_size, "CARv2 payload")?
+        } else {
+            bytes
+        };
+
+        let mut pos = 0;
+        let header_len = decode_varint(bytes, &mut pos)?;
+        let header = take(bytes, pos, header_len, "CAR header")?;
+        pos += header.len();
+        let roots = decode_header(header)?;
+
+        let mut car = Car::new(roots);
+        while pos < bytes.len() {
+            let section_len = decode_varint(bytes, &mut pos)?;
+            let section = take(bytes, pos, section_len, "CAR block")?;
+            pos += section.len();
+            let mut cursor = Cursor::new(section);
+            let cid = _Cid::read_bytes(&mut cursor)?;
+            let data = section[cursor.position() as usize..].to_vec();
+            verify_block(&cid, &data)?;
+            car.add(cid, data);
+        }
+        Ok(car)
+    }
+}
+
+/// Check that a block hashes to its CID
+/// # Arguments
+/// * `cid` - The CID of the block
+/// * `data` - The block
+/// # Returns
+/// * `Result<(), SyncError>` - An error if the block doesn't match, or uses an unknown hash
+pub fn verify_block(cid: &_Cid, data: &[u8]) -> Result<(), SyncError> {
+    let code = Code::try_from(cid.hash().code())?;
+    let digest = code.digest(data);
+    if digest != *cid.hash() {
+        return Err(SyncError::from(IntegrityError {
+            expected: Cid::from(*cid),
+            actual: Cid::from(_Cid::new_v1(cid.codec(), digest)),
+        }));
+    }
+    Ok(())
+}
+
+/* Varints */
+
+/// The `len` bytes at `start`. Lengths come from the archive, so however large they are, running
+/// past the end is a decode error naming `what`, never an overflow
+fn take<'a>(bytes: &'a [u8], start: usize, len: u64, what: &str) -> Result<&'a [u8], SyncError> {
+    usize::try_from(len)
+        .ok()
+        .and_then(|len| start.checked_add(len))
+        .and_then(|end| bytes.get(start..end))
+        .ok_or_else(|| SyncError::Decode(format!("Truncated {}", what)))
+}
+
+/// Read a little endian u64 from exactly 8 bytes
+fn read_u64(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0u64, |acc, byte| acc << 8 | *byte as u64)
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn decode_varint(bytes: &[u8], pos: &mut usize) -> Result<u64, SyncError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos).ok_or_else(|| SyncError::Decode("Truncated varint".to_string()))?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(SyncError::Decode("Varint too long".to_string()))
+}
+
+/* DAG-CBOR Header */
+
+/// Encode `{"roots": [...], "version": 1}`. Keys are in DAG-CBOR order: shortest first
+fn encode_header(roots: &[_Cid]) -> Vec<u8> {
+    let mut buf = vec![0xa2];
+    encode_cbor_head(&mut buf, 3, 5);
+    buf.extend_from_slice(b"roots");
+    encode_cbor_head(&mut buf, 4, roots.len() as u64);
+    for root in roots {
+        // Tag 42, then the CID bytes behind a multibase identity prefix
+        buf.extend_from_slice(&[0xd8, 0x2a]);
+        let cid_bytes = root.to_bytes();
+        encode_cbor_head(&mut buf, 2, cid_bytes.len() as u64 + 1);
+        buf.push(0x00);
+        buf.extend_from_slice(&cid_bytes);
+    }
+    encode_cbor_head(&mut buf, 3, 7);
+    buf.extend_from_slice(b"version");
+    buf.push(0x01);
+    buf
+}
+
+fn encode_cbor_head(buf: &mut Vec<u8>, major: u8, len: u64) {
+    let major = major << 5;
+    if len < 24 {
+        buf.push(major | len as u8);
+    } else if len <= u8::MAX as u64 {
+        buf.push(major | 24);
+        buf.push(len as u8);
+    } else if len <= u16::MAX as u64 {
+        buf.push(major | 25);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+    } else if len <= u32::MAX as u64 {
+        buf.push(major | 26);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        buf.push(major | 27);
+        buf.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// The subset of CBOR a CAR header uses
+enum Cbor {
+    Uint(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Tag(u64, Box<Cbor>),
+}
+
+/// Decode a CARv1 header, returning its roots
+fn decode_header(bytes: &[u8]) -> Result<Vec<_Cid>, SyncError> {
+    let mut pos = 0;
+    let header = decode_cbor(bytes, &mut pos)?;
+    let entries = match header {
+        Cbor::Map(entries) => entries,
+        _ => return Err(SyncError::Decode("CAR header is not a map".to_string())),
+    };
+    let mut roots = None;
+    let mut version = None;
+    for (key, value) in entries {
+        match (key, value) {
+            (Cbor::Text(key), Cbor::Uint(v)) if key == "version" => version = Some(v),
+            (Cbor::Text(key), Cbor::Array(items)) if key == "roots" => roots = Some(items),
+            _ => {}
+        }
+    }
+    if version != Some(1) {
+        return Err(SyncError::Decode(format!("Unsupported CAR version: {:?}", version)));
+    }
+    roots
+        .ok_or_else(|| SyncError::Decode("CAR header has no roots".to_string()))?
+        .into_iter()
+        .map(|root| match root {
+            Cbor::Tag(42, value) => match *value {
+                Cbor::Bytes(bytes) if bytes.first() == Some(&0x00) => Ok(_Cid::try_from(&bytes[1..])?),
+                _ => Err(SyncError::Decode("Malformed CID in CAR header".to_string())),
+            },
+            _ => Err(SyncError::Decode("Malformed CID in CAR header".to_string())),
+        })
+        .collect()
+}
+
+fn decode_cbor(bytes: &[u8], pos: &mut usize) -> Result<Cbor, SyncError> {
+    let initial = *bytes.get(*pos).ok_or_else(|| SyncError::Decode("Truncated CBOR".to_string()))?;
+    *pos += 1;
+    let major = initial >> 5;
+    let info = initial & 0x1f;
+    let arg = match info {
+        0..=23 => info as u64,
+        24..=27 => {
+            let arg_bytes = take(bytes, *pos, 1 << (info - 24), "CBOR")?;
+            *pos += arg_bytes.len();
+            arg_bytes.iter().fold(0u64, |acc, byte| acc << 8 | *byte as u64)
+        }
+        _ => return Err(SyncError::Decode("Unsupported CBOR item".to_string())),
+    };
+    let mut take = |len: u64| -> Result<Vec<u8>, SyncError> {
+        let slice = take(bytes, *pos, len, "CBOR")?;
+        *pos += slice.len();
+        Ok(slice.to_vec())
+    };
+    match major {
+        0 => Ok(Cbor::Uint(arg)),
+        2 => Ok(Cbor::Bytes(take(arg)?)),
+        3 => Ok(Cbor::Text(String::from_utf8(take(arg)?).map_err(|e| SyncError::Decode(e.to_string()))?)),
+        4 => (0..arg)
+            .map(|_| decode_cbor(bytes, pos))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Cbor::Array),
+        5 => (0..arg)
+            .map(|_| Ok((decode_cbor(bytes, pos)?, decode_cbor(bytes, pos)?)))
+            .collect::<Result<Vec<_>, SyncError>>()
+            .map(Cbor::Map),
+        6 => Ok(Cbor::Tag(arg, Box::new(decode_cbor(bytes, pos)?))),
+        _ => Err(SyncError::Decode("Unsupported CBOR item".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::unixfs::{read_file, UnixFsBuilder, UnixFsOptions};
+    use std::collections::HashMap;
+
+    fn build_car(data: &[u8]) -> Car {
+        let options = UnixFsOptions { chunk_size: 1024, ..Default::default() };
+        let mut blocks = Vec::new();
+        let mut on_block = |cid: _Cid, block: Vec<u8>| blocks.push((cid, block));
+        let root = UnixFsBuilder::new(data, options)
+            .with_blocks(&mut on_block)
+            .build()
+            .unwrap();
+        let mut car = Car::new(vec![root]);
+        for (cid, block) in blocks {
+            car.add(cid, block);
+        }
+        car
+    }
+
+    #[test]
+    /// Write a file's DAG to CARv1 and CARv2, read it back and reassemble the file
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let car = build_car(&data);
+        for bytes in [car.to_v1(), car.to_v2()] {
+            let decoded = Car::decode(&bytes).unwrap();
+            assert_eq!(decoded, car);
+            let blocks: HashMap<_Cid, Vec<u8>> = decoded.blocks.into_iter().collect();
+            assert_eq!(read_file(&decoded.roots[0], &blocks).unwrap(), data);
+        }
+    }
+
+    #[test]
+    /// A block that doesn't match its CID is rejected
+    fn test_corrupt_block() {
+        let mut car = build_car(b"hello");
+        car.blocks[0].1 = b"jello".to_vec();
+        assert!(matches!(Car::decode(&car.to_v1()), Err(SyncError::Integrity(_))));
+    }
+
+    #[test]
+    /// Lengths that run past the end, or overflow, are decode errors rather than panics
+    fn test_bad_lengths() {
+        let v2 = build_car(b"hello").to_v2();
+        let offset = CARV2_PRAGMA.len() + 16;
+        for (data_offset, data_size) in [(u64::MAX, 1), (1, u64::MAX), (u64::MAX, u64::MAX), (51, 1 << 40)] {
+            let mut bytes = v2.clone();
+            bytes[offset..offset + 8].copy_from_slice(&data_offset.to_le_bytes());
+            bytes[offset + 8..offset + 16].copy_from_slice(&data_size.to_le_bytes());
+            assert!(matches!(Car::decode(&bytes), Err(SyncError::Decode(_))), "{} {}", data_offset, data_size);
+        }
+
+        // A CARv1 header, and a block, claiming nearly 2^64 bytes
+        let mut huge = Vec::new();
+        encode_varint(&mut huge, u64::MAX - 1);
+        assert!(matches!(Car::decode(&huge), Err(SyncError::Decode(_))));
+        let v1 = build_car(b"hello").to_v1();
+        let mut pos = 0;
+        let header_len = decode_varint(&v1, &mut pos).unwrap() as usize;
+        let mut bytes = v1[..pos + header_len].to_vec();
+        bytes.extend_from_slice(&huge);
+        assert!(matches!(Car::decode(&bytes), Err(SyncError::Decode(_))));
+    }
+}
@@ -0,0 +1,341 @@
+/* Front Matter */
+
+use crate::error::{Result, SyncError};
+use crate::types::{cid::{Cid, CidMode}, metadata::Metadata};
+use chrono::{DateTime, Utc};
+use std::{fs::File, path::Path};
+
+/// The extensions we read front matter from
+const MARKDOWN_EXTENSIONS: [&str; 3] = ["md", "markdown", "mdx"];
+
+/// How a document's front matter is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Between `---` lines, as Jekyll and most static site generators write it
+    Yaml,
+    /// Between `+++` lines, as Hugo and Zola write it
+    Toml,
+}
+
+/// The front matter at the start of a document, and the body after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrontMatter<'a> {
+    pub format: Format,
+    /// The front matter, without its fences
+    pub raw: &'a str,
+    /// Everything after the closing fence
+    pub body: &'a str,
+}
+
+impl<'a> FrontMatter<'a> {
+    /// Split a document into its front matter and body
+    /// # Arguments
+    /// * `content` - The document
+    /// # Returns
+    /// * `Option<FrontMatter>` - None if the document doesn't open with a fence, or never closes it
+    pub fn split(content: &'a str) -> Option<Self> {
+        let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+        let mut lines = content.split_inclusive('\n');
+        let first = lines.next()?;
+        let (format, fences): (Format, &[&str]) = match first.trim_end() {
+            "---" => (Format::Yaml, &["---", "..."]),
+            "+++" => (Format::Toml, &["+++"]),
+            _ => return None,
+        };
+        let start = first.len();
+        let mut offset = start;
+        for line in lines {
+            if fences.contains(&line.trim_end()) {
+                return Some(Self {
+                    format,
+                    raw: &content[start..offset],
+                    body: &content[offset + line.len()..],
+                });
+            }
+            offset += line.len();
+        }
+        None
+    }
+
+    /// Read the front matter into Metadata. Strings are kept as they are, and anything else is
+    /// written as JSON, e.g. `tags: [a, b]` becomes `["a","b"]`
+    /// # Returns
+    /// * `Result<Metadata, SyncError>` - A decoding error if the front matter is malformed
+    pub fn metadata(&self) -> Result<Metadata, SyncError> {
+        let mut metadata = Metadata::new();
+        if self.raw.trim().is_empty() {
+            return Ok(metadata);
+        }
+        match self.format {
+            Format::Yaml => {
+                let mapping: serde_yaml::Mapping = serde_yaml::from_str(self.raw)
+                    .map_err(|e| SyncError::Decode(format!("Invalid YAML front matter: {}", e)))?;
+                for (key, value) in mapping {
+                    let key = match key {
+                        serde_yaml::Value::String(key) => key,
+                        other => yaml_string(&other)?,
+                    };
+                    if !value.is_null() {
+                        metadata.insert(key, yaml_string(&value)?);
+                    }
+                }
+            }
+            Format::Toml => {
+                let table: toml::Table = toml::from_str(self.raw)
+                    .map_err(|e| SyncError::Decode(format!("Invalid TOML front matter: {}", e)))?;
+                for (key, value) in table {
+                    let value = match value {
+                        toml::Value::String(value) => value,
+                        toml::Value::Datetime(value) => value.to_string(),
+                        toml::Value::Array(_) | toml::Value::Table(_) => serde_json::to_string(&value)?,
+                        other => other.to_string(),
+                    };
+                    metadata.insert(key, value);
+                }
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+/// Write a YAML value as a metadata string
+fn yaml_string(value: &serde_yaml::Value) -> Result<String, SyncError> {
+    Ok(match value {
+        serde_yaml::Value::String(value) => value.clone(),
+        serde_yaml::Value::Bool(value) => value.to_string(),
+        serde_yaml::Value::Number(value) => value.to_string(),
+        other => serde_json::to_string(other)?,
+    })
+}
+
+/// Whether we read front matter from a file
+/// # Arguments
+/// * `path` - The path to the file
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| MARKDOWN_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// The text of the first `# Heading` in a markdown body, outside of code blocks
+/// # Arguments
+/// * `body` - The markdown
+pub fn heading(body: &str) -> Option<String> {
+    let mut in_code = false;
+    for line in body.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        if let Some(title) = trimmed.strip_prefix("# ") {
+            let title = title.trim().trim_end_matches('#').trim_end();
+            if !title.is_empty() {
+                return Some(title.to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Drop the front matter from a document, if it has any
+/// # Arguments
+/// * `content` - The document
+/// # Returns
+/// * `&[u8]` - The body. Content that isn't UTF-8 is returned as it is
+pub fn strip(content: &[u8]) -> &[u8] {
+    match std::str::from_utf8(content).ok().and_then(FrontMatter::split) {
+        Some(front_matter) => front_matter.body.as_bytes(),
+        None => content,
+    }
+}
+
+/// Put front matter back on a document uploaded without it, written as YAML from its metadata.
+/// Values that hold a JSON list or map are written as YAML ones, so they read back the same
+/// # Arguments
+/// * `metadata` - The metadata on chain
+/// * `body` - The document, as it was uploaded
+/// # Returns
+/// * `Result<Vec<u8>, SyncError>` - The document with its front matter. Without metadata, the body
+pub fn restore(metadata: &Metadata, body: &[u8]) -> Result<Vec<u8>, SyncError> {
+    if metadata.is_empty() {
+        return Ok(body.to_vec());
+    }
+    let mut keys: Vec<&String> = metadata.keys().collect();
+    keys.sort();
+    let mut mapping = serde_yaml::Mapping::new();
+    for key in keys {
+        let value = &metadata[key];
+        let value = match serde_json::from_str::<serde_json::Value>(value) {
+            Ok(json) if json.is_array() || json.is_object() => serde_yaml::to_value(json)
+                .map_err(|e| SyncError::Decode(format!("Could not write front matter: {}", e)))?,
+            _ => serde_yaml::Value::String(value.clone()),
+        };
+        mapping.insert(serde_yaml::Value::String(key.clone()), value);
+    }
+    let yaml = serde_yaml::to_string(&mapping)
+        .map_err(|e| SyncError::Decode(format!("Could not write front matter: {}", e)))?;
+    let mut document = format!("---\n{}---\n", yaml).into_bytes();
+    document.extend_from_slice(body);
+    Ok(document)
+}
+
+/// Read a file as we upload it: without its front matter, if we strip it
+/// # Arguments
+/// * `path` - The path to the file
+/// * `strip_front_matter` - Whether to drop the front matter of markdown files
+/// # Returns
+/// * `Result<Vec<u8>, SyncError>` - The content to upload
+pub fn content(path: &Path, strip_front_matter: bool) -> Result<Vec<u8>, SyncError> {
+    let data = std::fs::read(path)?;
+    if strip_front_matter && is_markdown(path) {
+        return Ok(strip(&data).to_vec());
+    }
+    Ok(data)
+}
+
+/// The CID of a file as we upload it. Use this rather than hashing the file directly,
+/// or stripped files always look modified
+/// # Arguments
+/// * `path` - The path to the file
+/// * `strip_front_matter` - Whether to drop the front matter of markdown files
+/// * `mode` - How the CID is computed
+/// # Returns
+/// * `Result<Cid, SyncError>` - The CID of the content we upload
+pub fn cid(path: &Path, strip_front_matter: bool, mode: CidMode) -> Result<Cid, SyncError> {
+    if strip_front_matter && is_markdown(path) {
+        return Cid::from_reader(content(path, true)?.as_slice(), mode);
+    }
+    Cid::from_reader(File::open(path)?, mode)
+}
+
+/// Work out a file's metadata. For markdown, its front matter overrides `base`, and `overrides`
+/// override both. A missing `title` falls back on the first `# Heading`, and a missing `date` on
+/// the file's modification date. Other files get `base` and `overrides` alone
+/// # Arguments
+/// * `path` - The path to the file
+/// * `base` - The metadata to start from, e.g. what is already on chain
+/// * `overrides` - Metadata that wins over the front matter, e.g. from `--metadata`
+/// # Returns
+/// * `Result<Metadata, SyncError>` - A decoding error if the front matter is malformed
+pub fn metadata(path: &Path, base: Metadata, overrides: Metadata) -> Result<Metadata, SyncError> {
+    let mut metadata = base;
+    if !is_markdown(path) {
+        metadata.extend(overrides);
+        return Ok(metadata);
+    }
+    let data = std::fs::read(path)?;
+    let content = String::from_utf8_lossy(&data);
+    let front_matter = FrontMatter::split(&content);
+    let body = match &front_matter {
+        Some(front_matter) => {
+            let extracted = front_matter
+                .metadata()
+                .map_err(|e| SyncError::Decode(format!("{}: {}", path.display(), e)))?;
+            metadata.extend(extracted);
+            front_matter.body
+        }
+        None => &content,
+    };
+    metadata.extend(overrides);
+    if !metadata.contains_key("title") {
+        if let Some(title) = heading(body) {
+            metadata.insert("title".to_string(), title);
+        }
+    }
+    if !metadata.contains_key("date") {
+        let modified: DateTime<Utc> = std::fs::metadata(path)?.modified()?.into();
+        metadata.insert("date".to_string(), modified.format("%Y-%m-%d").to_string());
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = "---\ntitle: Hello\ntags: [rust, ipfs]\ndraft: false\nempty:\n---\n# Not the title\nBody\n";
+    const TOML: &str = "+++\ntitle = \"Hello\"\ndate = 2023-02-01\nviews = 3\n+++\nBody\n";
+
+    #[test]
+    /// Both kinds of front matter are read, and split from the body
+    fn test_split() {
+        let front_matter = FrontMatter::split(YAML).unwrap();
+        assert_eq!(front_matter.format, Format::Yaml);
+        assert_eq!(front_matter.body, "# Not the title\nBody\n");
+        let metadata = front_matter.metadata().unwrap();
+        assert_eq!(metadata["title"], "Hello");
+        assert_eq!(metadata["tags"], "[\"rust\",\"ipfs\"]");
+        assert_eq!(metadata["draft"], "false");
+        assert!(!metadata.contains_key("empty"));
+
+        let front_matter = FrontMatter::split(TOML).unwrap();
+        assert_eq!(front_matter.format, Format::Toml);
+        assert_eq!(front_matter.body, "Body\n");
+        let metadata = front_matter.metadata().unwrap();
+        assert_eq!(metadata["date"], "2023-02-01");
+        assert_eq!(metadata["views"], "3");
+
+        // A horizontal rule later on, or an unclosed fence, isn't front matter
+        assert_eq!(FrontMatter::split("# Title\n---\nBody\n"), None);
+        assert_eq!(FrontMatter::split("---\ntitle: Hello\n"), None);
+        assert!(FrontMatter::split("---\n: [\n---\n").unwrap().metadata().is_err());
+        assert_eq!(strip(YAML.as_bytes()), b"# Not the title\nBody\n");
+        assert_eq!(strip(b"Body\n"), b"Body\n");
+    }
+
+    #[test]
+    /// Restored front matter reads back as the metadata it was written from
+    fn test_restore() {
+        let metadata = FrontMatter::split(YAML).unwrap().metadata().unwrap();
+        let document = restore(&metadata, strip(YAML.as_bytes())).unwrap();
+        let document = String::from_utf8(document).unwrap();
+        let front_matter = FrontMatter::split(&document).unwrap();
+        assert_eq!(front_matter.metadata().unwrap(), metadata);
+        assert_eq!(front_matter.body, "# Not the title\nBody\n");
+        assert_eq!(restore(&Metadata::new(), b"Body\n").unwrap(), b"Body\n");
+    }
+
+    #[test]
+    /// Front matter wins over the base, overrides win over both, and fallbacks fill the gaps
+    fn test_metadata() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let post = dir.join("post.md");
+        std::fs::write(&post, YAML).unwrap();
+        let mut base = Metadata::new();
+        base.insert("title".to_string(), "Old".to_string());
+        base.insert("author".to_string(), "Alex".to_string());
+        let mut overrides = Metadata::new();
+        overrides.insert("draft".to_string(), "true".to_string());
+        let metadata = metadata(&post, base.clone(), overrides.clone()).unwrap();
+        assert_eq!(metadata["title"], "Hello");
+        assert_eq!(metadata["author"], "Alex");
+        assert_eq!(metadata["draft"], "true");
+        assert_eq!(metadata["date"].len(), "2023-02-01".len());
+
+        // Without front matter, the title comes from the first heading
+        let bare = dir.join("bare.md");
+        std::fs::write(&bare, "```\n# Code\n```\n# Bare Post #\nBody\n").unwrap();
+        let metadata = super::metadata(&bare, Metadata::new(), Metadata::new()).unwrap();
+        assert_eq!(metadata["title"], "Bare Post");
+
+        // Other files are left alone
+        let text = dir.join("notes.txt");
+        std::fs::write(&text, YAML).unwrap();
+        let metadata = super::metadata(&text, base.clone(), overrides).unwrap();
+        assert_eq!(metadata["title"], "Old");
+        assert!(!metadata.contains_key("date"));
+
+        // Stripping changes the CID of markdown only
+        assert_eq!(cid(&post, true, CidMode::default()).unwrap(), Cid::from(strip(YAML.as_bytes())));
+        assert_eq!(cid(&post, false, CidMode::default()).unwrap(), Cid::from(YAML.as_bytes()));
+        assert_eq!(cid(&text, true, CidMode::default()).unwrap(), Cid::from(YAML.as_bytes()));
+        let raw = Cid::from_reader(strip(YAML.as_bytes()), CidMode::Raw).unwrap();
+        assert_eq!(cid(&post, true, CidMode::Raw).unwrap(), raw);
+    }
+}
@@ -1,14 +1,31 @@
-use anyhow::{anyhow, Error, Result};
+use crate::error::{Result, SyncError};
+use crate::utils::path::normalize;
 use sha3::{Digest, Keccak256};
-use std::path::PathBuf;
+use std::path::Path;
 
-pub fn hash_path(path: &PathBuf) -> Result<[u8; 32], Error> {
+/// Hash a path into its key, as the CrudFs contract does. The path is normalized first,
+/// so `content/hello.md` and `./content/hello.md` share a key. Pass content paths, relative to
+/// the content root, rather than paths on disk
+/// # Arguments
+/// * `path` - The content path
+/// # Returns
+/// * `Result<[u8; 32], SyncError>` - A path error if the path isn't UTF-8, or climbs out of its root
+pub fn hash_path(path: &Path) -> Result<[u8; 32], SyncError> {
+    let path = normalize(path)?;
+    // Normalized paths are always UTF-8
+    Ok(hash_str(&path.to_string_lossy()))
+}
+
+/// Hash a path exactly as it is written, e.g. one read back from the contract
+/// # Arguments
+/// * `path` - The path
+/// # Returns
+/// * `[u8; 32]` - The key of the path
+pub fn hash_str(path: &str) -> [u8; 32] {
     let mut hasher = Keccak256::new();
-    // Read the path as a string and hash it
-    let path_string = path.to_str().unwrap().to_string();
-    hasher.update(path_string.as_bytes());
+    hasher.update(path.as_bytes());
     let result = hasher.finalize();
     let mut key = [0u8; 32];
     key.copy_from_slice(&result);
-    Ok(key)
+    key
 }
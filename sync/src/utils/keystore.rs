@@ -0,0 +1,141 @@
+/* Encrypted Keystores */
+
+use crate::config::var;
+use crate::error::{Result, SyncError};
+use ethers::{
+    signers::{LocalWallet, Signer},
+    types::Address,
+    utils::hex,
+};
+use serde_json::Value;
+use std::{
+    io::IsTerminal,
+    path::Path,
+};
+
+/// Where the passphrase is read from, if it is set. Otherwise we prompt for it
+pub const PASSPHRASE_VAR: &str = "KEYSTORE_PASSPHRASE";
+
+/// Create a keystore for a new, random key
+/// # Arguments
+/// * `path` - Where to write the keystore. Must not exist yet
+/// * `passphrase` - The passphrase to encrypt the key with
+/// # Returns
+/// * `Result<Address, SyncError>` - The address of the new key
+pub fn create(path: &Path, passphrase: &str) -> Result<Address, SyncError> {
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    write(path, &wallet, passphrase)
+}
+
+/// Create a keystore for an existing private key
+/// # Arguments
+/// * `path` - Where to write the keystore. Must not exist yet
+/// * `private_key` - The private key, in hex
+/// * `passphrase` - The passphrase to encrypt the key with
+/// # Returns
+/// * `Result<Address, SyncError>` - The address of the key
+pub fn import(path: &Path, private_key: &str, passphrase: &str) -> Result<Address, SyncError> {
+    let wallet = private_key
+        .trim()
+        .parse::<LocalWallet>()
+        .map_err(|e| SyncError::Signer(format!("Invalid private key: {}", e)))?;
+    write(path, &wallet, passphrase)
+}
+
+/// Encrypt a wallet's key into a Web3 Secret Storage keystore
+fn write(path: &Path, wallet: &LocalWallet, passphrase: &str) -> Result<Address, SyncError> {
+    if path.exists() {
+        return Err(SyncError::Config(format!("{} already exists", path.display())));
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| SyncError::Path(format!("{} is not a valid keystore path", path.display())))?;
+    std::fs::create_dir_all(dir)?;
+    eth_keystore::encrypt_key(dir, &mut rand::thread_rng(), wallet.signer().to_bytes(), passphrase, Some(name))
+        .map_err(|e| SyncError::Signer(format!("Could not encrypt the key: {}", e)))?;
+    // Record the address in the clear, like geth does, so it can be read without the passphrase
+    let mut keystore: Value = serde_json::from_slice(&std::fs::read(path)?)?;
+    keystore["address"] = Value::String(hex::encode(wallet.address()));
+    std::fs::write(path, serde_json::to_string(&keystore)?)?;
+    Ok(wallet.address())
+}
+
+/// Read the address of a keystore's key without decrypting it, if the keystore records it
+/// # Arguments
+/// * `path` - The keystore
+/// # Returns
+/// * `Result<Option<Address>, SyncError>` - The address, if it is recorded
+pub fn address(path: &Path) -> Result<Option<Address>, SyncError> {
+    let keystore: Value = serde_json::from_slice(&std::fs::read(path)?)?;
+    Ok(keystore["address"]
+        .as_str()
+        .and_then(|address| address.trim_start_matches("0x").parse().ok()))
+}
+
+/// Decrypt the wallet in a keystore
+/// # Arguments
+/// * `path` - The keystore
+/// * `passphrase` - The passphrase the key was encrypted with
+/// # Returns
+/// * `Result<LocalWallet, SyncError>` - A signer error if the passphrase is wrong
+pub fn decrypt(path: &Path, passphrase: &str) -> Result<LocalWallet, SyncError> {
+    if !path.exists() {
+        return Err(SyncError::Config(format!("Keystore {} does not exist", path.display())));
+    }
+    LocalWallet::decrypt_keystore(path, passphrase)
+        .map_err(|e| SyncError::Signer(format!("Could not decrypt {}: {}", path.display(), e)))
+}
+
+/// Get the passphrase for a keystore from `KEYSTORE_PASSPHRASE`, or else prompt for it.
+/// An empty `KEYSTORE_PASSPHRASE` counts as unset, as for every other setting
+/// # Arguments
+/// * `path` - The keystore, to name in the prompt
+/// * `confirm` - Ask twice, for a new passphrase
+/// # Returns
+/// * `Result<String, SyncError>` - A configuration error if there is no terminal to prompt on
+pub fn passphrase(path: &Path, confirm: bool) -> Result<String, SyncError> {
+    if let Some(passphrase) = var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(SyncError::Config(format!(
+            "{} is not set, and there is no terminal to ask for the passphrase on",
+            PASSPHRASE_VAR
+        )));
+    }
+    let mut prompt = dialoguer::Password::new();
+    prompt.with_prompt(format!("Passphrase for {}", path.display()));
+    if confirm {
+        prompt.with_confirmation("Repeat passphrase", "Passphrases don't match");
+    }
+    Ok(prompt.interact()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Keys survive a round trip through a keystore, and only open with the right passphrase
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.json");
+        let private_key = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+        let address = import(&path, private_key, "hunter2").unwrap();
+        assert_eq!(address, private_key.parse::<LocalWallet>().unwrap().address());
+        assert_eq!(super::address(&path).unwrap(), Some(address));
+        assert_eq!(decrypt(&path, "hunter2").unwrap().address(), address);
+        assert!(matches!(decrypt(&path, "hunter3"), Err(SyncError::Signer(_))));
+        // We never overwrite a keystore
+        assert!(create(&path, "hunter2").is_err());
+
+        let path = dir.path().join("new.json");
+        let address = create(&path, "hunter2").unwrap();
+        assert_eq!(decrypt(&path, "hunter2").unwrap().address(), address);
+    }
+}
@@ -1 +1,6 @@
+pub mod car;
+pub mod front_matter;
 pub mod hash;
+pub mod keystore;
+pub mod path;
+pub mod walk;
@@ -0,0 +1,148 @@
+/* Paths */
+
+use crate::config::Config;
+use crate::error::{Result, SyncError};
+use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+/// Put a path in canonical form: `/` separators, no `.` or `..` segments, and NFC Unicode.
+/// This is purely lexical, so symlinks aren't followed, and absolute paths stay absolute
+/// # Arguments
+/// * `path` - The path
+/// # Returns
+/// * `Result<PathBuf, SyncError>` - A path error if the path isn't UTF-8, or `..` climbs out of it
+pub fn normalize(path: &Path) -> Result<PathBuf, SyncError> {
+    let mut prefix = String::new();
+    let mut parts: Vec<String> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(drive) => prefix.push_str(utf8(path, drive.as_os_str())?),
+            Component::RootDir => prefix.push('/'),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if parts.pop().is_none() {
+                    return Err(SyncError::Path(format!("{} climbs out of its root with `..`", path.display())));
+                }
+            }
+            Component::Normal(part) => parts.push(utf8(path, part)?.nfc().collect()),
+        }
+    }
+    Ok(PathBuf::from(prefix + &parts.join("/")))
+}
+
+/// Read a path component as UTF-8
+fn utf8<'a>(path: &Path, part: &'a std::ffi::OsStr) -> Result<&'a str, SyncError> {
+    part.to_str()
+        .ok_or_else(|| SyncError::Path(format!("{} is not valid UTF-8", path.display())))
+}
+
+/// ContentRoot - The directory content paths are relative to. The path of a file on chain, and
+/// so its key, is its path from the content root in canonical form, however it was typed
+#[derive(Debug, Clone, Default)]
+pub struct ContentRoot {
+    root: Option<PathBuf>,
+}
+
+impl ContentRoot {
+    /// Create a ContentRoot
+    /// # Arguments
+    /// * `root` - The content root. Without one, paths are relative to the working directory
+    pub fn new(root: Option<PathBuf>) -> Self {
+        Self { root }
+    }
+
+    /// The configured `content_root`
+    /// # Arguments
+    /// * `config` - The resolved configuration
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.content_root.clone())
+    }
+
+    /// The content path of a local file, e.g. `hello.md` for `./content/hello.md`
+    /// or `/home/me/blog/content/hello.md` when the content root is `content`
+    /// # Arguments
+    /// * `path` - The path of the file on disk
+    /// # Returns
+    /// * `Result<PathBuf, SyncError>` - A path error if the file is outside the content root.
+    ///   Without a content root, paths outside the working directory are kept absolute
+    pub fn relative(&self, path: &Path) -> Result<PathBuf, SyncError> {
+        match &self.root {
+            None if !path.is_absolute() => normalize(path),
+            None => {
+                let cwd = normalize(&std::env::current_dir()?)?;
+                let path = normalize(path)?;
+                Ok(path.strip_prefix(&cwd).map(Path::to_path_buf).unwrap_or(path))
+            }
+            Some(root) => {
+                let root = normalize(&absolute(root)?)?;
+                let path = normalize(&absolute(path)?)?;
+                path.strip_prefix(&root).map(Path::to_path_buf).map_err(|_| {
+                    SyncError::Path(format!("{} is outside the content root {}", path.display(), root.display()))
+                })
+            }
+        }
+    }
+
+    /// Where a content path lives on disk
+    /// # Arguments
+    /// * `path` - The content path, e.g. from the manifest or the chain
+    pub fn local(&self, path: &Path) -> PathBuf {
+        match &self.root {
+            Some(root) => root.join(path),
+            None => path.to_path_buf(),
+        }
+    }
+}
+
+/// A path from the working directory, if it is relative
+pub fn absolute(path: &Path) -> Result<PathBuf, SyncError> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    Ok(std::env::current_dir()?.join(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// However a path is typed, it has one canonical form
+    fn test_normalize() {
+        let canonical = PathBuf::from("content/hello.md");
+        assert_eq!(normalize(Path::new("content/hello.md")).unwrap(), canonical);
+        assert_eq!(normalize(Path::new("./content//hello.md")).unwrap(), canonical);
+        assert_eq!(normalize(Path::new("content/drafts/../hello.md")).unwrap(), canonical);
+        assert_eq!(normalize(Path::new("/blog/./content/hello.md")).unwrap(), PathBuf::from("/blog/content/hello.md"));
+        // An "é" typed as "e" and a combining accent is the same file
+        assert_eq!(normalize(Path::new("cafe\u{301}.md")).unwrap(), PathBuf::from("caf\u{e9}.md"));
+        assert!(matches!(normalize(Path::new("../hello.md")), Err(SyncError::Path(_))));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let path = Path::new(std::ffi::OsStr::from_bytes(b"content/\xff.md"));
+            assert!(matches!(normalize(path), Err(SyncError::Path(_))));
+        }
+    }
+
+    #[test]
+    /// Paths are made relative to the content root, and back
+    fn test_content_root() {
+        let cwd = std::env::current_dir().unwrap();
+        let root = ContentRoot::new(Some(PathBuf::from("content")));
+        let canonical = PathBuf::from("posts/hello.md");
+        assert_eq!(root.relative(Path::new("content/posts/hello.md")).unwrap(), canonical);
+        assert_eq!(root.relative(Path::new("./content/posts/../posts/hello.md")).unwrap(), canonical);
+        assert_eq!(root.relative(&cwd.join("content/posts/hello.md")).unwrap(), canonical);
+        assert_eq!(root.relative(Path::new("content")).unwrap(), PathBuf::new());
+        assert!(matches!(root.relative(Path::new("other/hello.md")), Err(SyncError::Path(_))));
+        assert_eq!(root.local(&canonical), PathBuf::from("content/posts/hello.md"));
+
+        // Without a content root, paths are relative to the working directory
+        let root = ContentRoot::default();
+        assert_eq!(root.relative(&cwd.join("content/hello.md")).unwrap(), PathBuf::from("content/hello.md"));
+        assert_eq!(root.relative(Path::new("/elsewhere/hello.md")).unwrap(), PathBuf::from("/elsewhere/hello.md"));
+        assert_eq!(root.local(Path::new("content/hello.md")), PathBuf::from("content/hello.md"));
+    }
+}
@@ -0,0 +1,29 @@
+use crate::error::{Result, SyncError};
+use std::path::{Path, PathBuf};
+
+/// Recursively list every file under a directory
+/// Hidden files and directories (starting with `.`) are skipped
+/// # Arguments
+/// * `dir` - The directory to walk. Returned paths are prefixed with it
+/// # Returns
+/// * `Result<Vec<PathBuf>, SyncError>` - The files under the directory, sorted
+pub fn walk_dir(dir: &Path) -> Result<Vec<PathBuf>, SyncError> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
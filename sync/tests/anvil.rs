@@ -0,0 +1,302 @@
+//! Integration tests against a local anvil devnet
+//! These need `anvil` (from foundry) on the PATH and the compiled contract artifacts
+//! (`yarn build` in `contracts`), so they are ignored by default. Run them with
+//! `cargo test --test anvil -- --ignored`; they fail if either is missing.
+
+use ethers::{
+    abi::Abi,
+    contract::ContractFactory,
+    middleware::SignerMiddleware,
+    providers::{Http, Provider},
+    signers::{LocalWallet, Signer},
+    types::{Address, Bytes, U256},
+    utils::{Anvil, AnvilInstance},
+};
+use std::{path::PathBuf, process::Command, str::FromStr, sync::Arc};
+use sync::{
+    crud_fs::backend::{
+        eth::CrudFsContract, Backend, BackendClient, EthClient, EthSigner, ReadOnlyBackend, RemoteSigner,
+        SyncSigner,
+    },
+    error::SyncError,
+    history::{index, Change, History},
+    types::{cid::Cid, metadata::Metadata},
+    utils::hash::hash_path,
+};
+
+/// The hardhat artifact for the CrudFs contract. This path is relative to `sync`
+const ARTIFACT: &str = "../contracts/artifacts/contracts/crudFs.sol/CrudFs.json";
+/// What OpenZeppelin's Ownable reverts with
+const NOT_OWNER: &str = "Ownable: caller is not the owner";
+/// What the indexed views revert with past the last file
+const OUT_OF_BOUNDS: &str = "Index out of bounds.";
+
+/// A devnet with a freshly deployed CrudFs contract
+struct Devnet {
+    anvil: AnvilInstance,
+    contract_address: Address,
+}
+
+impl Devnet {
+    /// Start anvil and deploy the contract from the first account
+    /// Panics if anvil or the compiled artifact is not available
+    async fn start() -> Self {
+        if Command::new("anvil").arg("--version").output().is_err() {
+            panic!("anvil is not installed: install foundry, https://book.getfoundry.sh/getting-started/installation");
+        }
+        let artifact_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(ARTIFACT);
+        let contents = std::fs::read_to_string(&artifact_path)
+            .unwrap_or_else(|_| panic!("{} not found: run `yarn build` in contracts", ARTIFACT));
+        let artifact: serde_json::Value = serde_json::from_str(&contents).unwrap();
+        let abi: Abi = serde_json::from_value(artifact["abi"].clone()).unwrap();
+        let bytecode = Bytes::from_str(artifact["bytecode"].as_str().unwrap()).unwrap();
+        assert!(!bytecode.is_empty(), "{} has no bytecode: run `yarn build` in contracts", ARTIFACT);
+
+        let anvil = Anvil::new().spawn();
+        let signer = Self::signer(&anvil, 0);
+        let factory = ContractFactory::new(abi, bytecode, Arc::new(signer));
+        let contract = factory.deploy(()).unwrap().send().await.unwrap();
+        let contract_address = contract.address();
+        Self {
+            anvil,
+            contract_address,
+        }
+    }
+
+    /// A signer for one of anvil's dev accounts
+    fn signer(anvil: &AnvilInstance, account: usize) -> EthSigner {
+        let provider = Provider::<Http>::try_from(anvil.endpoint()).unwrap();
+        let wallet: LocalWallet = anvil.keys()[account].clone().into();
+        SignerMiddleware::new(provider, SyncSigner::from(wallet.with_chain_id(anvil.chain_id())))
+    }
+
+    /// A BackendClient signing with one of anvil's dev accounts
+    fn backend(&self, account: usize) -> BackendClient {
+        let eth_client = EthClient {
+            signer: Self::signer(&self.anvil, account),
+        };
+        BackendClient::from_eth_client(format!("{:?}", self.contract_address), eth_client).unwrap()
+    }
+
+    /// A ReadOnlyBackend, with no key at all
+    fn read_only(&self) -> ReadOnlyBackend {
+        ReadOnlyBackend::new(self.anvil.endpoint(), format!("{:?}", self.contract_address)).unwrap()
+    }
+
+    /// The raw contract bindings, for the views the Backend trait doesn't cover
+    fn contract(&self) -> CrudFsContract<EthSigner> {
+        CrudFsContract::new(self.contract_address, Arc::new(Self::signer(&self.anvil, 0)))
+    }
+}
+
+fn metadata(value: &str) -> Metadata {
+    let mut metadata = Metadata::new();
+    metadata.insert("test".to_string(), value.to_string());
+    metadata
+}
+
+/// Check that a call reverted with the given revert string
+fn assert_revert(err: SyncError, reason: &str) {
+    match err {
+        SyncError::Revert(actual) => assert_eq!(actual, reason),
+        other => panic!("Expected a revert with {:?}, got {:?}", reason, other),
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs anvil and the compiled contract"]
+/// Run the create/read/update/delete cycle through the BackendClient
+async fn test_crud() {
+    let devnet = Devnet::start().await;
+    let client = devnet.backend(0);
+    let path = PathBuf::from("content/hello.md");
+    let key = hash_path(&path).unwrap();
+
+    // C is for create
+    let cid = Cid::from(b"hello".as_slice());
+    let crud_file = client.create(path.clone(), cid.clone(), metadata("value")).await.unwrap();
+    assert_eq!(crud_file.key, key);
+    assert_eq!(crud_file.cid, cid);
+    assert!(crud_file.timestamp > 0);
+    // Creating the same path again reverts
+    let err = client.create(path.clone(), cid.clone(), metadata("value")).await.unwrap_err();
+    assert_revert(err, "File already exists.");
+
+    // R is for read
+    let read = client.read(key).await.unwrap();
+    assert_eq!(read.path, path);
+    assert_eq!(read.cid, cid);
+    assert_eq!(read.metadata, metadata("value"));
+    assert_eq!(read.timestamp, crud_file.timestamp);
+
+    // U is for update
+    let cid = Cid::from(b"hello again".as_slice());
+    let (updated_key, timestamp) = client.update(key, cid.clone(), metadata("value2")).await.unwrap();
+    assert_eq!(updated_key, key);
+    let read = client.read(key).await.unwrap();
+    assert_eq!(read.cid, cid);
+    assert_eq!(read.metadata, metadata("value2"));
+    assert_eq!(read.timestamp, timestamp);
+
+    // D is for delete
+    client.delete(key).await.unwrap();
+    assert_revert(client.read(key).await.unwrap_err(), "File does not exist.");
+    assert_revert(client.delete(key).await.unwrap_err(), "File does not exist.");
+    let err = client.update(key, cid, metadata("value3")).await.unwrap_err();
+    assert_revert(err, "File does not exist.");
+    assert!(client.list().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+#[ignore = "needs anvil and the compiled contract"]
+/// Check the bulk and indexed views against what we created
+async fn test_views() {
+    let devnet = Devnet::start().await;
+    let client = devnet.backend(0);
+    let contract = devnet.contract();
+
+    let paths: Vec<PathBuf> = ["a.md", "b.md", "c.md"].iter().map(PathBuf::from).collect();
+    for (i, path) in paths.iter().enumerate() {
+        let cid = Cid::from(format!("post {}", i).as_bytes());
+        client.create(path.clone(), cid, metadata(&i.to_string())).await.unwrap();
+    }
+    let keys: Vec<[u8; 32]> = paths.iter().map(|path| hash_path(path).unwrap()).collect();
+
+    // readFileCount / readAllFileKeys / readFileKeyAtIndex
+    assert_eq!(contract.read_file_count().call().await.unwrap(), U256::from(3));
+    assert_eq!(contract.read_all_file_keys().call().await.unwrap(), keys);
+    assert_eq!(contract.read_file_key_at_index(U256::from(1)).call().await.unwrap(), keys[1]);
+    let err = contract.read_file_key_at_index(U256::from(3)).call().await.unwrap_err();
+    assert_revert(err.into(), OUT_OF_BOUNDS);
+
+    // readFileAtIndex
+    let (path, cid, _, metadata_string) = contract.read_file_at_index(U256::from(2)).call().await.unwrap();
+    assert_eq!(path, "c.md");
+    assert_eq!(cid, Cid::from(b"post 2".as_slice()).to_string());
+    assert_eq!(metadata_string, serde_json::to_string(&metadata("2")).unwrap());
+    let err = contract.read_file_at_index(U256::from(3)).call().await.unwrap_err();
+    assert_revert(err.into(), OUT_OF_BOUNDS);
+
+    // readFiles
+    let (read_paths, _, _, _) = contract
+        .read_files(vec![keys[2], keys[0]])
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(read_paths, vec!["c.md".to_string(), "a.md".to_string()]);
+    let err = contract.read_files(vec![[0; 32]]).call().await.unwrap_err();
+    assert_revert(err.into(), "File does not exist.");
+
+    // readAllFiles, through the Backend
+    let listed: Vec<PathBuf> = client.list().await.unwrap().into_iter().map(|f| f.path).collect();
+    assert_eq!(listed, paths);
+
+    // Deleting moves the last key into the deleted slot
+    client.delete(keys[0]).await.unwrap();
+    assert_eq!(contract.read_all_file_keys().call().await.unwrap(), vec![keys[2], keys[1]]);
+}
+
+#[tokio::test]
+#[ignore = "needs anvil and the compiled contract"]
+/// Everything can be read without a key, but nothing can be written
+async fn test_read_only() {
+    let devnet = Devnet::start().await;
+    let client = devnet.backend(0);
+    let reader = devnet.read_only();
+    let paths: Vec<PathBuf> = ["a.md", "b.md"].iter().map(PathBuf::from).collect();
+    for path in &paths {
+        client.create(path.clone(), Cid::from(path.to_str().unwrap().as_bytes()), metadata("value")).await.unwrap();
+    }
+    let keys: Vec<[u8; 32]> = paths.iter().map(|path| hash_path(path).unwrap()).collect();
+
+    assert_eq!(reader.owner().await.unwrap(), devnet.anvil.addresses()[0]);
+    assert_eq!(reader.count().await.unwrap(), 2);
+    assert_eq!(reader.keys().await.unwrap(), keys);
+    assert_eq!(reader.key_at(1).await.unwrap(), keys[1]);
+    assert_eq!(reader.read_at(0).await.unwrap().path, paths[0]);
+    let many: Vec<PathBuf> = reader.read_many(vec![keys[1], keys[0]]).await.unwrap().into_iter().map(|f| f.path).collect();
+    assert_eq!(many, vec![paths[1].clone(), paths[0].clone()]);
+    assert_eq!(reader.read(keys[0]).await.unwrap().metadata, metadata("value"));
+    assert_eq!(reader.list().await.unwrap().len(), 2);
+    // Out of range views still revert
+    assert_revert(reader.key_at(2).await.unwrap_err(), OUT_OF_BOUNDS);
+
+    let err = reader.delete(keys[0]).await.unwrap_err();
+    assert!(matches!(err, SyncError::ReadOnly(_)), "{:?}", err);
+    assert_eq!(client.list().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+#[ignore = "needs anvil and the compiled contract"]
+/// The history of a file is rebuilt from its events, a page of blocks at a time
+async fn test_history() {
+    let devnet = Devnet::start().await;
+    let client = devnet.backend(0);
+    let reader = devnet.read_only();
+    let path = PathBuf::from("hello.md");
+    let key = hash_path(&path).unwrap();
+    let created = client.create(path.clone(), Cid::from(b"hello".as_slice()), metadata("value")).await.unwrap();
+    let (_, updated) = client.update(key, Cid::from(b"hello again".as_slice()), metadata("value2")).await.unwrap();
+    client.delete(key).await.unwrap();
+
+    // Each transaction is mined in its own block, so pages of one block take several requests
+    let mut history = History::in_memory(&format!("{:?}", devnet.contract_address)).unwrap();
+    assert_eq!(index(&reader, &mut history, 0, 1).await.unwrap(), 3);
+    let revisions = history.revisions(key).unwrap();
+    let changes: Vec<Change> = revisions.iter().map(|revision| revision.change).collect();
+    assert_eq!(changes, vec![Change::Create, Change::Update, Change::Delete]);
+    assert_eq!(revisions[0].timestamp, created.timestamp);
+    assert_eq!(revisions[0].parsed_metadata(), Some(metadata("value")));
+    assert_eq!(revisions[1].timestamp, updated);
+    assert_eq!(revisions[1].cid, Some(Cid::from(b"hello again".as_slice())));
+    assert_eq!(revisions[2].cid, None);
+    assert!(revisions[2].timestamp >= updated);
+    assert!(revisions.windows(2).all(|pair| pair[0].block_number < pair[1].block_number));
+
+    // Nothing is read twice
+    assert_eq!(index(&reader, &mut history, 0, 1).await.unwrap(), 0);
+}
+
+#[tokio::test]
+#[ignore = "needs anvil and the compiled contract"]
+/// Only the owner can write
+async fn test_owner_only() {
+    let devnet = Devnet::start().await;
+    let owner = devnet.backend(0);
+    let other = devnet.backend(1);
+    let path = PathBuf::from("content/hello.md");
+    let key = hash_path(&path).unwrap();
+    let cid = Cid::from(b"hello".as_slice());
+
+    // Anyone else's writes revert
+    let err = other.create(path.clone(), cid.clone(), Metadata::new()).await.unwrap_err();
+    assert_revert(err, NOT_OWNER);
+    assert!(owner.list().await.unwrap().is_empty());
+
+    owner.create(path.clone(), cid.clone(), Metadata::new()).await.unwrap();
+    let new_cid = Cid::from(b"hijacked".as_slice());
+    assert_revert(other.update(key, new_cid, Metadata::new()).await.unwrap_err(), NOT_OWNER);
+    assert_revert(other.delete(key).await.unwrap_err(), NOT_OWNER);
+
+    // But anyone can read, and nothing changed
+    let read = other.read(key).await.unwrap();
+    assert_eq!(read.cid, cid);
+}
+
+#[tokio::test]
+#[ignore = "needs anvil and the compiled contract"]
+/// Writes signed by a remote signer land like any others. anvil holds its dev accounts unlocked
+/// and answers `eth_signTransaction` for them
+async fn test_remote_signer() {
+    let devnet = Devnet::start().await;
+    let remote = RemoteSigner::new(&devnet.anvil.endpoint(), devnet.anvil.addresses()[0], devnet.anvil.chain_id())
+        .unwrap();
+    let eth_client = EthClient::with_signer(devnet.anvil.endpoint(), devnet.anvil.chain_id(), remote.into())
+        .unwrap();
+    let client = BackendClient::from_eth_client(format!("{:?}", devnet.contract_address), eth_client).unwrap();
+    let path = PathBuf::from("content/hello.md");
+    let cid = Cid::from(b"hello".as_slice());
+    client.create(path.clone(), cid.clone(), Metadata::new()).await.unwrap();
+    let read = client.read(hash_path(&path).unwrap()).await.unwrap();
+    assert_eq!(read.cid, cid);
+}